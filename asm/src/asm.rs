@@ -21,12 +21,29 @@ pub struct Program {
     pub code_section: Option<Section>,
     /// The statements in the `.static` section
     pub static_section: Option<Section>,
+    /// The label of the first instruction to execute
+    ///
+    /// If this is `None`, execution starts at the beginning of the `.code` section.
+    pub entry: Option<Ident>,
+    /// Labels that occur after the last statement in the program
+    ///
+    /// These refer to the address just past the end of the program.
+    pub end_labels: Vec<Ident>,
+    /// Labels declared with `.global`, which may be referred to by other object files
+    pub globals: Vec<Ident>,
+    /// Labels declared with `.extern`, which must be defined by another object file
+    pub externs: Vec<Ident>,
+    /// Every constant defined in the program or on the command line, sorted by the position of
+    /// their definition
+    ///
+    /// Any constants in the values have already been substituted.
+    pub consts: Vec<Const>,
 }
 
 impl Program {
     /// Iterates through all the statements in the program, in order
     pub fn iter_all_stmts(&self) -> impl Iterator<Item = &Stmt> {
-        let Program {code_section, static_section} = self;
+        let Program {code_section, static_section, entry: _, end_labels: _, globals: _, externs: _, consts: _} = self;
         code_section.as_ref().map(|section| section.stmts.iter())
             .into_iter()
             .chain(static_section.as_ref().map(|section| section.stmts.iter()))
@@ -115,7 +132,11 @@ impl StaticData {
 /// The `.b1`, `.b2`, `.b4`, or `.b8` static data directive
 #[derive(Debug, Clone, PartialEq)]
 pub struct StaticBytes {
-    pub value: StaticBytesValue,
+    /// Either 1, 2, 4, or 8
+    pub size: u8,
+    /// The values to store, one after the other, each of which may refer to labels that are only
+    /// resolved during layout (at least one)
+    pub values: Vec<Expr>,
     /// The span of the entire directive
     pub span: Span,
 }
@@ -123,39 +144,7 @@ pub struct StaticBytes {
 impl StaticBytes {
     /// Returns the size in bytes that this will have in the generated executable
     pub fn size_bytes(&self) -> u64 {
-        self.value.size_bytes()
-    }
-}
-
-/// Note that each value is in **little-endian** byte order.
-#[derive(Debug, Clone, PartialEq)]
-pub enum StaticBytesValue {
-    B1([u8; 1], Span),
-    B2([u8; 2], Span),
-    B4([u8; 4], Span),
-    B8([u8; 8], Span),
-}
-
-impl StaticBytesValue {
-    pub fn span(&self) -> Span {
-        use StaticBytesValue::*;
-        match *self {
-            B1(_, span) |
-            B2(_, span) |
-            B4(_, span) |
-            B8(_, span) => span,
-        }
-    }
-
-    /// Returns the size in bytes that this will have in the generated executable
-    pub fn size_bytes(&self) -> u64 {
-        use StaticBytesValue::*;
-        match self {
-            B1(_, _) => 1,
-            B2(_, _) => 2,
-            B4(_, _) => 4,
-            B8(_, _) => 8,
-        }
+        self.size as u64 * self.values.len() as u64
     }
 }
 
@@ -204,12 +193,25 @@ impl StaticByteStr {
     }
 }
 
+/// The kinds of arguments that an instruction may take
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArgKind {
+    /// A register, immediate, label, or expression (see `Source`)
+    Source,
+    /// A register (see `Destination`)
+    Destination,
+    /// A register with an optional offset, or an address (see `Location`)
+    Location,
+}
+
 /// Represents an argument for an instruction that may be used as a source operand
 #[derive(Debug, Clone, PartialEq)]
 pub enum Source {
     Register(Register),
     Immediate(Immediate),
     Label(Ident),
+    /// An expression that may refer to labels or to the location counter
+    Expr(Expr),
 }
 
 impl Source {
@@ -230,9 +232,30 @@ impl Source {
             ast::InstrArg::Immediate(imm) => Source::Immediate(imm),
             // After const expansion, the only names left are labels
             ast::InstrArg::Name(label) => Source::Label(label),
+            ast::InstrArg::Expr(expr) => Source::Expr(expr),
         }
     }
 
+    /// If this argument refers to labels or to the location counter, replaces it with a
+    /// placeholder immediate and records the expression that it replaced
+    ///
+    /// `imm_index` is the index that the next immediate will have in the layout of the instruction.
+    pub fn take_relocation(&mut self, imm_index: &mut usize, relocations: &mut Vec<(usize, Expr)>) {
+        let expr = match self {
+            Source::Register(_) => return,
+            Source::Immediate(_) => None,
+            Source::Label(label) => Some(label_expr(label.clone())),
+            Source::Expr(expr) if expr.depends_on_layout() => Some(expr.clone()),
+            Source::Expr(_) => None,
+        };
+
+        if let Some(expr) = expr {
+            *self = Source::Immediate(placeholder_immediate(expr.span));
+            relocations.push((*imm_index, expr));
+        }
+        *imm_index += 1;
+    }
+
     /// Returns a default value for this type in case of an error (for error recovery)
     pub fn error_default(span: Span) -> Self {
         Source::Register(Register {
@@ -273,6 +296,9 @@ impl Destination {
         }
     }
 
+    /// Destinations are always registers, so they never need to be relocated
+    pub fn take_relocation(&mut self, _imm_index: &mut usize, _relocations: &mut Vec<(usize, Expr)>) {}
+
     /// Returns a default value for this type in case of an error (for error recovery)
     pub fn error_default(span: Span) -> Self {
         Destination::Register(Register {
@@ -288,6 +314,8 @@ pub enum Location {
     Register(Register, Option<Offset>),
     Immediate(Immediate),
     Label(Ident),
+    /// An expression that may refer to labels or to the location counter
+    Expr(Expr),
 }
 
 impl Location {
@@ -305,7 +333,28 @@ impl Location {
             ast::InstrArg::Immediate(imm) => Location::Immediate(imm),
             // After const expansion, the only names left are labels
             ast::InstrArg::Name(label) => Location::Label(label),
+            ast::InstrArg::Expr(expr) => Location::Expr(expr),
+        }
+    }
+
+    /// If this argument refers to labels or to the location counter, replaces it with a
+    /// placeholder immediate and records the expression that it replaced
+    ///
+    /// `imm_index` is the index that the next immediate will have in the layout of the instruction.
+    pub fn take_relocation(&mut self, imm_index: &mut usize, relocations: &mut Vec<(usize, Expr)>) {
+        let expr = match self {
+            Location::Register(..) => return,
+            Location::Immediate(_) => None,
+            Location::Label(label) => Some(label_expr(label.clone())),
+            Location::Expr(expr) if expr.depends_on_layout() => Some(expr.clone()),
+            Location::Expr(_) => None,
+        };
+
+        if let Some(expr) = expr {
+            *self = Location::Immediate(placeholder_immediate(expr.span));
+            relocations.push((*imm_index, expr));
         }
+        *imm_index += 1;
     }
 
     /// Returns a default value for this type in case of an error (for error recovery)
@@ -317,6 +366,21 @@ impl Location {
     }
 }
 
+/// Converts a label argument into an expression that refers to that label
+fn label_expr(label: Ident) -> Expr {
+    Expr {
+        span: label.span,
+        kind: ast::ExprKind::Name(label),
+    }
+}
+
+/// An immediate that takes the place of a relocated argument until it is resolved during linking
+///
+/// Zero fits in every immediate size, so the instruction can still be laid out as usual.
+fn placeholder_immediate(span: Span) -> Immediate {
+    Immediate {value: 0, span}
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Size {
     pub value: u64,
@@ -430,3 +494,5 @@ pub type Immediate = ast::Immediate;
 pub type Integer = ast::Integer;
 pub type Bytes = ast::Bytes;
 pub type Ident = ast::Ident;
+pub type Expr = ast::Expr;
+pub type Const = ast::Const;
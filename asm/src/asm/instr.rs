@@ -4,6 +4,8 @@ use crate::diagnostics::Diagnostics;
 use crate::label_offsets::LabelOffsets;
 
 use super::{
+    ArgKind,
+    Expr,
     Source,
     Destination,
     Location,
@@ -68,10 +70,23 @@ macro_rules! instr {
                 8
             }
 
-            pub fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> InstrLayout {
+            /// Computes the layout of this instruction, given the address of the instruction
+            pub fn layout(self, diag: &Diagnostics, labels: &LabelOffsets, addr: u64) -> InstrLayout {
                 use $instr_enum::*;
                 match self {
-                    $($instr_variant(instr) => instr.layout(diag, labels)),*
+                    $($instr_variant(instr) => instr.layout(diag, labels, addr)),*
+                }
+            }
+
+            /// Replaces every argument that refers to labels or to the location counter with a
+            /// placeholder immediate
+            ///
+            /// Returns each replaced expression along with the index of the immediate that it was
+            /// replaced with in the layout of this instruction (see `Layout::set_immediate`).
+            pub fn take_relocations(&mut self) -> Vec<(usize, Expr)> {
+                use $instr_enum::*;
+                match self {
+                    $($instr_variant(instr) => instr.take_relocations()),*
                 }
             }
         }
@@ -94,6 +109,33 @@ macro_rules! instr {
                 (kind, opcode - instr_opcode)
             }
 
+            /// Every kind of instruction, in order of opcode
+            pub const ALL: &'static [Self] = &[$($instr_kind_enum::$instr_variant),*];
+
+            /// Returns true if the given name is the name of an instruction
+            pub fn is_name(name: &str) -> bool {
+                [$($instr_name),*].contains(&name)
+            }
+
+            /// Returns the kind of instruction with the given name, if any
+            pub fn from_name(name: &str) -> Option<Self> {
+                Self::ALL.iter().copied().find(|kind| kind.name() == name)
+            }
+
+            /// Returns the name and kind of each argument of this instruction, in order
+            pub fn args(self) -> &'static [(&'static str, ArgKind)] {
+                match self {
+                    $($instr_kind_enum::$instr_variant => &[$((stringify!($instr_field), ArgKind::$instr_value_ty)),*]),*
+                }
+            }
+
+            /// Returns the name of this instruction as it is written in assembly code
+            pub fn name(self) -> &'static str {
+                match self {
+                    $($instr_kind_enum::$instr_variant => $instr_name),*
+                }
+            }
+
             /// Returns the size in bytes that this will have in the generated executable
             pub fn size_bytes(&self) -> usize {
                 // All instructions are currently 8 bytes
@@ -148,12 +190,22 @@ macro_rules! instr {
                     }
                 }
 
-                pub fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> InstrLayout {
+                pub fn take_relocations(&mut self) -> Vec<(usize, Expr)> {
+                    #[allow(unused_mut)]
+                    let mut relocations = Vec::new();
+                    #[allow(unused_mut, unused_variables)]
+                    let mut imm_index = 0;
+                    $(self.$instr_field.take_relocation(&mut imm_index, &mut relocations);)*
+
+                    relocations
+                }
+
+                pub fn layout(self, diag: &Diagnostics, labels: &LabelOffsets, addr: u64) -> InstrLayout {
                     let Self {$($instr_field,)* span: _} = self;
 
                     InstrLayout {
                         base_opcode: Self::OPCODE,
-                        layout: ($($instr_field,)*).layout(diag, labels),
+                        layout: ($($instr_field,)*).layout(diag, labels, addr),
                     }
                 }
             }
@@ -282,5 +334,47 @@ instr! {
         Call(struct Call {loc: Location}),
         #[opcode = 624, name = "ret"]
         Ret(struct Ret {}),
+
+        // Opcodes must be sorted in ascending order, so these are grouped at the end even though
+        // they are bitwise instructions
+
+        #[opcode = 636, name = "shl"]
+        Shl(struct Shl {dest: Destination, source: Source}),
+        #[opcode = 648, name = "shr"]
+        Shr(struct Shr {dest: Destination, source: Source}),
+        #[opcode = 660, name = "sal"]
+        Sal(struct Sal {dest: Destination, source: Source}),
+        #[opcode = 672, name = "sar"]
+        Sar(struct Sar {dest: Destination, source: Source}),
+        #[opcode = 684, name = "rol"]
+        Rol(struct Rol {dest: Destination, source: Source}),
+        #[opcode = 696, name = "ror"]
+        Ror(struct Ror {dest: Destination, source: Source}),
+        #[opcode = 708, name = "rcl"]
+        Rcl(struct Rcl {dest: Destination, source: Source}),
+        #[opcode = 720, name = "rcr"]
+        Rcr(struct Rcr {dest: Destination, source: Source}),
+
+        #[opcode = 732, name = "syscall"]
+        Syscall(struct Syscall {}),
+
+        #[opcode = 744, name = "fadd"]
+        Fadd(struct Fadd {dest: Destination, source: Source}),
+        #[opcode = 756, name = "fsub"]
+        Fsub(struct Fsub {dest: Destination, source: Source}),
+        #[opcode = 768, name = "fmul"]
+        Fmul(struct Fmul {dest: Destination, source: Source}),
+        #[opcode = 780, name = "fdiv"]
+        Fdiv(struct Fdiv {dest: Destination, source: Source}),
+        #[opcode = 792, name = "fsqrt"]
+        Fsqrt(struct Fsqrt {dest: Destination, source: Source}),
+
+        #[opcode = 804, name = "fcmp"]
+        Fcmp(struct Fcmp {source1: Source, source2: Source}),
+
+        #[opcode = 816, name = "itof"]
+        Itof(struct Itof {dest: Destination, source: Source}),
+        #[opcode = 828, name = "ftoi"]
+        Ftoi(struct Ftoi {dest: Destination, source: Source}),
     }
 }
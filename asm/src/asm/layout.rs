@@ -53,6 +53,15 @@ macro_rules! layout {
                 }
             }
 
+            /// Replaces the value of the immediate with the given index, counting only the
+            /// immediates in this layout
+            pub fn set_immediate(&mut self, index: usize, imm: asm::Immediate, diag: &Diagnostics) {
+                use $layout_enum::*;
+                match self {
+                    $($layout_variant(layout) => layout.set_immediate(index, imm, diag),)*
+                }
+            }
+
             /// Decodes the given binary representation of an instruction using
             /// the provided opcode offset to determine which layout to use
             ///
@@ -101,6 +110,16 @@ macro_rules! layout {
                     out
                 }
 
+                /// Replaces the value of the immediate with the given index, counting only the
+                /// immediates in this layout
+                pub fn set_immediate(&mut self, index: usize, imm: asm::Immediate, diag: &Diagnostics) {
+                    let $layout_struct($($field_var),*) = self;
+                    let fields = vec![$($field_var as &mut dyn LayoutField),*];
+                    let field = fields.into_iter().filter(|field| field.is_immediate()).nth(index)
+                        .expect("bug: no immediate with the given index in this layout");
+                    field.set_immediate(imm, diag);
+                }
+
                 /// Decodes the 64-bit binary representation of this instruction
                 pub fn from_binary(instr: u64) -> Self {
                     // Start right after opcode
@@ -151,11 +170,13 @@ layout! {
 
 pub trait LayoutArguments {
     /// Computes the layout of the `arguments` section of an instruction
-    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> Layout;
+    ///
+    /// The `addr` is the address of the instruction, used as the value of `.` in expressions.
+    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets, addr: u64) -> Layout;
 }
 
 impl LayoutArguments for () {
-    fn layout(self, _diag: &Diagnostics, _labels: &LabelOffsets) -> Layout {
+    fn layout(self, _diag: &Diagnostics, _labels: &LabelOffsets, _addr: u64) -> Layout {
         // For zero arguments, layout is unspecified and can be anything
         // No one should rely on this exact representation
         // This was chosen because L1 has an opcode offset of 0 so the opcode is not changed
@@ -164,10 +185,10 @@ impl LayoutArguments for () {
 }
 
 impl LayoutArguments for (Destination, Source) {
-    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> Layout {
+    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets, addr: u64) -> Layout {
         let (dest, src) = self;
         let dest = Dest::new(dest, diag, labels);
-        let src = Src::new(src, diag, labels);
+        let src = Src::new(src, diag, labels, addr);
 
         match (dest, src) {
             (Dest::Register(dest_reg), Src::Register(src_reg)) => Layout::L1(L1(
@@ -183,10 +204,10 @@ impl LayoutArguments for (Destination, Source) {
 }
 
 impl LayoutArguments for (Source, Source) {
-    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> Layout {
+    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets, addr: u64) -> Layout {
         let (src1, src2) = self;
-        let src1 = Src::new(src1, diag, labels);
-        let src2 = Src::new(src2, diag, labels);
+        let src1 = Src::new(src1, diag, labels, addr);
+        let src2 = Src::new(src2, diag, labels, addr);
 
         match (src1, src2) {
             (Src::Register(src1_reg), Src::Register(src2_reg)) => Layout::L1(L1(
@@ -210,10 +231,10 @@ impl LayoutArguments for (Source, Source) {
 }
 
 impl LayoutArguments for (Destination, Location) {
-    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> Layout {
+    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets, addr: u64) -> Layout {
         let (dest, loc) = self;
         let dest = Dest::new(dest, diag, labels);
-        let loc = Loc::new(loc, diag, labels);
+        let loc = Loc::new(loc, diag, labels, addr);
 
         match (dest, loc) {
             (Dest::Register(dest_reg), Loc::Register(loc_reg, None)) => Layout::L1(L1(
@@ -234,10 +255,10 @@ impl LayoutArguments for (Destination, Location) {
 }
 
 impl LayoutArguments for (Location, Source) {
-    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> Layout {
+    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets, addr: u64) -> Layout {
         let (loc, src) = self;
-        let loc = Loc::new(loc, diag, labels);
-        let src = Src::new(src, diag, labels);
+        let loc = Loc::new(loc, diag, labels, addr);
+        let src = Src::new(src, diag, labels, addr);
 
         match (loc, src) {
             (Loc::Register(loc_reg, None), Src::Register(src_reg)) => Layout::L1(L1(
@@ -271,11 +292,11 @@ impl LayoutArguments for (Location, Source) {
 }
 
 impl LayoutArguments for (Destination, Destination, Source) {
-    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> Layout {
+    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets, addr: u64) -> Layout {
         let (dest1, dest2, src) = self;
         let dest1 = Dest::new(dest1, diag, labels);
         let dest2 = Dest::new(dest2, diag, labels);
-        let src = Src::new(src, diag, labels);
+        let src = Src::new(src, diag, labels, addr);
 
         match (dest1, dest2, src) {
             (Dest::Register(dest1_reg), Dest::Register(dest2_reg), Src::Register(src_reg)) => Layout::L7(L7(
@@ -293,9 +314,9 @@ impl LayoutArguments for (Destination, Destination, Source) {
 }
 
 impl LayoutArguments for (Source,) {
-    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> Layout {
+    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets, addr: u64) -> Layout {
         let (src,) = self;
-        let src = Src::new(src, diag, labels);
+        let src = Src::new(src, diag, labels, addr);
 
         match src {
             Src::Register(reg) => Layout::L9(L9(Reg::new(reg, diag))),
@@ -305,7 +326,7 @@ impl LayoutArguments for (Source,) {
 }
 
 impl LayoutArguments for (Destination,) {
-    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> Layout {
+    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets, _addr: u64) -> Layout {
         let (dest,) = self;
         let dest = Dest::new(dest, diag, labels);
 
@@ -316,9 +337,9 @@ impl LayoutArguments for (Destination,) {
 }
 
 impl LayoutArguments for (Location,) {
-    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> Layout {
+    fn layout(self, diag: &Diagnostics, labels: &LabelOffsets, addr: u64) -> Layout {
         let (loc,) = self;
-        let loc = Loc::new(loc, diag, labels);
+        let loc = Loc::new(loc, diag, labels, addr);
 
         match loc {
             Loc::Register(reg, None) => Layout::L9(L9(Reg::new(reg, diag))),
@@ -331,7 +352,7 @@ impl LayoutArguments for (Location,) {
     }
 }
 
-/// Like `asm::Source`, but with labels resolved to immediates
+/// Like `asm::Source`, but with labels and expressions resolved to immediates
 #[derive(Debug, Clone, PartialEq)]
 enum Src {
     Register(asm::Register),
@@ -339,11 +360,12 @@ enum Src {
 }
 
 impl Src {
-    pub fn new(source: asm::Source, diag: &Diagnostics, labels: &LabelOffsets) -> Self {
+    pub fn new(source: asm::Source, diag: &Diagnostics, labels: &LabelOffsets, addr: u64) -> Self {
         match source {
             asm::Source::Register(reg) => Src::Register(reg),
             asm::Source::Immediate(imm) => Src::Immediate(imm),
-            asm::Source::Label(label) => Src::Immediate(labels.lookup(&label, diag)),
+            asm::Source::Label(label) => Src::Immediate(labels.lookup(&label, addr, diag)),
+            asm::Source::Expr(expr) => Src::Immediate(labels.eval(&expr, addr, diag)),
         }
     }
 }
@@ -362,7 +384,7 @@ impl Dest {
     }
 }
 
-/// Like `Location`, but with labels resolved to immediates
+/// Like `Location`, but with labels and expressions resolved to immediates
 #[derive(Debug, Clone, PartialEq)]
 enum Loc {
     Register(asm::Register, Option<asm::Offset>),
@@ -370,11 +392,12 @@ enum Loc {
 }
 
 impl Loc {
-    pub fn new(source: asm::Location, diag: &Diagnostics, labels: &LabelOffsets) -> Self {
+    pub fn new(source: asm::Location, diag: &Diagnostics, labels: &LabelOffsets, addr: u64) -> Self {
         match source {
             asm::Location::Register(reg, offset) => Loc::Register(reg, offset),
             asm::Location::Immediate(imm) => Loc::Immediate(imm),
-            asm::Location::Label(label) => Loc::Immediate(labels.lookup(&label, diag)),
+            asm::Location::Label(label) => Loc::Immediate(labels.lookup(&label, addr, diag)),
+            asm::Location::Expr(expr) => Loc::Immediate(labels.eval(&expr, addr, diag)),
         }
     }
 }
@@ -400,6 +423,33 @@ pub trait BitPattern {
     fn read(value: u64, msb_offset: u8) -> Self;
 }
 
+/// A field of a layout, which may be an immediate that can be replaced after layout
+pub trait LayoutField {
+    /// Returns true if this field is an immediate
+    fn is_immediate(&self) -> bool {
+        false
+    }
+
+    /// Replaces the value of this field, which must be an immediate
+    fn set_immediate(&mut self, _imm: asm::Immediate, _diag: &Diagnostics) {
+        unreachable!("bug: only immediates can be replaced")
+    }
+}
+
+impl LayoutField for Reg {}
+
+impl LayoutField for Offset {}
+
+impl<S: ImmSize> LayoutField for Imm<S> {
+    fn is_immediate(&self) -> bool {
+        true
+    }
+
+    fn set_immediate(&mut self, imm: asm::Immediate, diag: &Diagnostics) {
+        *self = Imm::new(imm, diag);
+    }
+}
+
 /// The opcode of an instruction, encoded in 12-bits
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Opcode(u16);
@@ -525,12 +575,13 @@ impl<S: ImmSize> BitPattern for Imm<S> {
         let bits = Self::size_bits();
         let value = self.0;
 
-        // Get the bits of the value, preserving signedness
-        let value_bits = u128::from_le_bytes(value.to_le_bytes());
+        debug_assert!(value >= -2i128.pow(bits as u32 - 1) && value < 2i128.pow(bits as u32),
+            "bug: immediate value does not fit in {}-bits", bits);
 
-        // Truncate to max 64 bits (safe because no bits past that should be set)
-        debug_assert!(value_bits < 2u128.pow(bits as u32), "bug: immediate value does not fit in {}-bits", bits);
-        let value = value_bits as u64;
+        // Get the bits of the value, preserving signedness, then truncate to the size of the
+        // immediate so that the sign bits of negative values do not overwrite other fields
+        let mask = !0u64 >> (asm::REGISTERS - bits);
+        let value = (value as u64) & mask;
 
         // Shift the value to the position specified by msb_offset
         let value = value << (asm::REGISTERS - msb_offset - bits);
@@ -551,7 +602,7 @@ impl<S: ImmSize> BitPattern for Imm<S> {
 
         // Sign-extend the number: http://graphics.stanford.edu/~seander/bithacks.html#VariableSignExtend
         let mask = 1u64 << (bits - 1);
-        let value = (value ^ mask) - mask;
+        let value = (value ^ mask).wrapping_sub(mask);
         // Reinterpret the value as signed
         let value = i64::from_le_bytes(value.to_le_bytes());
 
@@ -720,4 +771,35 @@ mod tests {
         let expected = 0b_00000010_0100__1111_01__111100_10110000_00__111111_00111111_01111010_11001001_u64;
         assert_eq!(layout.to_binary(base_opcode), expected);
     }
+
+    #[test]
+    fn replace_immediate() {
+        use std::sync::Arc;
+        use parking_lot::RwLock;
+        use crate::parser::{Span, SourceFiles};
+
+        let diag = Diagnostics::new(Arc::new(RwLock::new(SourceFiles::default())), termcolor::ColorChoice::Never);
+        let imm = |value| asm::Immediate {value, span: Span {start: 0, end: 0}};
+
+        let mut layout = Layout::L6(L6(Imm(0, PhantomData), Imm(0, PhantomData)));
+        layout.set_immediate(1, imm(24), &diag);
+        assert_eq!(layout, Layout::L6(L6(Imm(0, PhantomData), Imm(24, PhantomData))));
+        layout.set_immediate(0, imm(-3), &diag);
+        assert_eq!(layout, Layout::L6(L6(Imm(-3, PhantomData), Imm(24, PhantomData))));
+
+        // Registers and offsets are skipped when counting immediates
+        let mut layout = Layout::L5(L5(Reg(1), Offset(-8), Imm(0, PhantomData)));
+        layout.set_immediate(0, imm(0x1000), &diag);
+        assert_eq!(layout, Layout::L5(L5(Reg(1), Offset(-8), Imm(0x1000, PhantomData))));
+        assert_eq!(diag.emitted_errors(), 0);
+    }
+
+    #[test]
+    fn negative_immediate() {
+        let layout = Layout::L2(L2(Reg(5), Imm(-8, PhantomData)));
+        let instr = layout.to_binary(0);
+        // The sign bits must not overwrite the opcode or the register
+        assert_eq!(instr >> 52, 1);
+        assert_eq!(Layout::from_binary(instr, 1), Some(layout));
+    }
 }
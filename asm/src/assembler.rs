@@ -0,0 +1,236 @@
+//! Assembling a program from start to finish without going through the `wolf-asm` binary, and
+//! the stages of assembling that are shared with the binary and the language server
+
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+
+use parking_lot::RwLock;
+
+use crate::ast;
+use crate::parser::{self, SourceFiles, FileHandle};
+use crate::diagnostics::{Diagnostics, Diagnostic};
+use crate::file_provider::{FileProvider, FsFileProvider};
+use crate::include_expansion::expand_includes;
+use crate::conditional_assembly::{Conditionals, expand_conditionals};
+use crate::macro_expansion::expand_macros;
+use crate::const_table::parse_define_arg;
+use crate::validate::validate_program;
+use crate::label_offsets::LabelOffsets;
+use crate::executable::{Executable, DebugInfo};
+
+/// The maximum number of times we are allowed to recurse when expanding `.include` directives
+pub const MAX_INCLUDE_DEPTH: usize = 50;
+/// The maximum number of times we are allowed to recurse when expanding macros
+pub const MAX_MACRO_DEPTH: usize = 50;
+
+/// Configures what `assemble` assembles and how
+pub struct AssembleOptions {
+    /// The path of the program to assemble, read through `files`
+    pub path: PathBuf,
+    /// Provides the contents of the program and any files it includes
+    pub files: Box<dyn FileProvider>,
+    /// The source code of every file that was assembled
+    ///
+    /// The spans of any diagnostics refer to these files, so keep a clone of this to find the
+    /// path and line of each diagnostic.
+    pub source_files: Arc<RwLock<SourceFiles>>,
+    /// Constants defined before assembling, in the same format as `-D` (`name` or `name=value`)
+    pub defines: Vec<String>,
+    /// The address where the program will be loaded into memory
+    pub load_addr: u64,
+    /// Include labels and source locations in the executable for better runtime errors
+    pub debug_info: bool,
+}
+
+impl AssembleOptions {
+    /// Creates options that assemble the file at the given path, reading it and any files it
+    /// includes from the file system
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            files: Box::new(FsFileProvider),
+            source_files: Default::default(),
+            defines: Vec::new(),
+            load_addr: 0,
+            debug_info: false,
+        }
+    }
+}
+
+/// A successfully assembled program
+#[derive(Debug)]
+pub struct Assembled {
+    pub executable: Executable,
+    /// Every warning produced while assembling, in the order they were produced
+    pub warnings: Vec<Diagnostic<'static>>,
+}
+
+/// Assembles a program into an executable, the same way as the `wolf-asm` binary
+///
+/// If any errors occur, returns every diagnostic that was produced (including warnings) in the
+/// order they were produced. Assembling stops at the first stage that produces errors.
+pub fn assemble(options: AssembleOptions) -> Result<Assembled, Vec<Diagnostic<'static>>> {
+    let AssembleOptions {path, files, source_files, defines, load_addr, debug_info} = options;
+    let diag = Diagnostics::collecting(source_files.clone());
+
+    macro_rules! check_errors {
+        () => {
+            if diag.emitted_errors() > 0 {
+                return Err(diag.take_collected());
+            }
+        };
+    }
+
+    let defines: Vec<_> = defines.iter()
+        .filter_map(|define| parse_define_arg(define, &source_files, &diag))
+        .collect();
+    check_errors!();
+
+    let root_source = match files.read(&path) {
+        Ok(source) => source,
+        Err(err) => {
+            diag.error(format!("Could not read source file `{}`: {}", path.display(), err)).emit();
+            return Err(diag.take_collected());
+        },
+    };
+    let root_file = source_files.write().add_source(&path, &root_source);
+    let expanded_program = expand_program(&path, root_file, &source_files, &*files, &defines, &diag);
+    check_errors!();
+
+    let validated_program = validate_program(expanded_program, &defines, &diag);
+    check_errors!();
+
+    for label in &validated_program.externs {
+        diag.span_error(label.span, "`.extern` labels can only be used in object files (`--format object`) that are linked with `wolf-ld`").emit();
+    }
+    check_errors!();
+
+    // Must be generated before layout since that discards all of the labels and spans
+    let debug_info = if debug_info {
+        Some(DebugInfo::new(&validated_program, &source_files.read()))
+    } else {
+        None
+    };
+
+    let label_offsets = LabelOffsets::with_base_addr(&validated_program, load_addr);
+    let exec = Executable::layout_executable(validated_program, &diag, &label_offsets);
+    check_errors!();
+
+    Ok(Assembled {
+        executable: Executable {debug_info, ..exec},
+        warnings: diag.take_collected(),
+    })
+}
+
+/// Parses the program in the given root file and expands every `.include` directive,
+/// conditional directive, and macro in it
+///
+/// Stops after the first stage that produces errors and returns the statements produced by that
+/// stage (none if the root file could not be tokenized), so check `diag.emitted_errors()` before
+/// validating the returned program.
+pub fn expand_program(
+    path: &Path,
+    root_file: FileHandle,
+    source_files: &Arc<RwLock<SourceFiles>>,
+    files: &dyn FileProvider,
+    defines: &[ast::Const],
+    diag: &Diagnostics,
+) -> ast::Program {
+    let tokens = {
+        // New scope because we want to drop this lock guard as soon as possible
+        let sources = source_files.read();
+        parser::collect_tokens(sources.source(root_file), diag)
+    };
+    if diag.emitted_errors() > 0 {
+        return ast::Program {stmts: Vec::new()};
+    }
+
+    let program = parser::parse_program(&tokens, diag);
+    if diag.emitted_errors() > 0 {
+        return program;
+    }
+
+    let program = expand_includes(path, program, source_files, files, diag, MAX_INCLUDE_DEPTH);
+    if diag.emitted_errors() > 0 {
+        return program;
+    }
+
+    let mut conditionals = Conditionals::new(defines);
+    let program = expand_conditionals(program, &mut conditionals, diag);
+    if diag.emitted_errors() > 0 {
+        return program;
+    }
+
+    expand_macros(program, &mut conditionals, source_files, diag, MAX_MACRO_DEPTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::file_provider::MemoryFileProvider;
+    use crate::diagnostics::Level;
+
+    fn options(files: MemoryFileProvider) -> AssembleOptions {
+        AssembleOptions {files: Box::new(files), ..AssembleOptions::new("src/main.wa")}
+    }
+
+    #[test]
+    fn in_memory_includes() {
+        let mut files = MemoryFileProvider::new();
+        files.insert("src/main.wa", "section .code\n.include \"lib/exit.wa\"\nmain:\n    jmp exit\n");
+        files.insert("src/lib/exit.wa", "exit:\n    .ifdef CODE\n    mov $1, CODE\n    .endif\n    ret\n");
+
+        let exec = assemble(options(files.clone())).unwrap().executable;
+        assert!(exec.debug_info.is_none());
+
+        let exec_with_define = assemble(AssembleOptions {
+            defines: vec!["CODE=3".to_string()],
+            debug_info: true,
+            ..options(files)
+        }).unwrap().executable;
+        assert!(exec_with_define.debug_info.is_some());
+        assert_eq!(exec_with_define.code_section.len(), exec.code_section.len() + 1);
+    }
+
+    #[test]
+    fn returns_diagnostics() {
+        let mut files = MemoryFileProvider::new();
+        files.insert("src/main.wa", "section .code\n.include \"missing.wa\"\nmain:\n    bogus $1\n");
+
+        let source_files = Arc::new(RwLock::new(SourceFiles::default()));
+        let diagnostics = assemble(AssembleOptions {
+            source_files: source_files.clone(),
+            ..options(files)
+        }).unwrap_err();
+
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.title.level, Level::Error);
+        assert!(diagnostic.title.label.starts_with("unable to read included source file: `src/missing.wa`"));
+
+        let source_files = source_files.read();
+        let pos = source_files.pos(diagnostic.fragments[0].span);
+        assert_eq!(pos.path, std::path::Path::new("src/main.wa"));
+        assert_eq!(pos.start_line, 2);
+    }
+
+    #[test]
+    fn returns_warnings() {
+        let mut files = MemoryFileProvider::new();
+        files.insert("src/main.wa", "section .code\n.const SIZE 1\n.const SIZE 2\nmain:\n    ret\n");
+
+        let Assembled {executable, warnings} = assemble(options(files)).unwrap();
+        assert!(!executable.code_section.is_empty());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].title.level, Level::Warning);
+        assert_eq!(warnings[0].title.label, "constant named `size` was redefined");
+    }
+
+    #[test]
+    fn missing_root_file() {
+        let diagnostics = assemble(options(MemoryFileProvider::new())).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].fragments.is_empty());
+    }
+}
@@ -7,6 +7,8 @@ use std::sync::Arc;
 use std::borrow::Borrow;
 use std::hash::{Hash, Hasher};
 
+use serde::{Serialize, Deserialize};
+
 use crate::parser::Span;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -22,6 +24,22 @@ pub enum Stmt {
 
     Include(Include),
     Const(Const),
+    Entry(Entry),
+    Global(Global),
+    Extern(Extern),
+
+    Macro(Macro),
+    /// The `.endm` directive
+    EndMacro(Span),
+    Rept(Rept),
+    /// The `.endr` directive
+    EndRept(Span),
+
+    If(If),
+    /// The `.else` directive
+    Else(Span),
+    /// The `.endif` directive
+    EndIf(Span),
 
     StaticData(StaticData),
 
@@ -84,11 +102,82 @@ pub struct Include {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Const {
     pub name: Ident,
-    pub value: Immediate,
+    pub value: Expr,
+    /// The span of the entire directive
+    pub span: Span,
+}
+
+/// An `.entry` directive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The label of the first instruction to execute
+    pub label: Ident,
+    /// The span of the entire directive
+    pub span: Span,
+}
+
+/// A `.global` directive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    /// The label to make visible to other object files
+    pub label: Ident,
+    /// The span of the entire directive
+    pub span: Span,
+}
+
+/// An `.extern` directive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extern {
+    /// The label defined (with `.global`) in another object file
+    pub label: Ident,
     /// The span of the entire directive
     pub span: Span,
 }
 
+/// A `.macro` directive, which starts a macro definition that ends with `.endm`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub name: Ident,
+    pub params: Vec<Ident>,
+    /// The span of the entire directive
+    pub span: Span,
+}
+
+/// A `.rept` directive, which starts a block of statements that ends with `.endr`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rept {
+    /// The number of times to repeat the block
+    pub count: Integer,
+    /// The span of the entire directive
+    pub span: Span,
+}
+
+/// An `.if` or `.ifdef` directive, which starts a block of statements that ends with `.endif`
+#[derive(Debug, Clone, PartialEq)]
+pub struct If {
+    pub cond: Condition,
+    /// The span of the entire directive
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// `.if expr`, true if the expression is not zero
+    Expr(Expr),
+    /// `.ifdef NAME`, true if a constant with the given name has been defined
+    Defined(Ident),
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Condition::*;
+        match self {
+            Expr(_) => write!(f, ".if"),
+            Defined(_) => write!(f, ".ifdef"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum StaticData {
     StaticBytes(StaticBytes),
@@ -102,7 +191,8 @@ pub enum StaticData {
 pub struct StaticBytes {
     /// Either 1, 2, 4, or 8
     pub size: u8,
-    pub value: Immediate,
+    /// The comma-separated values, each of which is stored in `size` bytes (at least one)
+    pub values: Vec<Expr>,
     /// The span of the entire directive
     pub span: Span,
 }
@@ -155,6 +245,8 @@ pub enum InstrArg {
     Register(Register),
     Immediate(Immediate),
     Name(Ident),
+    /// Any expression that is not just a single immediate or name
+    Expr(Expr),
 }
 
 impl fmt::Display for InstrArg {
@@ -164,6 +256,18 @@ impl fmt::Display for InstrArg {
             Register(reg) => write!(f, "{}", reg),
             Immediate(imm) => write!(f, "{}", imm),
             Name(name) => write!(f, "{}", name),
+            Expr(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl From<Expr> for InstrArg {
+    fn from(expr: Expr) -> Self {
+        match expr.kind {
+            ExprKind::Integer(value) => InstrArg::Immediate(Integer {value, span: expr.span}),
+            ExprKind::Float(value) => InstrArg::Immediate(Integer {value: value.to_bits() as i128, span: expr.span}),
+            ExprKind::Name(name) => InstrArg::Name(name),
+            _ => InstrArg::Expr(expr),
         }
     }
 }
@@ -175,6 +279,7 @@ impl InstrArg {
             Register(reg) => reg.span,
             Immediate(imm) => imm.span,
             Name(name) => name.span,
+            Expr(expr) => expr.span,
         }
     }
 
@@ -184,6 +289,149 @@ impl InstrArg {
             _ => false,
         }
     }
+
+    /// Returns true if this argument is a name or an expression that may contain names
+    pub fn has_names(&self) -> bool {
+        matches!(self, InstrArg::Name(_) | InstrArg::Expr(_))
+    }
+}
+
+/// A constant expression, e.g. `end - start`, `(SIZE + 1) * 8`, `. + 16`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ExprKind::*;
+        match &self.kind {
+            Integer(value) => write!(f, "{}", value),
+            Float(value) => write!(f, "{:?}", value),
+            Name(name) => write!(f, "{}", name),
+            Here => write!(f, "."),
+            Neg(expr) => {
+                write!(f, "-")?;
+                expr.fmt_operand(f)
+            },
+            BinOp(op, lhs, rhs) => {
+                lhs.fmt_operand(f)?;
+                write!(f, " {} ", op)?;
+                rhs.fmt_operand(f)
+            },
+        }
+    }
+}
+
+impl Expr {
+    /// Formats this expression with parentheses if it is made up of more than one operand
+    fn fmt_operand(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ExprKind::BinOp(..) => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+
+    /// Replaces every name in this expression with the expression returned by `f`
+    pub fn map_names(self, f: &mut impl FnMut(Ident) -> Expr) -> Self {
+        let Self {kind, span} = self;
+        let kind = match kind {
+            ExprKind::Name(name) => return f(name),
+            ExprKind::Neg(expr) => ExprKind::Neg(Box::new(expr.map_names(f))),
+            ExprKind::BinOp(op, lhs, rhs) => {
+                let lhs = lhs.map_names(f);
+                let rhs = rhs.map_names(f);
+                ExprKind::BinOp(op, Box::new(lhs), Box::new(rhs))
+            },
+            kind => kind,
+        };
+
+        Self {kind, span}
+    }
+
+    /// Returns true if this expression refers to a name or to the location counter (`.`)
+    ///
+    /// After constants have been substituted, such an expression can only be evaluated once the
+    /// address of every label is known.
+    pub fn depends_on_layout(&self) -> bool {
+        match &self.kind {
+            ExprKind::Name(_) |
+            ExprKind::Here => true,
+            ExprKind::Neg(expr) => expr.depends_on_layout(),
+            ExprKind::BinOp(_, lhs, rhs) => lhs.depends_on_layout() || rhs.depends_on_layout(),
+            ExprKind::Integer(_) |
+            ExprKind::Float(_) => false,
+        }
+    }
+
+    /// Calls `f` with every name in this expression, in order
+    pub fn for_each_name<'a>(&'a self, f: &mut impl FnMut(&'a Ident)) {
+        match &self.kind {
+            ExprKind::Name(name) => f(name),
+            ExprKind::Neg(expr) => expr.for_each_name(f),
+            ExprKind::BinOp(_, lhs, rhs) => {
+                lhs.for_each_name(f);
+                rhs.for_each_name(f);
+            },
+            ExprKind::Integer(_) |
+            ExprKind::Float(_) |
+            ExprKind::Here => {},
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ExprKind {
+    /// An integer literal
+    Integer(i128),
+    /// A floating point literal
+    ///
+    /// Can only be used on its own or negated, not as part of a larger expression
+    Float(f64),
+    /// The name of a constant or label
+    Name(Ident),
+    /// The location counter `.`, which is the address of the current statement
+    Here,
+    /// Unary negation, e.g. `-x`
+    Neg(Box<Expr>),
+    BinOp(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BinOp {
+    /// `+`
+    Add,
+    /// `-`
+    Sub,
+    /// `*`
+    Mul,
+    /// `/`
+    Div,
+    /// `<<`
+    Shl,
+    /// `>>`
+    Shr,
+    /// `&`
+    And,
+    /// `|`
+    Or,
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use BinOp::*;
+        match self {
+            Add => write!(f, "+"),
+            Sub => write!(f, "-"),
+            Mul => write!(f, "*"),
+            Div => write!(f, "/"),
+            Shl => write!(f, "<<"),
+            Shr => write!(f, ">>"),
+            And => write!(f, "&"),
+            Or => write!(f, "|"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -220,6 +468,8 @@ impl fmt::Display for RegisterKind {
 }
 
 /// An immediate value
+///
+/// Floating point literals are stored as an integer containing their IEEE 754 binary64 bit pattern
 pub type Immediate = Integer;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -248,7 +498,7 @@ impl fmt::Display for Bytes {
     }
 }
 
-#[derive(Debug, Clone, Eq)]
+#[derive(Debug, Clone, Eq, Serialize, Deserialize)]
 pub struct Ident {
     pub value: Arc<str>,
     pub span: Span,
@@ -5,60 +5,80 @@
 #![deny(unused_must_use)]
 
 use std::env;
-use std::process;
 use std::sync::Arc;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::fs::File;
+use std::io::{Write, BufWriter};
+use std::mem;
 
 use parking_lot::RwLock;
-use termcolor::ColorChoice;
 use structopt::StructOpt;
 
 use wolf_asm::{
-    diagnostics::Diagnostics,
-    parser::{self, SourceFiles},
-    include_expansion::expand_includes,
+    diagnostics::{Diagnostics, ErrorFormat},
+    parser::SourceFiles,
+    file_provider::FsFileProvider,
+    assembler::expand_program,
+    const_table::parse_define_arg,
     validate::validate_program,
     label_offsets::LabelOffsets,
-    executable::Executable,
+    executable::{Executable, DebugInfo},
+    object::Object,
+    listing::Listing,
+    cli::{ColorArg, parse_addr},
+    quit,
+    check_errors,
 };
 
-/// The maximum number of times we are allowed to recurse when expanding `.include` directives
-const MAX_INCLUDE_DEPTH: usize = 50;
-
-/// A command line argument that configures the coloring of the output
+/// A command line argument that configures the format of errors and warnings
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub struct ColorArg(pub ColorChoice);
-
-impl Default for ColorArg {
-    fn default() -> Self {
-        ColorArg(ColorChoice::Auto)
-    }
-}
+pub struct ErrorFormatArg(pub ErrorFormat);
 
-impl ColorArg {
+impl ErrorFormatArg {
     /// Allowed values the argument
-    pub const VARIANTS: &'static [&'static str] = &["auto", "always", "ansi", "never"];
+    pub const VARIANTS: &'static [&'static str] = &["human", "json"];
 }
 
-impl FromStr for ColorArg {
+impl FromStr for ErrorFormatArg {
     type Err = &'static str;
 
-    fn from_str(src: &str) -> Result<ColorArg, &'static str> {
+    fn from_str(src: &str) -> Result<ErrorFormatArg, &'static str> {
         match src {
-            _ if src.eq_ignore_ascii_case("auto") => Ok(ColorArg(ColorChoice::Auto)),
-            _ if src.eq_ignore_ascii_case("always") => Ok(ColorArg(ColorChoice::Always)),
-            _ if src.eq_ignore_ascii_case("ansi") => Ok(ColorArg(ColorChoice::AlwaysAnsi)),
-            _ if src.eq_ignore_ascii_case("never") => Ok(ColorArg(ColorChoice::Never)),
-            _ => Err("valid values: auto, always, ansi, never"),
+            _ if src.eq_ignore_ascii_case("human") => Ok(ErrorFormatArg(ErrorFormat::Human)),
+            _ if src.eq_ignore_ascii_case("json") => Ok(ErrorFormatArg(ErrorFormat::Json)),
+            _ => Err("valid values: human, json"),
         }
     }
 }
 
-impl Into<ColorChoice> for ColorArg {
-    fn into(self) -> ColorChoice {
-        self.0
+/// A command line argument that configures the kind of file generated by the assembler
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FormatArg {
+    /// An executable file with a header and section table
+    Executable,
+    /// The exact bytes of the program as they are laid out in memory when loaded at the load
+    /// address
+    Raw,
+    /// An object file that must be linked with `wolf-ld` to produce an executable
+    Object,
+}
+
+impl FormatArg {
+    /// Allowed values the argument
+    pub const VARIANTS: &'static [&'static str] = &["executable", "raw", "object"];
+}
+
+impl FromStr for FormatArg {
+    type Err = &'static str;
+
+    fn from_str(src: &str) -> Result<FormatArg, &'static str> {
+        match src {
+            _ if src.eq_ignore_ascii_case("executable") => Ok(FormatArg::Executable),
+            _ if src.eq_ignore_ascii_case("raw") => Ok(FormatArg::Raw),
+            _ if src.eq_ignore_ascii_case("object") => Ok(FormatArg::Object),
+            _ => Err("valid values: executable, raw, object"),
+        }
     }
 }
 
@@ -71,37 +91,42 @@ struct AssemblerOptions {
     /// Write output to <file>
     #[structopt(short = "o", name = "file")]
     output_path: Option<PathBuf>,
+    /// Include labels and source locations in the executable for better runtime errors
+    #[structopt(short = "g")]
+    debug_info: bool,
+    /// Write a listing of the address and encoding of every statement, along with every label
+    /// and constant, to <listing>
+    #[structopt(short = "l", name = "listing", parse(from_os_str))]
+    listing_path: Option<PathBuf>,
+    /// The kind of file to generate: an executable for the VM, a raw memory image that must be
+    /// loaded at the address given by `--load-addr`, or an object file to pass to `wolf-ld`
+    #[structopt(long = "format", parse(try_from_str), default_value = "executable",
+        possible_values = FormatArg::VARIANTS, case_insensitive = true)]
+    format: FormatArg,
+    /// The address where the program will be loaded into memory. Must match the `--load-addr`
+    /// passed to the VM.
+    #[structopt(long = "load-addr", name = "addr", default_value = "0", parse(try_from_str = parse_addr))]
+    load_addr: u64,
+    /// Define a constant with the given value (or `1` if no value is given) before assembling.
+    /// May be used multiple times.
+    #[structopt(short = "D", name = "name[=value]", number_of_values = 1)]
+    defines: Vec<String>,
     /// Configure coloring of output
     #[structopt(long = "color", parse(try_from_str), default_value = "auto",
         possible_values = ColorArg::VARIANTS, case_insensitive = true)]
     pub color: ColorArg,
-}
-
-macro_rules! quit {
-    ($diag:expr, $($args:tt)*) => {
-        {
-            $diag.error(format!($($args)*)).emit();
-            process::exit(1);
-        }
-    };
-}
-
-macro_rules! check_errors {
-    ($diag:expr) => {
-        let diag = $diag;
-        match diag.emitted_errors() {
-            0 => {},
-            1 => quit!(diag, "aborting due to 1 previous error"),
-            errors => quit!(diag, "aborting due to {} previous errors", errors),
-        }
-    };
+    /// The format of errors and warnings: messages for people to read, or one JSON object per
+    /// line for other programs to read
+    #[structopt(long = "error-format", parse(try_from_str), default_value = "human",
+        possible_values = ErrorFormatArg::VARIANTS, case_insensitive = true)]
+    error_format: ErrorFormatArg,
 }
 
 fn main() {
-    let AssemblerOptions {program_path, output_path, color} = AssemblerOptions::from_args();
+    let AssemblerOptions {program_path, output_path, debug_info, listing_path, format, load_addr, defines, color, error_format} = AssemblerOptions::from_args();
 
     let source_files = Arc::new(RwLock::new(SourceFiles::default()));
-    let diag = Diagnostics::new(source_files.clone(), color.into());
+    let diag = Diagnostics::with_error_format(source_files.clone(), color.into(), error_format.0);
 
     // Check that the path and stem are valid
     let program_stem = match (program_path.file_stem(), program_path.extension()) {
@@ -109,9 +134,13 @@ fn main() {
         _ => quit!(&diag, "Invalid input path. Must use extension `wa`"),
     };
 
-    // Default output path is the input path without its extension
-    let output_path = output_path.as_ref().map(|p| p.as_path())
-        .unwrap_or_else(|| Path::new(program_stem));
+    // Default output path is the input path without its extension, or with the extension `o` for
+    // object files
+    let default_output_path = match format {
+        FormatArg::Object => Path::new(program_stem).with_extension("o"),
+        FormatArg::Executable | FormatArg::Raw => PathBuf::from(program_stem),
+    };
+    let output_path = output_path.as_ref().unwrap_or(&default_output_path);
     // Append the current directory to the output path if necessary
     let output_path = if output_path.is_absolute() {
         output_path.to_path_buf()
@@ -121,29 +150,84 @@ fn main() {
         current_dir.join(output_path)
     };
 
+    let defines: Vec<_> = defines.iter()
+        .filter_map(|define| parse_define_arg(define, &source_files, &diag))
+        .collect();
+    check_errors!(&diag);
+
     let root_file = source_files.write().add_file(&program_path)
         .unwrap_or_else(|err| quit!(&diag, "Could not read source file `{}`: {}", program_path.display(), err));
-    let program = {
-        // New scope because we want to drop this lock guard as soon as possible
-        let files = source_files.read();
-        let tokens = parser::collect_tokens(files.source(root_file), &diag);
-        check_errors!(&diag);
-        parser::parse_program(&tokens, &diag)
-    };
+    let expanded_program = expand_program(&program_path, root_file, &source_files, &FsFileProvider, &defines, &diag);
     check_errors!(&diag);
 
-    let expanded_program = expand_includes(&program_path, program, &source_files, &diag, MAX_INCLUDE_DEPTH);
+    let validated_program = validate_program(expanded_program, &defines, &diag);
     check_errors!(&diag);
 
-    let validated_program = validate_program(expanded_program, &diag);
-    check_errors!(&diag);
+    if format != FormatArg::Object {
+        for label in &validated_program.externs {
+            diag.span_error(label.span, "`.extern` labels can only be used in object files (`--format object`) that are linked with `wolf-ld`").emit();
+        }
+        check_errors!(&diag);
+    }
+
+    if load_addr != 0 && format == FormatArg::Object {
+        diag.warning("the load address of an object file is ignored, pass `--load-addr` to `wolf-ld` instead").emit();
+    }
+
+    if listing_path.is_some() && format == FormatArg::Object {
+        quit!(&diag, "listings cannot be generated for object files since their addresses are not known until they are linked");
+    }
+
+    if debug_info && format == FormatArg::Raw {
+        diag.warning("debug info is not included in raw memory images").emit();
+    }
+
+    // Must be generated before layout since that discards all of the labels and spans
+    let debug_info = if debug_info && format != FormatArg::Raw {
+        Some(DebugInfo::new(&validated_program, &source_files.read()))
+    } else {
+        None
+    };
+
+    if format == FormatArg::Object {
+        let object = Object::layout_object(validated_program, &diag);
+        check_errors!(&diag);
+        // No more errors can be produced, so the source files are no longer needed
+        let source_files = mem::take(&mut *source_files.write());
+        let object = Object {debug_info, source_files, ..object};
+
+        let output_file = File::create(&output_path)
+            .unwrap_or_else(|err| quit!(&diag, "Could not open output path `{}`: {}", output_path.display(), err));
+        object.write_to(output_file)
+            .unwrap_or_else(|err| quit!(&diag, "Unable to write object file `{}`: {}", output_path.display(), err));
+        return;
+    }
 
-    let label_offsets = LabelOffsets::new(&validated_program);
+    let label_offsets = LabelOffsets::with_base_addr(&validated_program, load_addr);
+    // Must also be generated before layout
+    let listing = listing_path.as_ref()
+        .map(|_| Listing::new(&validated_program, &label_offsets, &source_files.read(), &program_path));
     let exec = Executable::layout_executable(validated_program, &diag, &label_offsets);
     check_errors!(&diag);
+    let exec = Executable {debug_info, ..exec};
+
+    if let (Some(listing), Some(listing_path)) = (listing, &listing_path) {
+        let listing_file = File::create(listing_path)
+            .unwrap_or_else(|err| quit!(&diag, "Could not open listing path `{}`: {}", listing_path.display(), err));
+        listing.write_to(BufWriter::new(listing_file), &exec)
+            .unwrap_or_else(|err| quit!(&diag, "Unable to write listing `{}`: {}", listing_path.display(), err));
+    }
 
-    let output_file = File::create(&output_path)
+    if format == FormatArg::Raw && exec.entry != 0 {
+        diag.warning(format!("raw memory images do not include the entry point, pass `--entry 0x{:x}` to the VM to run this program", load_addr + exec.entry)).emit();
+    }
+
+    let mut output_file = File::create(&output_path)
         .unwrap_or_else(|err| quit!(&diag, "Could not open output path `{}`: {}", output_path.display(), err));
-    bincode::serialize_into(output_file, &exec)
-        .unwrap_or_else(|err| quit!(&diag, "Unable to write executable `{}`: {}", output_path.display(), err));
+    let written = match format {
+        FormatArg::Executable => exec.write_to(&mut output_file),
+        FormatArg::Raw => output_file.write_all(&exec.to_raw_image()),
+        FormatArg::Object => unreachable!("bug: object files should have already been written"),
+    };
+    written.unwrap_or_else(|err| quit!(&diag, "Unable to write executable `{}`: {}", output_path.display(), err));
 }
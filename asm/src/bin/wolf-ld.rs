@@ -0,0 +1,86 @@
+//! wolf-ld - The Wolf Assembly Language linker
+//!
+//! Combines object files generated by `wolf-asm --format object` into an executable for the wolf
+//! virtual machine
+
+#![deny(unused_must_use)]
+
+use std::env;
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::BufReader;
+
+use parking_lot::RwLock;
+use structopt::StructOpt;
+
+use wolf_asm::{
+    diagnostics::Diagnostics,
+    parser::SourceFiles,
+    object::Object,
+    linker::link_objects,
+    cli::{ColorArg, parse_addr},
+    quit,
+    check_errors,
+};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "wolf-ld", about = "Linker for object files generated by the wolf-asm assembler")]
+struct LinkerOptions {
+    /// The object files (`.o`) to link, generated by `wolf-asm --format object`
+    #[structopt(name = "input", parse(from_os_str), required = true)]
+    object_paths: Vec<PathBuf>,
+    /// Write output to <file>
+    #[structopt(short = "o", name = "file")]
+    output_path: Option<PathBuf>,
+    /// The address where the program will be loaded into memory. Must match the `--load-addr`
+    /// passed to the VM.
+    #[structopt(long = "load-addr", name = "addr", default_value = "0", parse(try_from_str = parse_addr))]
+    load_addr: u64,
+    /// Configure coloring of output
+    #[structopt(long = "color", parse(try_from_str), default_value = "auto",
+        possible_values = ColorArg::VARIANTS, case_insensitive = true)]
+    pub color: ColorArg,
+}
+
+fn main() {
+    let LinkerOptions {object_paths, output_path, load_addr, color} = LinkerOptions::from_args();
+
+    let source_files = Arc::new(RwLock::new(SourceFiles::default()));
+    let diag = Diagnostics::new(source_files.clone(), color.into());
+
+    // Check that the paths and stems are valid
+    for object_path in &object_paths {
+        match (object_path.file_stem(), object_path.extension()) {
+            (Some(stem), Some(ext)) if !stem.is_empty() && ext == "o" => {},
+            _ => quit!(&diag, "Invalid input path `{}`. Must use extension `o`", object_path.display()),
+        }
+    }
+
+    // Default output path is the first input path without its extension
+    let default_output_path = Path::new(object_paths[0].file_stem().unwrap());
+    let output_path = output_path.as_deref().unwrap_or(default_output_path);
+    // Append the current directory to the output path if necessary
+    let output_path = if output_path.is_absolute() {
+        output_path.to_path_buf()
+    } else {
+        let current_dir = env::current_dir()
+            .unwrap_or_else(|err| quit!(&diag, "Could not access current directory: {}", err));
+        current_dir.join(output_path)
+    };
+
+    let objects = object_paths.iter().map(|object_path| {
+        let object_file = File::open(object_path)
+            .unwrap_or_else(|err| quit!(&diag, "Could not read object file `{}`: {}", object_path.display(), err));
+        Object::read_from(BufReader::new(object_file))
+            .unwrap_or_else(|err| quit!(&diag, "Could not load object file `{}`: {}", object_path.display(), err))
+    }).collect();
+
+    let exec = link_objects(objects, &source_files, &diag, load_addr);
+    check_errors!(&diag);
+
+    let mut output_file = File::create(&output_path)
+        .unwrap_or_else(|err| quit!(&diag, "Could not open output path `{}`: {}", output_path.display(), err));
+    exec.write_to(&mut output_file)
+        .unwrap_or_else(|err| quit!(&diag, "Unable to write executable `{}`: {}", output_path.display(), err));
+}
@@ -0,0 +1,75 @@
+//! Command line argument parsing and error reporting shared by the wolf command line programs
+
+use std::str::FromStr;
+
+use termcolor::ColorChoice;
+
+/// A command line argument that configures the coloring of the output
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorArg(pub ColorChoice);
+
+impl Default for ColorArg {
+    fn default() -> Self {
+        ColorArg(ColorChoice::Auto)
+    }
+}
+
+impl ColorArg {
+    /// Allowed values the argument
+    pub const VARIANTS: &'static [&'static str] = &["auto", "always", "ansi", "never"];
+}
+
+impl FromStr for ColorArg {
+    type Err = &'static str;
+
+    fn from_str(src: &str) -> Result<ColorArg, &'static str> {
+        match src {
+            _ if src.eq_ignore_ascii_case("auto") => Ok(ColorArg(ColorChoice::Auto)),
+            _ if src.eq_ignore_ascii_case("always") => Ok(ColorArg(ColorChoice::Always)),
+            _ if src.eq_ignore_ascii_case("ansi") => Ok(ColorArg(ColorChoice::AlwaysAnsi)),
+            _ if src.eq_ignore_ascii_case("never") => Ok(ColorArg(ColorChoice::Never)),
+            _ => Err("valid values: auto, always, ansi, never"),
+        }
+    }
+}
+
+impl From<ColorArg> for ColorChoice {
+    fn from(color: ColorArg) -> Self {
+        color.0
+    }
+}
+
+/// Parses a decimal or hexadecimal (`0x` prefix) address
+pub fn parse_addr(src: &str) -> Result<u64, String> {
+    let digits = src.replace('_', "");
+    let parsed = match digits.strip_prefix("0x") {
+        Some(hex_digits) => u64::from_str_radix(hex_digits, 16),
+        None => digits.parse(),
+    };
+
+    parsed.map_err(|_| format!("invalid address `{}`", src))
+}
+
+/// Emits an error with the given message and exits the program
+#[macro_export]
+macro_rules! quit {
+    ($diag:expr, $($args:tt)*) => {
+        {
+            $diag.error(format!($($args)*)).emit();
+            ::std::process::exit(1);
+        }
+    };
+}
+
+/// Exits the program if any errors have been emitted
+#[macro_export]
+macro_rules! check_errors {
+    ($diag:expr) => {
+        let diag = $diag;
+        match diag.emitted_errors() {
+            0 => {},
+            1 => $crate::quit!(diag, "aborting due to 1 previous error"),
+            errors => $crate::quit!(diag, "aborting due to {} previous errors", errors),
+        }
+    };
+}
@@ -0,0 +1,279 @@
+use std::mem;
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+
+use crate::ast;
+use crate::parser::Span;
+use crate::diagnostics::Diagnostics;
+use crate::const_expr::eval_expr;
+
+/// Removes every `.if`, `.ifdef`, `.else`, and `.endif` directive from a program, keeping only
+/// the statements in the branches whose conditions are true
+///
+/// Macro definitions are kept as-is. The conditional directives in a macro body are evaluated
+/// each time the macro is expanded (see `Conditionals::expand`).
+///
+/// If no errors occur, the returned program is guaranteed to not have any remaining conditional
+/// directives in it, except within macro definitions.
+pub fn expand_conditionals(
+    prog: ast::Program,
+    conditionals: &mut Conditionals,
+    diag: &Diagnostics,
+) -> ast::Program {
+    let ast::Program {stmts} = prog;
+
+    // Macros may be invoked before they are defined
+    conditionals.macro_names.extend(stmts.iter().filter_map(|stmt| match stmt {
+        ast::Stmt::Macro(header) => Some(header.name.value.clone()),
+        _ => None,
+    }));
+
+    ast::Program {stmts: conditionals.expand(stmts, diag)}
+}
+
+/// An `.if` or `.ifdef` block whose `.endif` has not been found yet
+#[derive(Debug)]
+struct Block {
+    /// The directive that started this block
+    start: ast::If,
+    /// The `.else` directive in this block, if found so far
+    else_span: Option<Span>,
+    /// True if the condition of the block is true
+    cond: bool,
+    /// True if the block itself is in an active branch
+    parent_active: bool,
+}
+
+impl Block {
+    /// Returns true if the statements currently being read in this block should be kept
+    fn is_active(&self) -> bool {
+        self.parent_active && (self.cond == self.else_span.is_none())
+    }
+}
+
+/// Evaluates conditional directives using the constants defined so far
+///
+/// A condition may only refer to constants that were defined before it (in a branch that was
+/// kept), or that were defined on the command line. Since macros are expanded after the rest of
+/// the program, the constants defined before each macro invocation are recorded so that the
+/// conditions in the macro body can be evaluated with them.
+#[derive(Debug, Default)]
+pub struct Conditionals {
+    /// The value of every constant defined so far, with any references to previously defined
+    /// constants already substituted
+    ///
+    /// Shared with `call_sites` until the next constant is defined.
+    consts: Arc<Consts>,
+    /// The names of the macros defined in the program
+    macro_names: HashSet<Arc<str>>,
+    /// The constants defined before each macro invocation, indexed by the span of the invocation
+    call_sites: HashMap<Span, Arc<Consts>>,
+}
+
+type Consts = HashMap<Arc<str>, ast::Expr>;
+
+impl Conditionals {
+    /// Creates a new set of conditionals, seeded with constants defined outside of the program
+    pub fn new(defines: &[ast::Const]) -> Self {
+        let mut conditionals = Self::default();
+        for define in defines {
+            conditionals.define(define);
+        }
+
+        conditionals
+    }
+
+    /// Removes every conditional directive from the given statements, keeping only the
+    /// statements in branches whose conditions are true
+    pub fn expand(&mut self, stmts: Vec<ast::Stmt>, diag: &Diagnostics) -> Vec<ast::Stmt> {
+        let mut expanded_stmts = Vec::with_capacity(stmts.len());
+        let mut blocks: Vec<Block> = Vec::new();
+        let mut stmts = stmts.into_iter();
+
+        while let Some(stmt) = stmts.next() {
+            let active = blocks.last().map(Block::is_active).unwrap_or(true);
+
+            match stmt {
+                ast::Stmt::If(if_stmt) => {
+                    // Conditions in inactive branches are not evaluated since they may refer to
+                    // constants that are not defined
+                    let cond = active && self.eval(&if_stmt.cond, diag);
+                    blocks.push(Block {start: if_stmt, else_span: None, cond, parent_active: active});
+                },
+
+                ast::Stmt::Else(span) => match blocks.last_mut() {
+                    Some(block) => match block.else_span {
+                        Some(prev) => diag.span_error(span, "duplicate `.else`")
+                            .span_note(prev, "previous `.else` here").emit(),
+                        None => block.else_span = Some(span),
+                    },
+                    None => diag.span_error(span, "`.else` without a matching `.if`").emit(),
+                },
+
+                ast::Stmt::EndIf(span) => {
+                    if blocks.pop().is_none() {
+                        diag.span_error(span, "`.endif` without a matching `.if`").emit();
+                    }
+                },
+
+                _ if !active => {},
+
+                ast::Stmt::Macro(header) => {
+                    // Macro bodies are kept as-is until the macro is expanded
+                    expanded_stmts.push(ast::Stmt::Macro(header));
+                    for stmt in &mut stmts {
+                        let is_end = matches!(stmt, ast::Stmt::EndMacro(_));
+                        expanded_stmts.push(stmt);
+                        if is_end {
+                            break;
+                        }
+                    }
+                },
+
+                ast::Stmt::Const(const_stmt) => {
+                    self.define(&const_stmt);
+                    expanded_stmts.push(ast::Stmt::Const(const_stmt));
+                },
+
+                ast::Stmt::Instr(instr) => {
+                    if self.macro_names.contains(&instr.name.value) {
+                        self.call_sites.insert(instr.span(), self.consts.clone());
+                    }
+                    expanded_stmts.push(ast::Stmt::Instr(instr));
+                },
+
+                stmt => expanded_stmts.push(stmt),
+            }
+        }
+
+        for block in blocks {
+            diag.span_error(block.start.span, format!("`{}` is missing `.endif`", block.start.cond)).emit();
+        }
+
+        expanded_stmts
+    }
+
+    /// Removes every conditional directive from the body of the macro invoked at the given call
+    /// site, using the constants defined before the invocation
+    ///
+    /// Constants defined in the body are only used for the rest of that body.
+    pub fn expand_macro_body(&mut self, stmts: Vec<ast::Stmt>, call_site: Span, diag: &Diagnostics) -> Vec<ast::Stmt> {
+        let consts = self.call_sites.get(&call_site).cloned().unwrap_or_else(|| self.consts.clone());
+        let outer_consts = mem::replace(&mut self.consts, consts);
+        let stmts = self.expand(stmts, diag);
+        self.consts = outer_consts;
+
+        stmts
+    }
+
+    /// Records the value of a constant so it can be used in later conditions
+    fn define(&mut self, const_stmt: &ast::Const) {
+        let ast::Const {name, value, span: _} = const_stmt;
+
+        let value = value.clone().map_names(&mut |name| match self.consts.get(&name.value) {
+            Some(value) => ast::Expr {span: name.span, ..value.clone()},
+            None => ast::Expr {span: name.span, kind: ast::ExprKind::Name(name)},
+        });
+        Arc::make_mut(&mut self.consts).insert(name.value.clone(), value);
+    }
+
+    fn eval(&self, cond: &ast::Condition, diag: &Diagnostics) -> bool {
+        match cond {
+            ast::Condition::Expr(expr) => {
+                let value = eval_expr(expr, diag, &mut |expr| match &expr.kind {
+                    ast::ExprKind::Name(name) => match self.consts.get(&name.value) {
+                        // Any names left in the value of a constant were not constants when it
+                        // was defined
+                        Some(value) => eval_expr(value, diag, &mut |value_expr| not_const(value_expr, Some(expr.span), diag)),
+                        None => not_const(expr, None, diag),
+                    },
+                    _ => not_const(expr, None, diag),
+                });
+
+                value != 0
+            },
+
+            ast::Condition::Defined(name) => self.consts.contains_key(&name.value),
+        }
+    }
+}
+
+/// Produces an error for a name or location counter that cannot be used in a condition
+///
+/// If the name or location counter is in the value of a constant, `const_span` is the span where
+/// that constant was used in the condition.
+fn not_const(expr: &ast::Expr, const_span: Option<Span>, diag: &Diagnostics) -> i128 {
+    let mut error = match &expr.kind {
+        ast::ExprKind::Name(name) => {
+            diag.span_error(expr.span, format!("`{}` must be a constant defined before it is used in a condition", name))
+        },
+        ast::ExprKind::Here => {
+            diag.span_error(expr.span, "the location counter `.` cannot be used in a condition")
+        },
+        _ => unreachable!("bug: only names and `.` should need to be looked up"),
+    };
+
+    if let Some(const_span) = const_span {
+        error = error.span_note(const_span, "in the value of the constant used here");
+    }
+    error.emit();
+
+    // Error recovery: treat the value as zero so more errors can be found
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parking_lot::RwLock;
+    use termcolor::ColorChoice;
+
+    use crate::parser::{SourceFiles, collect_tokens, parse_program};
+    use crate::const_table::parse_define_arg;
+
+    /// Expands the conditionals in the given source, returning the names of the remaining
+    /// instructions
+    fn expand_with_defines(source: &str, defines: &[&str]) -> Vec<String> {
+        let source_files = Arc::new(RwLock::new(SourceFiles::default()));
+        let diag = Diagnostics::new(source_files.clone(), ColorChoice::Never);
+
+        let defines: Vec<_> = defines.iter()
+            .map(|define| parse_define_arg(define, &source_files, &diag).expect("invalid define"))
+            .collect();
+        let prog = {
+            let mut files = source_files.write();
+            let handle = files.add_source("test.wa", source.as_bytes());
+            let tokens = collect_tokens(files.source(handle), &diag);
+            parse_program(&tokens, &diag)
+        };
+
+        let prog = expand_conditionals(prog, &mut Conditionals::new(&defines), &diag);
+        assert_eq!(diag.emitted_errors(), 0);
+
+        prog.stmts.into_iter().filter_map(|stmt| match stmt {
+            ast::Stmt::Instr(instr) => Some(instr.name.value.to_string()),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn command_line_defines() {
+        let source = "\
+.ifdef DEBUG
+  debug
+.else
+  release
+.endif
+.if LEVEL * 2 - 4
+  nonzero
+.endif
+";
+
+        assert_eq!(expand_with_defines(source, &["LEVEL=2"]), &["release"]);
+        assert_eq!(expand_with_defines(source, &["DEBUG", "LEVEL=1 << 2"]), &["debug", "nonzero"]);
+        // Constants defined in the program take precedence over the command line
+        let source = format!(".const LEVEL 2\n{}", source);
+        assert_eq!(expand_with_defines(&source, &["DEBUG=0", "LEVEL=3"]), &["debug"]);
+    }
+}
@@ -0,0 +1,93 @@
+//! Evaluation of constant expressions
+
+use crate::ast;
+use crate::diagnostics::Diagnostics;
+
+/// The smallest value allowed in an expression (the minimum 64-bit signed integer)
+const MIN_VALUE: i128 = i64::MIN as i128;
+/// The largest value allowed in an expression (the maximum 64-bit unsigned integer)
+const MAX_VALUE: i128 = u64::MAX as i128;
+
+/// Evaluates an expression to an immediate value
+///
+/// The `lookup` function is called to find the value of each name and of the location counter
+/// (`.`) in the expression.
+///
+/// A floating point literal evaluates to its IEEE 754 binary64 bit pattern. Floating point
+/// literals are only allowed on their own (optionally negated), not as part of a larger
+/// expression.
+///
+/// Every intermediate value must fit in 64 bits, either as a signed or an unsigned integer. Any
+/// errors are reported and evaluation continues with a value of zero so more errors can be found.
+pub fn eval_expr(
+    expr: &ast::Expr,
+    diag: &Diagnostics,
+    lookup: &mut impl FnMut(&ast::Expr) -> i128,
+) -> i128 {
+    match &expr.kind {
+        &ast::ExprKind::Float(value) => value.to_bits() as i128,
+        ast::ExprKind::Neg(operand) => match operand.kind {
+            ast::ExprKind::Float(value) => (-value).to_bits() as i128,
+            _ => eval_integer(expr, diag, lookup),
+        },
+        _ => eval_integer(expr, diag, lookup),
+    }
+}
+
+fn eval_integer(
+    expr: &ast::Expr,
+    diag: &Diagnostics,
+    lookup: &mut impl FnMut(&ast::Expr) -> i128,
+) -> i128 {
+    use ast::ExprKind::*;
+    let value = match &expr.kind {
+        &Integer(value) => return value,
+
+        Float(_) => {
+            diag.span_error(expr.span, "floating point numbers cannot be used in expressions").emit();
+            return 0;
+        },
+
+        Name(_) | Here => return lookup(expr),
+
+        Neg(operand) => eval_integer(operand, diag, lookup).checked_neg(),
+
+        BinOp(op, lhs, rhs) => {
+            let lhs_value = eval_integer(lhs, diag, lookup);
+            let rhs_value = eval_integer(rhs, diag, lookup);
+
+            use ast::BinOp::*;
+            match op {
+                Add => lhs_value.checked_add(rhs_value),
+                Sub => lhs_value.checked_sub(rhs_value),
+                Mul => lhs_value.checked_mul(rhs_value),
+
+                Div if rhs_value == 0 => {
+                    diag.span_error(rhs.span, "attempt to divide by zero").emit();
+                    return 0;
+                },
+                Div => lhs_value.checked_div(rhs_value),
+
+                Shl | Shr if !(0..=63).contains(&rhs_value) => {
+                    diag.span_error(rhs.span, format!("shift amount `{}` must be between `0` and `63`", rhs_value)).emit();
+                    return 0;
+                },
+                // Multiplying catches any bits that are shifted out of the value
+                Shl => lhs_value.checked_mul(1 << rhs_value),
+                // Arithmetic shift, so negative values stay negative
+                Shr => Some(lhs_value >> rhs_value),
+
+                And => Some(lhs_value & rhs_value),
+                Or => Some(lhs_value | rhs_value),
+            }
+        },
+    };
+
+    match value {
+        Some(value) if (MIN_VALUE..=MAX_VALUE).contains(&value) => value,
+        _ => {
+            diag.span_error(expr.span, "the value of this expression does not fit in 64 bits").emit();
+            0
+        },
+    }
+}
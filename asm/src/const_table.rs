@@ -2,9 +2,42 @@ use std::borrow::Borrow;
 use std::hash::{Hash, Hasher};
 use std::collections::HashSet;
 
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
 use crate::ast;
+use crate::parser::{SourceFiles, collect_tokens, parse_define};
 use crate::diagnostics::Diagnostics;
 
+/// The path used in diagnostics for constants defined on the command line
+const DEFINE_PATH: &str = "<command line>";
+
+/// Parses a constant defined on the command line as `NAME=VALUE`, where `VALUE` may be any
+/// expression, or as just `NAME`, which defines the constant with the value `1`
+///
+/// The definition is added to the source files so that diagnostics can point to it. Returns
+/// `None` if an error occurred.
+pub fn parse_define_arg(
+    define: &str,
+    source_files: &Arc<RwLock<SourceFiles>>,
+    diag: &Diagnostics,
+) -> Option<ast::Const> {
+    // Replacing `=` with a space keeps the columns in diagnostics the same as the original text
+    let source = match define.split_once('=') {
+        Some((name, value)) => format!("{} {}", name, value),
+        None => format!("{} 1", define),
+    };
+
+    let tokens = {
+        let mut files = source_files.write();
+        let handle = files.add_source(DEFINE_PATH, source.as_bytes());
+        collect_tokens(files.source(handle), diag)
+    };
+
+    parse_define(&tokens, diag)
+}
+
 #[derive(Debug, Clone)]
 struct ConstEntry(ast::Const);
 
@@ -31,18 +64,30 @@ impl Borrow<ast::Ident> for ConstEntry {
 #[derive(Debug)]
 pub struct ConstTable {
     const_values: HashSet<ConstEntry>,
+    /// Constants whose values depend on themselves
+    ///
+    /// These have already produced errors, so they are substituted with zero.
+    recursive: HashSet<ast::Ident>,
 }
 
 impl ConstTable {
-    pub fn new(prog: &ast::Program, diag: &Diagnostics, labels: &HashSet<ast::Ident>) -> Self {
+    /// Creates a table from every constant in the program, seeded with the constants in
+    /// `defines` (e.g. from the command line)
+    pub fn new(
+        prog: &ast::Program,
+        defines: &[ast::Const],
+        diag: &Diagnostics,
+        labels: &HashSet<ast::Ident>,
+    ) -> Self {
         let mut const_values = HashSet::new();
-
-        for stmt in &prog.stmts {
-            let const_stmt = match stmt {
-                ast::Stmt::Const(const_stmt) => const_stmt,
-                _ => continue,
-            };
-
+        // Used to tell whether a constant was defined on the command line
+        let define_spans: HashSet<_> = defines.iter().map(|define| define.span).collect();
+
+        let prog_consts = prog.stmts.iter().filter_map(|stmt| match stmt {
+            ast::Stmt::Const(const_stmt) => Some(const_stmt),
+            _ => None,
+        });
+        for const_stmt in defines.iter().chain(prog_consts) {
             if let Some(label) = labels.get(&const_stmt.name) {
                 diag.span_error(const_stmt.span, format!("constant name `{}` conflicts with a label name", const_stmt.name))
                     .span_note(label.span, "the conflicting label")
@@ -50,7 +95,13 @@ impl ConstTable {
             }
 
             if let Some(ConstEntry(prev_const)) = const_values.get(&const_stmt.name) {
-                if prev_const.value != const_stmt.value {
+                if define_spans.contains(&prev_const.span) && !define_spans.contains(&const_stmt.span) {
+                    // The command line value is ignored even if it is the same, which is likely a
+                    // mistake
+                    diag.span_warning(const_stmt.span, format!("constant named `{}` overrides the value defined on the command line", const_stmt.name))
+                        .span_note(prev_const.span, "the value defined on the command line")
+                        .emit();
+                } else if prev_const.value != const_stmt.value {
                     diag.span_warning(const_stmt.span, format!("constant named `{}` was redefined", const_stmt.name))
                         .span_note(prev_const.span, "the previous declaration of this constant")
                         .emit();
@@ -61,13 +112,67 @@ impl ConstTable {
             const_values.replace(ConstEntry(const_stmt.clone()));
         }
 
-        Self {const_values}
+        let mut table = Self {const_values, recursive: HashSet::new()};
+
+        // Sorted so that errors are produced in a consistent order
+        let mut consts: Vec<_> = table.const_values.iter().map(|ConstEntry(const_stmt)| const_stmt).collect();
+        consts.sort_unstable_by_key(|const_stmt| const_stmt.span.start);
+        let recursive = consts.into_iter().filter(|const_stmt| table.is_recursive(&const_stmt.name)).map(|const_stmt| {
+            diag.span_error(const_stmt.span, format!("the value of constant `{}` depends on itself", const_stmt.name)).emit();
+            const_stmt.name.clone()
+        }).collect();
+        table.recursive = recursive;
+
+        table
+    }
+
+    /// Returns true if the value of the given constant refers to the constant itself, either
+    /// directly or through other constants
+    fn is_recursive(&self, name: &ast::Ident) -> bool {
+        let mut visited = HashSet::new();
+        let mut remaining = vec![name];
+        while let Some(current) = remaining.pop() {
+            let ConstEntry(const_stmt) = match self.const_values.get(current) {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            let mut found = false;
+            const_stmt.value.for_each_name(&mut |value_name| {
+                if value_name == name {
+                    found = true;
+                } else if visited.insert(value_name) {
+                    remaining.push(value_name);
+                }
+            });
+
+            if found {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Returns every constant in the table with all constants in its value substituted, sorted by
+    /// the position of their definition
+    ///
+    /// A redefined constant is placed where it was last defined.
+    pub fn into_consts(self) -> Vec<ast::Const> {
+        let mut consts: Vec<_> = self.const_values.iter().map(|ConstEntry(const_stmt)| ast::Const {
+            name: const_stmt.name.clone(),
+            value: self.subst_name(const_stmt.name.clone()),
+            span: const_stmt.span,
+        }).collect();
+        consts.sort_unstable_by_key(|const_stmt| const_stmt.span.start);
+
+        consts
     }
 
     /// Replaces all constant names with the immediate values that they map to
     pub fn subst_instr(&self, instr: ast::Instr) -> ast::Instr {
         // Fast path for instructions without names in them
-        if !instr.args.iter().any(|arg| arg.is_name()) {
+        if !instr.args.iter().any(|arg| arg.has_names()) {
             return instr;
         }
 
@@ -75,21 +180,33 @@ impl ConstTable {
         ast::Instr {
             name,
             args: args.into_iter().map(|arg| match arg {
-                ast::InstrArg::Name(name) => match self.const_values.get(&name) {
-                    Some(ConstEntry(ast::Const {value: const_value, ..}))  => {
-                        ast::InstrArg::Immediate(ast::Integer {
-                            value: const_value.value,
-                            // Preserve the span of the replaced value so error messages point to
-                            // the right place
-                            span: name.span,
-                        })
-                    },
-
-                    None => ast::InstrArg::Name(name),
-                },
-
+                ast::InstrArg::Name(name) => self.subst_name(name).into(),
+                ast::InstrArg::Expr(expr) => self.subst_expr(expr).into(),
                 arg => arg,
             }).collect(),
         }
     }
+
+    /// Replaces all constant names in the given expression with the values that they map to
+    pub fn subst_expr(&self, expr: ast::Expr) -> ast::Expr {
+        expr.map_names(&mut |name| self.subst_name(name))
+    }
+
+    fn subst_name(&self, name: ast::Ident) -> ast::Expr {
+        let kind = if self.recursive.contains(&name) {
+            // Error recovery: an error was already produced for this constant
+            ast::ExprKind::Integer(0)
+        } else {
+            match self.const_values.get(&name) {
+                Some(ConstEntry(const_stmt)) => self.subst_expr(const_stmt.value.clone()).kind,
+                None => ast::ExprKind::Name(name.clone()),
+            }
+        };
+
+        ast::Expr {
+            kind,
+            // Preserve the span of the replaced value so error messages point to the right place
+            span: name.span,
+        }
+    }
 }
@@ -1,8 +1,12 @@
 mod writer;
 mod diagnostic;
+mod json;
 
 pub use diagnostic::*;
+pub use json::*;
 
+use std::io;
+use std::mem;
 use std::borrow::Cow;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -17,26 +21,80 @@ type OutputStream = termcolor::StandardStream;
 #[cfg(test)]
 type OutputStream = writer::NullWriter;
 
+/// Where emitted diagnostics go
+enum Output {
+    /// Diagnostics are written to the stream as soon as they are emitted
+    Stream(OutputStream),
+    /// Diagnostics are written to stderr as JSON as soon as they are emitted (see the `json`
+    /// module)
+    Json(io::Stderr),
+    /// Diagnostics are stored so that they can be retrieved later (see
+    /// `Diagnostics::take_collected`)
+    Collect(Vec<Diagnostic<'static>>),
+}
+
+/// The format that diagnostics are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Messages for people to read, colored according to the `ColorChoice`
+    Human,
+    /// One JSON object per line for other programs to read
+    Json,
+}
+
 pub struct Diagnostics {
     source_files: Arc<RwLock<SourceFiles>>,
-    /// The stream where diagnostics will be written to
-    out: Mutex<OutputStream>,
+    /// Where diagnostics will be written to
+    out: Mutex<Output>,
     /// The number of errors that have been emitted
     errors: AtomicUsize,
 }
 
 impl Diagnostics {
     pub fn new(source_files: Arc<RwLock<SourceFiles>>, color_choice: ColorChoice) -> Self {
-        Self {
-            source_files,
+        Self::with_error_format(source_files, color_choice, ErrorFormat::Human)
+    }
+
+    pub fn with_error_format(
+        source_files: Arc<RwLock<SourceFiles>>,
+        color_choice: ColorChoice,
+        error_format: ErrorFormat,
+    ) -> Self {
+        let out = match error_format {
             #[cfg(not(test))]
-            out: Mutex::new(termcolor::StandardStream::stderr(color_choice)),
+            ErrorFormat::Human => Output::Stream(termcolor::StandardStream::stderr(color_choice)),
             #[cfg(test)]
-            out: Mutex::new(writer::NullWriter::new(color_choice)),
+            ErrorFormat::Human => Output::Stream(writer::NullWriter::new(color_choice)),
+            ErrorFormat::Json => Output::Json(io::stderr()),
+        };
+
+        Self {
+            source_files,
+            out: Mutex::new(out),
+            errors: AtomicUsize::default(),
+        }
+    }
+
+    /// Creates a set of diagnostics that stores every emitted diagnostic instead of writing it
+    /// out, e.g. so that diagnostics can be shown in an editor
+    pub fn collecting(source_files: Arc<RwLock<SourceFiles>>) -> Self {
+        Self {
+            source_files,
+            out: Mutex::new(Output::Collect(Vec::new())),
             errors: AtomicUsize::default(),
         }
     }
 
+    /// Removes and returns every diagnostic emitted so far, in the order they were emitted
+    ///
+    /// Always returns an empty list unless this was created with `collecting`.
+    pub fn take_collected(&self) -> Vec<Diagnostic<'static>> {
+        match &mut *self.out.lock() {
+            Output::Stream(_) | Output::Json(_) => Vec::new(),
+            Output::Collect(collected) => mem::take(collected),
+        }
+    }
+
     /// Returns the number of errors that have been emitted
     pub fn emitted_errors(&self) -> usize {
         self.errors.load(Ordering::SeqCst)
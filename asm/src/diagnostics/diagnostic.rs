@@ -1,14 +1,19 @@
 use std::borrow::Cow;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use std::io::Write;
+
 use parking_lot::{MutexGuard, RwLockReadGuard};
+use serde::Serialize;
 
 use crate::parser::{Span, SourceFiles};
 
-use super::OutputStream;
+use super::{Output, OutputStream};
 use super::writer::DiagnosticsWriter;
+use super::json::JsonDiagnostic;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Level {
     Error,
     Warning,
@@ -24,6 +29,13 @@ pub struct Message<'a> {
     pub label: Cow<'a, str>,
 }
 
+impl<'a> Message<'a> {
+    pub fn into_owned(self) -> Message<'static> {
+        let Self {level, label} = self;
+        Message {level, label: Cow::Owned(label.into_owned())}
+    }
+}
+
 /// A fragment of code with any number of annotations
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Fragment<'a> {
@@ -39,10 +51,23 @@ pub struct Diagnostic<'a> {
     pub fragments: Vec<Fragment<'a>>,
 }
 
+impl<'a> Diagnostic<'a> {
+    pub fn into_owned(self) -> Diagnostic<'static> {
+        let Self {title, fragments} = self;
+        Diagnostic {
+            title: title.into_owned(),
+            fragments: fragments.into_iter().map(|Fragment {span, message}| Fragment {
+                span,
+                message: message.into_owned(),
+            }).collect(),
+        }
+    }
+}
+
 #[must_use]
 pub struct DiagnosticWriter<'a> {
     pub(super) source_files: RwLockReadGuard<'a, SourceFiles>,
-    pub(super) out: MutexGuard<'a, OutputStream>,
+    pub(super) out: MutexGuard<'a, Output>,
     pub(super) errors: &'a AtomicUsize,
     pub(super) data: Diagnostic<'a>,
 }
@@ -81,29 +106,84 @@ impl<'a> DiagnosticWriter<'a> {
 
     pub fn emit(self) {
         let Self {source_files, mut out, errors, data} = self;
-        let Diagnostic {title, fragments} = &data;
 
-        if title.level == Level::Error {
+        if data.title.level == Level::Error {
             errors.fetch_add(1, Ordering::SeqCst);
         }
 
+        let out = match &mut *out {
+            Output::Stream(stream) => stream,
+            Output::Json(stream) => {
+                let notes = expansion_notes(&source_files, &data);
+                let json = JsonDiagnostic::new(&source_files, &data, &notes);
+                let json = serde_json::to_string(&json).expect("bug: diagnostics should always serialize");
+                writeln!(stream.lock(), "{}", json).expect("IO error");
+                return;
+            },
+            Output::Collect(collected) => {
+                collected.push(data.into_owned());
+                return;
+            },
+        };
+        let Diagnostic {title, fragments} = &data;
+
         if let Some(frag) = fragments.get(0) {
             // Skip the title if it is the same as the first fragment
             if frag.message != *title {
-                emit_message(&source_files, &mut out, None, title);
+                emit_message(&source_files, out, None, title);
             }
         } else {
-            emit_message(&source_files, &mut out, None, title);
+            emit_message(&source_files, out, None, title);
         }
 
         for frag in fragments {
             let &Fragment {span, ref message} = frag;
-            emit_message(&source_files, &mut out, Some(span), message);
+            emit_message(&source_files, out, Some(span), message);
         }
+
+        for Fragment {span, message} in expansion_notes(&source_files, &data) {
+            emit_message(&source_files, out, Some(span), &message);
+        }
+
         out.write_newline().expect("IO error");
     }
 }
 
+/// Returns a note pointing to every macro invocation that led to the code with the primary span
+/// of the given diagnostic
+fn expansion_notes(source_files: &SourceFiles, diagnostic: &Diagnostic) -> Vec<Fragment<'static>> {
+    let mut expansions = Vec::new();
+    let mut expansion_span = diagnostic.fragments.first().map(|frag| frag.span);
+    while let Some(expansion) = expansion_span.and_then(|span| source_files.expansion(span)) {
+        expansions.push(expansion);
+        expansion_span = Some(expansion.call_site);
+    }
+
+    // Recursive macros invoke themselves from the same place many times, so those invocations
+    // are grouped together into a single note
+    let mut notes = Vec::new();
+    let mut expansions = expansions.into_iter().peekable();
+    while let Some(expansion) = expansions.next() {
+        let call_site_pos = source_files.pos(expansion.call_site);
+        let mut count = 1;
+        while expansions.next_if(|next| next.name == expansion.name && source_files.pos(next.call_site) == call_site_pos).is_some() {
+            count += 1;
+        }
+
+        let label = if count == 1 {
+            format!("in this expansion of macro `{}`", expansion.name)
+        } else {
+            format!("in {} nested expansions of macro `{}`", count, expansion.name)
+        };
+        notes.push(Fragment {
+            span: expansion.call_site,
+            message: Message {level: Level::Note, label: label.into()},
+        });
+    }
+
+    notes
+}
+
 fn emit_message(
     source_files: &SourceFiles,
     out: &mut OutputStream,
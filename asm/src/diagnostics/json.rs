@@ -0,0 +1,175 @@
+//! Diagnostics in a machine readable format, one JSON object per diagnostic
+//!
+//! For example:
+//!
+//! ```json
+//! {
+//!   "level": "error",
+//!   "message": "unknown instruction `bogus`",
+//!   "spans": [{
+//!     "file": "test.wa", "byte_start": 20, "byte_end": 25,
+//!     "line_start": 3, "column_start": 5, "line_end": 3, "column_end": 10,
+//!     "is_primary": true, "label": "unknown instruction `bogus`"
+//!   }],
+//!   "children": []
+//! }
+//! ```
+//!
+//! Lines and columns are 1-based and `column_end` is one past the last column of the span. Byte
+//! ranges are offsets from the start of the file, with `byte_end` one past the last byte.
+
+use serde::Serialize;
+
+use crate::parser::{Span, SourceFiles};
+
+use super::{Level, Message, Fragment, Diagnostic};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JsonDiagnostic {
+    pub level: Level,
+    pub message: String,
+    /// The code that the diagnostic applies to, with the primary span first
+    pub spans: Vec<JsonSpan>,
+    /// Notes and help messages attached to the diagnostic
+    pub children: Vec<JsonDiagnostic>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JsonSpan {
+    pub file: String,
+    pub byte_start: usize,
+    pub byte_end: usize,
+    pub line_start: usize,
+    pub column_start: usize,
+    pub line_end: usize,
+    pub column_end: usize,
+    /// True for the span that the diagnostic is about, false for any related code
+    pub is_primary: bool,
+    pub label: String,
+}
+
+impl JsonDiagnostic {
+    /// Converts a diagnostic and the notes pointing to the macro invocations it came from (see
+    /// `expansion_notes`)
+    ///
+    /// The first fragment is the primary span. Any later fragments with the `note` or `help`
+    /// level become children, and all other fragments become secondary spans.
+    pub fn new(source_files: &SourceFiles, diagnostic: &Diagnostic, expansion_notes: &[Fragment]) -> Self {
+        let Diagnostic {title, fragments} = diagnostic;
+
+        let mut spans = Vec::new();
+        let mut children = Vec::new();
+        for (i, frag) in fragments.iter().enumerate() {
+            match frag.message.level {
+                Level::Note | Level::Help if i > 0 => children.push(Self::child(source_files, frag)),
+                _ => spans.push(JsonSpan::new(source_files, frag.span, i == 0, &frag.message)),
+            }
+        }
+        children.extend(expansion_notes.iter().map(|frag| Self::child(source_files, frag)));
+
+        Self {
+            level: title.level,
+            message: title.label.to_string(),
+            spans,
+            children,
+        }
+    }
+
+    fn child(source_files: &SourceFiles, frag: &Fragment) -> Self {
+        let Fragment {span, message} = frag;
+        Self {
+            level: message.level,
+            message: message.label.to_string(),
+            spans: vec![JsonSpan::new(source_files, *span, true, message)],
+            children: Vec::new(),
+        }
+    }
+}
+
+impl JsonSpan {
+    fn new(source_files: &SourceFiles, span: Span, is_primary: bool, message: &Message) -> Self {
+        let pos = source_files.pos(span);
+        let bytes = source_files.file_range(span);
+
+        Self {
+            file: pos.path.to_string_lossy().into_owned(),
+            byte_start: bytes.start,
+            byte_end: bytes.end,
+            line_start: pos.start_line,
+            column_start: pos.start_offset,
+            line_end: pos.end_line,
+            // The end offset is the column of the last byte in the span
+            column_end: pos.end_offset + 1,
+            is_primary,
+            label: message.label.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn spans_and_children() {
+        let mut source_files = SourceFiles::default();
+        source_files.add_source("other.wa", b"x");
+        source_files.add_source("test.wa", b"section .code\nmain:\nmain: add $1, 2\n");
+
+        let diagnostic = Diagnostic {
+            title: Message {level: Level::Error, label: "duplicate label `main`".into()},
+            fragments: vec![
+                Fragment {
+                    span: Span {start: 21, end: 25},
+                    message: Message {level: Level::Error, label: "duplicate label `main`".into()},
+                },
+                Fragment {
+                    span: Span {start: 15, end: 19},
+                    message: Message {level: Level::Note, label: "previously defined here".into()},
+                },
+            ],
+        };
+        let json = JsonDiagnostic::new(&source_files, &diagnostic, &[]);
+
+        let expected = json!({
+            "level": "error",
+            "message": "duplicate label `main`",
+            "spans": [{
+                "file": "test.wa", "byte_start": 20, "byte_end": 24,
+                "line_start": 3, "column_start": 1, "line_end": 3, "column_end": 5,
+                "is_primary": true, "label": "duplicate label `main`",
+            }],
+            "children": [{
+                "level": "note",
+                "message": "previously defined here",
+                "spans": [{
+                    "file": "test.wa", "byte_start": 14, "byte_end": 18,
+                    "line_start": 2, "column_start": 1, "line_end": 2, "column_end": 5,
+                    "is_primary": true, "label": "previously defined here",
+                }],
+                "children": [],
+            }],
+        });
+        assert_eq!(serde_json::to_value(&json).unwrap(), expected);
+    }
+
+    #[test]
+    fn no_spans() {
+        let source_files = SourceFiles::default();
+        let diagnostic = Diagnostic {
+            title: Message {level: Level::Warning, label: "debug info is not included".into()},
+            fragments: Vec::new(),
+        };
+        let json = JsonDiagnostic::new(&source_files, &diagnostic, &[]);
+
+        let expected = json!({
+            "level": "warning",
+            "message": "debug info is not included",
+            "spans": [],
+            "children": [],
+        });
+        assert_eq!(serde_json::to_value(&json).unwrap(), expected);
+    }
+}
@@ -1,6 +1,11 @@
 mod binary_format;
+mod debug_info;
+mod file_format;
+mod raw_image;
 
 pub use binary_format::*;
+pub use debug_info::*;
+pub use file_format::*;
 
 use serde::{Serialize, Deserialize};
 
@@ -12,23 +17,87 @@ use crate::diagnostics::Diagnostics;
 pub struct Executable {
     pub code_section: Vec<Stmt>,
     pub static_section: Vec<Stmt>,
+    /// Labels and source locations, only included if requested
+    pub debug_info: Option<DebugInfo>,
+    /// The offset of the first instruction to execute from the start of the program
+    pub entry: u64,
 }
 
 impl Executable {
     pub fn layout_executable(prog: asm::Program, diag: &Diagnostics, labels: &LabelOffsets) -> Self {
-        let asm::Program {code_section, static_section} = prog;
+        let asm::Program {code_section, static_section, entry, end_labels: _, globals: _, externs: _, consts: _} = prog;
 
-        let code_section = code_section.map(|section| layout_section(section, diag, labels)).unwrap_or_default();
-        let static_section = static_section.map(|section| layout_section(section, diag, labels)).unwrap_or_default();
+        let entry = entry.map(|label| entry_offset(code_section.as_ref(), &label)).unwrap_or(0);
 
-        Self {code_section, static_section}
+        let mut addr = labels.base_addr();
+        let code_section = code_section.map(|section| layout_section(section, diag, labels, &mut addr)).unwrap_or_default();
+        let static_section = static_section.map(|section| layout_section(section, diag, labels, &mut addr)).unwrap_or_default();
+
+        Self {code_section, static_section, debug_info: None, entry}
+    }
+}
+
+/// Returns the offset of the statement with the given label from the start of the code section
+pub fn entry_offset(code_section: Option<&asm::Section>, label: &asm::Ident) -> u64 {
+    let stmts = code_section.map(|section| &section.stmts[..]).unwrap_or_default();
+    let mut offset = 0;
+    for stmt in stmts {
+        if stmt.labels.contains(label) {
+            return offset;
+        }
+
+        offset += stmt.size_bytes();
     }
+
+    unreachable!("bug: entry point should have been validated to be a label in the code section")
 }
 
-fn layout_section(section: asm::Section, diag: &Diagnostics, labels: &LabelOffsets) -> Vec<Stmt> {
+/// Lays out each statement in the section, starting at `addr`
+///
+/// After this, `addr` is updated to the address after the last statement in the section.
+fn layout_section(section: asm::Section, diag: &Diagnostics, labels: &LabelOffsets, addr: &mut u64) -> Vec<Stmt> {
     let asm::Section {section_header_span: _, stmts} = section;
-    stmts.into_iter().map(|stmt| match stmt.kind {
-        asm::StmtKind::StaticData(data) => Stmt::StaticData(data.into()),
-        asm::StmtKind::Instr(instr) => Stmt::Instr(instr.layout(diag, labels)),
-    }).collect()
+    let mut laid_out_stmts = Vec::with_capacity(stmts.len());
+
+    for stmt in stmts {
+        let stmt_addr = *addr;
+        *addr += stmt.size_bytes();
+
+        let data = match stmt.kind {
+            asm::StmtKind::StaticData(asm::StaticData::StaticBytes(data)) => {
+                layout_static_bytes(data, diag, labels, stmt_addr, &mut laid_out_stmts);
+                continue;
+            },
+
+            asm::StmtKind::StaticData(asm::StaticData::StaticZero(data)) => StaticData::StaticZero(data.into()),
+            asm::StmtKind::StaticData(asm::StaticData::StaticUninit(data)) => StaticData::StaticUninit(data.into()),
+            asm::StmtKind::StaticData(asm::StaticData::StaticByteStr(data)) => StaticData::StaticByteStr(data.into()),
+
+            asm::StmtKind::Instr(instr) => {
+                laid_out_stmts.push(Stmt::Instr(instr.layout(diag, labels, stmt_addr)));
+                continue;
+            },
+        };
+
+        laid_out_stmts.push(Stmt::StaticData(data));
+    }
+
+    laid_out_stmts
+}
+
+/// Lays out each value of a `.bN` directive starting at `addr` as a separate statement,
+/// resolving any labels and expressions in the values
+fn layout_static_bytes(
+    data: asm::StaticBytes,
+    diag: &Diagnostics,
+    labels: &LabelOffsets,
+    mut addr: u64,
+    out: &mut Vec<Stmt>,
+) {
+    let asm::StaticBytes {size, values, span: _} = data;
+    for value in values {
+        let value = labels.eval(&value, addr, diag);
+        out.push(Stmt::StaticData(StaticData::StaticBytes(StaticBytes::new(size, value, diag))));
+        addr += size as u64;
+    }
 }
@@ -5,6 +5,7 @@ use std::sync::Arc;
 use serde::{Serialize, Deserialize};
 
 use crate::asm::{self, layout::InstrLayout};
+use crate::diagnostics::Diagnostics;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Stmt {
@@ -12,6 +13,16 @@ pub enum Stmt {
     Instr(InstrLayout),
 }
 
+impl Stmt {
+    /// Returns the size in bytes of this statement in memory
+    pub fn size_bytes(&self) -> u64 {
+        match self {
+            Stmt::StaticData(data) => data.size_bytes(),
+            Stmt::Instr(_) => 8,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum StaticData {
     StaticBytes(StaticBytes),
@@ -20,14 +31,15 @@ pub enum StaticData {
     StaticByteStr(StaticByteStr),
 }
 
-impl From<asm::StaticData> for StaticData {
-    fn from(data: asm::StaticData) -> Self {
-        use asm::StaticData::*;
-        match data {
-            StaticBytes(data) => StaticData::StaticBytes(data.into()),
-            StaticZero(data) => StaticData::StaticZero(data.into()),
-            StaticUninit(data) => StaticData::StaticUninit(data.into()),
-            StaticByteStr(data) => StaticData::StaticByteStr(data.into()),
+impl StaticData {
+    /// Returns the size in bytes of this data in memory
+    pub fn size_bytes(&self) -> u64 {
+        use StaticData::*;
+        match self {
+            StaticBytes(data) => data.as_bytes().len() as u64,
+            StaticZero(self::StaticZero {nbytes}) |
+            StaticUninit(self::StaticUninit {nbytes}) => *nbytes,
+            StaticByteStr(data) => data.bytes.len() as u64,
         }
     }
 }
@@ -43,14 +55,35 @@ pub enum StaticBytes {
     B8([u8; 8]),
 }
 
-impl From<asm::StaticBytes> for StaticBytes {
-    fn from(data: asm::StaticBytes) -> Self {
-        use asm::StaticBytesValue::*;
-        match data.value {
-            B1(data, _) => StaticBytes::B1(data),
-            B2(data, _) => StaticBytes::B2(data),
-            B4(data, _) => StaticBytes::B4(data),
-            B8(data, _) => StaticBytes::B8(data),
+impl StaticBytes {
+    /// Encodes a single value of a `.bN` directive that stores each value in `size` bytes
+    ///
+    /// Produces an error if the value does not fit in that many bytes.
+    pub fn new(size: u8, value: asm::Immediate, diag: &Diagnostics) -> Self {
+        let asm::Integer {value, span} = value;
+
+        // Negative values are stored as two's complement, so the value may be interpreted as either
+        // signed or unsigned
+        let (min_value, max_value) = match size {
+            1 => (i8::MIN as i128, u8::MAX as i128),
+            2 => (i16::MIN as i128, u16::MAX as i128),
+            4 => (i32::MIN as i128, u32::MAX as i128),
+            8 => (i64::MIN as i128, u64::MAX as i128),
+            _ => unreachable!("bug: unexpected size of static bytes: `{}`", size),
+        };
+
+        if value < min_value || value > max_value {
+            diag.span_error(span, format!("value `{}` for `.b{}` must be in the range `{}` to `{}`", value, size, min_value, max_value)).emit();
+        }
+
+        // Error recovery: if an error is produced above, we'll just end up with the result of
+        // `as` when casting with overflow
+        match size {
+            1 => StaticBytes::B1((value as u8).to_le_bytes()),
+            2 => StaticBytes::B2((value as u16).to_le_bytes()),
+            4 => StaticBytes::B4((value as u32).to_le_bytes()),
+            8 => StaticBytes::B8((value as u64).to_le_bytes()),
+            _ => unreachable!("bug: unexpected size of static bytes: `{}`", size),
         }
     }
 }
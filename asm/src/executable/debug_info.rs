@@ -0,0 +1,197 @@
+//! Optional information about the source of an executable, used to produce better runtime errors
+
+use std::sync::Arc;
+use std::ops::Range;
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::asm;
+use crate::parser::SourceFiles;
+
+/// A label and the address it refers to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Symbol {
+    pub name: Arc<str>,
+    pub addr: u64,
+}
+
+/// The position in the source code of the statement starting at a given address
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceLocation {
+    pub addr: u64,
+    /// The size in bytes of the statement
+    pub size: u64,
+    /// An index into `DebugInfo::files`
+    pub file: usize,
+    /// The 1-based line number
+    pub line: usize,
+    /// The 1-based column number
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DebugInfo {
+    /// The path of every source file that contains a statement in the executable
+    pub files: Vec<Arc<str>>,
+    /// Every label in the program, sorted by address
+    pub symbols: Vec<Symbol>,
+    /// The location of every statement in the program, sorted by address
+    pub locations: Vec<SourceLocation>,
+}
+
+impl DebugInfo {
+    /// Records the labels and source locations of the statements in the given program
+    ///
+    /// Must be called before the program is laid out into an executable.
+    pub fn new(prog: &asm::Program, source_files: &SourceFiles) -> Self {
+        let mut files = Vec::new();
+        let mut file_indexes = HashMap::new();
+        let mut symbols = Vec::new();
+        let mut locations = Vec::new();
+        let mut current_offset = 0;
+
+        for stmt in prog.iter_all_stmts() {
+            for label in &stmt.labels {
+                symbols.push(Symbol {name: label.value.clone(), addr: current_offset});
+            }
+
+            let span = stmt.kind.span();
+            let pos = source_files.pos(span);
+            let file = *file_indexes.entry(pos.path).or_insert_with(|| {
+                files.push(pos.path.display().to_string().into());
+                files.len() - 1
+            });
+
+            locations.push(SourceLocation {
+                addr: current_offset,
+                size: stmt.size_bytes(),
+                file,
+                line: pos.start_line,
+                column: pos.start_offset,
+            });
+
+            current_offset += stmt.size_bytes();
+        }
+
+        for label in &prog.end_labels {
+            symbols.push(Symbol {name: label.value.clone(), addr: current_offset});
+        }
+
+        Self {files, symbols, locations}
+    }
+
+    /// Returns the closest label at or before the given address, if any
+    pub fn label_at(&self, addr: u64) -> Option<&Symbol> {
+        // Statements are laid out in order, so the symbols are already sorted by address
+        let index = self.symbols.partition_point(|symbol| symbol.addr <= addr);
+        index.checked_sub(1).map(|index| &self.symbols[index])
+    }
+
+    /// Returns the location of the statement containing the given address, if any
+    pub fn location(&self, addr: u64) -> Option<&SourceLocation> {
+        let index = self.locations.partition_point(|loc| loc.addr <= addr);
+        let loc = &self.locations[index.checked_sub(1)?];
+
+        if addr - loc.addr < loc.size {
+            Some(loc)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the addresses from the label with the given name up to the next label that is not
+    /// one of its local labels (e.g. `main.loop` for `main`), or up to the end of the program
+    ///
+    /// Returns None if there is no label with the given name
+    pub fn label_range(&self, name: &str) -> Option<Range<u64>> {
+        let index = self.symbols.iter().position(|symbol| &*symbol.name == name)?;
+        let start = self.symbols[index].addr;
+
+        let local_prefix = format!("{}.", name);
+        let end = self.symbols[index+1..].iter()
+            .find(|symbol| symbol.addr > start && !symbol.name.starts_with(&local_prefix))
+            .map(|symbol| symbol.addr)
+            .or_else(|| self.locations.last().map(|loc| loc.addr + loc.size))
+            .unwrap_or(start);
+
+        Some(start..end.max(start))
+    }
+
+    /// Returns the path of the file that the given location is in
+    pub fn file(&self, loc: &SourceLocation) -> &str {
+        &self.files[loc.file]
+    }
+
+    /// Formats the address as the source position of the statement at that address, followed by
+    /// the closest label (if any), e.g. "fib.wa:23:3 in `loop`"
+    ///
+    /// Returns None if there is no statement at the given address
+    pub fn describe(&self, addr: u64) -> Option<String> {
+        let loc = self.location(addr)?;
+        let pos = format!("{}:{}:{}", self.file(loc), loc.line, loc.column);
+
+        Some(match self.label_at(addr) {
+            Some(symbol) => format!("{} in `{}`", pos, symbol.name),
+            None => pos,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debug_info() -> DebugInfo {
+        let location = |addr, line| SourceLocation {addr, size: 8, file: 0, line, column: 3};
+        DebugInfo {
+            files: vec!["fib.wa".into()],
+            symbols: vec![
+                Symbol {name: "main".into(), addr: 8},
+                Symbol {name: "loop".into(), addr: 24},
+            ],
+            locations: vec![location(0, 2), location(8, 5), location(16, 6), location(24, 9)],
+        }
+    }
+
+    #[test]
+    fn label_lookup() {
+        let info = debug_info();
+        assert_eq!(info.label_at(0), None);
+        assert_eq!(info.label_at(8).map(|symbol| &*symbol.name), Some("main"));
+        assert_eq!(info.label_at(16).map(|symbol| &*symbol.name), Some("main"));
+        assert_eq!(info.label_at(24).map(|symbol| &*symbol.name), Some("loop"));
+        assert_eq!(info.label_at(u64::MAX).map(|symbol| &*symbol.name), Some("loop"));
+    }
+
+    #[test]
+    fn label_range_lookup() {
+        let info = debug_info();
+        assert_eq!(info.label_range("main"), Some(8..24));
+        assert_eq!(info.label_range("loop"), Some(24..32));
+        assert_eq!(info.label_range("missing"), None);
+
+        // Local labels are part of the range of the label they are scoped to
+        let mut info = debug_info();
+        info.symbols.insert(1, Symbol {name: "main.inner".into(), addr: 16});
+        assert_eq!(info.label_range("main"), Some(8..24));
+        assert_eq!(info.label_range("main.inner"), Some(16..24));
+    }
+
+    #[test]
+    fn location_lookup() {
+        let info = debug_info();
+        assert_eq!(info.location(0).map(|loc| loc.line), Some(2));
+        assert_eq!(info.location(12).map(|loc| loc.line), Some(5));
+        assert_eq!(info.location(24).map(|loc| loc.line), Some(9));
+
+        assert_eq!(info.describe(0).as_deref(), Some("fib.wa:2:3"));
+        assert_eq!(info.describe(16).as_deref(), Some("fib.wa:6:3 in `main`"));
+        assert_eq!(info.describe(31).as_deref(), Some("fib.wa:9:3 in `loop`"));
+        // Past the end of the program
+        assert_eq!(info.describe(32), None);
+
+        let empty = DebugInfo {files: Vec::new(), symbols: Vec::new(), locations: Vec::new()};
+        assert_eq!(empty.describe(0), None);
+    }
+}
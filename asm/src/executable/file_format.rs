@@ -0,0 +1,342 @@
+//! The on-disk container format for executables
+//!
+//! An executable file starts with a fixed size header, followed by a table describing each section
+//! in the file, followed by the contents of each section. All integers are little-endian.
+//!
+//! ```text
+//! offset  size  field
+//!      0     4  magic number: the bytes `WOLF`
+//!      4     2  format version (see `FORMAT_VERSION`)
+//!      6     2  flags (see `FileFlags`)
+//!      8     8  entry point: offset of the first instruction from the start of the program
+//!     16     4  number of entries in the section table
+//!     20     4  reserved (must be zero)
+//!     24        section table
+//! ```
+//!
+//! Each section table entry is 24 bytes:
+//!
+//! ```text
+//! offset  size  field
+//!      0     4  section kind (see `SectionKind`)
+//!      4     4  reserved (must be zero)
+//!      8     8  offset of the section contents from the start of the file
+//!     16     8  size of the section contents in bytes
+//! ```
+//!
+//! The code and static sections are required. Sections with an unknown kind are skipped so that
+//! optional sections can be added without breaking older readers. The contents of each section
+//! are encoded with `bincode`, so any change to `Stmt` or `DebugInfo` must increment the format
+//! version.
+
+use std::io::{self, Read, Write};
+use std::convert::TryInto;
+use std::fmt;
+
+use thiserror::Error;
+
+use super::Executable;
+
+/// The first four bytes of every executable file
+pub const MAGIC: [u8; 4] = *b"WOLF";
+
+/// The version of the format written by this version of the assembler
+///
+/// Only executables with exactly this version can be read.
+pub const FORMAT_VERSION: u16 = 1;
+
+const HEADER_SIZE: usize = 24;
+const SECTION_ENTRY_SIZE: usize = 24;
+
+/// Flags stored in the header of the executable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileFlags(u16);
+
+impl FileFlags {
+    /// The executable contains a debug info section
+    pub const DEBUG_INFO: u16 = 1 << 0;
+
+    /// All of the flags supported by this version of the format
+    const SUPPORTED: u16 = Self::DEBUG_INFO;
+
+    pub fn contains(self, flag: u16) -> bool {
+        self.0 & flag == flag
+    }
+}
+
+/// The kind of data stored in a section of the executable file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    Code = 1,
+    Static = 2,
+    DebugInfo = 3,
+}
+
+impl SectionKind {
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            1 => Some(SectionKind::Code),
+            2 => Some(SectionKind::Static),
+            3 => Some(SectionKind::DebugInfo),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SectionKind::*;
+        match self {
+            Code => write!(f, "code"),
+            Static => write!(f, "static"),
+            DebugInfo => write!(f, "debug info"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error(transparent)]
+    IOError(#[from] io::Error),
+    #[error("Not a wolf executable (missing magic number)")]
+    InvalidMagic,
+    #[error("Executable has format version {found}, but only version {expected} is supported. Try assembling the program again.")]
+    UnsupportedVersion {found: u16, expected: u16},
+    #[error("Executable has unsupported flags `0x{0:04x}`")]
+    UnsupportedFlags(u16),
+    #[error("Executable is truncated: expected at least {0} bytes")]
+    Truncated(u64),
+    #[error("Executable is missing its {0} section")]
+    MissingSection(SectionKind),
+    #[error("Executable has more than one {0} section")]
+    DuplicateSection(SectionKind),
+    #[error("Executable has a {0} section that was not declared in its flags")]
+    UnexpectedSection(SectionKind),
+    #[error("Executable has an invalid {kind} section: {source}")]
+    InvalidSection {kind: SectionKind, source: bincode::Error},
+}
+
+impl Executable {
+    /// Writes the executable in the format described in this module
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let Executable {code_section, static_section, debug_info, entry} = self;
+
+        let mut sections = vec![
+            (SectionKind::Code, encode(code_section)),
+            (SectionKind::Static, encode(static_section)),
+        ];
+        let mut flags = 0;
+        if let Some(debug_info) = debug_info {
+            sections.push((SectionKind::DebugInfo, encode(debug_info)));
+            flags |= FileFlags::DEBUG_INFO;
+        }
+
+        let mut header = Vec::with_capacity(HEADER_SIZE + SECTION_ENTRY_SIZE * sections.len());
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header.extend_from_slice(&flags.to_le_bytes());
+        header.extend_from_slice(&entry.to_le_bytes());
+        header.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+
+        let mut offset = (HEADER_SIZE + SECTION_ENTRY_SIZE * sections.len()) as u64;
+        for (kind, contents) in &sections {
+            header.extend_from_slice(&(*kind as u32).to_le_bytes());
+            header.extend_from_slice(&0u32.to_le_bytes());
+            header.extend_from_slice(&offset.to_le_bytes());
+            header.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+            offset += contents.len() as u64;
+        }
+
+        writer.write_all(&header)?;
+        for (_, contents) in &sections {
+            writer.write_all(contents)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads an executable in the format described in this module
+    pub fn read_from(mut reader: impl Read) -> Result<Self, ReadError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Decodes an executable in the format described in this module
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReadError> {
+        // Check the magic number before anything else so that random files get a clear error
+        if bytes.get(..MAGIC.len()) != Some(&MAGIC[..]) {
+            return Err(ReadError::InvalidMagic);
+        }
+
+        let header = slice(bytes, 0, HEADER_SIZE as u64)?;
+        let version = u16::from_le_bytes(header[4..6].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(ReadError::UnsupportedVersion {found: version, expected: FORMAT_VERSION});
+        }
+
+        let flags = u16::from_le_bytes(header[6..8].try_into().unwrap());
+        if flags & !FileFlags::SUPPORTED != 0 {
+            return Err(ReadError::UnsupportedFlags(flags));
+        }
+        let flags = FileFlags(flags);
+
+        let entry = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let nsections = u32::from_le_bytes(header[16..20].try_into().unwrap()) as u64;
+
+        let table = slice(bytes, HEADER_SIZE as u64, nsections * SECTION_ENTRY_SIZE as u64)?;
+        let mut code_section = None;
+        let mut static_section = None;
+        let mut debug_info = None;
+        for section in table.chunks_exact(SECTION_ENTRY_SIZE) {
+            let kind = u32::from_le_bytes(section[0..4].try_into().unwrap());
+            let offset = u64::from_le_bytes(section[8..16].try_into().unwrap());
+            let size = u64::from_le_bytes(section[16..24].try_into().unwrap());
+
+            let kind = match SectionKind::from_u32(kind) {
+                Some(kind) => kind,
+                // Skip sections added by newer versions of the format
+                None => continue,
+            };
+            let contents = slice(bytes, offset, size)?;
+
+            match kind {
+                SectionKind::Code => decode_section(kind, contents, &mut code_section)?,
+                SectionKind::Static => decode_section(kind, contents, &mut static_section)?,
+                SectionKind::DebugInfo => {
+                    if !flags.contains(FileFlags::DEBUG_INFO) {
+                        return Err(ReadError::UnexpectedSection(kind));
+                    }
+                    decode_section(kind, contents, &mut debug_info)?
+                },
+            }
+        }
+
+        if flags.contains(FileFlags::DEBUG_INFO) && debug_info.is_none() {
+            return Err(ReadError::MissingSection(SectionKind::DebugInfo));
+        }
+
+        Ok(Self {
+            code_section: code_section.ok_or(ReadError::MissingSection(SectionKind::Code))?,
+            static_section: static_section.ok_or(ReadError::MissingSection(SectionKind::Static))?,
+            debug_info,
+            entry,
+        })
+    }
+}
+
+fn encode<T: serde::Serialize>(value: &T) -> Vec<u8> {
+    bincode::serialize(value).expect("bug: executable sections should always be serializable")
+}
+
+/// Returns `size` bytes starting at `offset`, or an error if the file is too short
+fn slice(bytes: &[u8], offset: u64, size: u64) -> Result<&[u8], ReadError> {
+    let end = offset.checked_add(size).ok_or(ReadError::Truncated(u64::MAX))?;
+    if end > bytes.len() as u64 {
+        return Err(ReadError::Truncated(end));
+    }
+
+    Ok(&bytes[offset as usize..end as usize])
+}
+
+fn decode_section<'a, T: serde::Deserialize<'a>>(
+    kind: SectionKind,
+    contents: &'a [u8],
+    section: &mut Option<T>,
+) -> Result<(), ReadError> {
+    if section.is_some() {
+        return Err(ReadError::DuplicateSection(kind));
+    }
+
+    let value = bincode::deserialize(contents)
+        .map_err(|source| ReadError::InvalidSection {kind, source})?;
+    *section = Some(value);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::executable::{Stmt, StaticData, StaticBytes, StaticUninit, DebugInfo, Symbol};
+
+    fn executable() -> Executable {
+        Executable {
+            code_section: Vec::new(),
+            static_section: vec![
+                Stmt::StaticData(StaticData::StaticBytes(StaticBytes::B2([1, 2]))),
+                Stmt::StaticData(StaticData::StaticUninit(StaticUninit {nbytes: 3})),
+            ],
+            debug_info: Some(DebugInfo {
+                files: Vec::new(),
+                symbols: vec![Symbol {name: "msg".into(), addr: 0}],
+                locations: Vec::new(),
+            }),
+            entry: 16,
+        }
+    }
+
+    fn to_bytes(exec: &Executable) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        exec.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let exec = executable();
+        let bytes = to_bytes(&exec);
+        assert_eq!(&bytes[..4], b"WOLF");
+        assert_eq!(Executable::from_bytes(&bytes).unwrap(), exec);
+
+        let exec = Executable {debug_info: None, ..exec};
+        assert_eq!(Executable::read_from(&to_bytes(&exec)[..]).unwrap(), exec);
+    }
+
+    #[test]
+    fn invalid_header() {
+        let bytes = to_bytes(&executable());
+
+        assert!(matches!(Executable::from_bytes(b""), Err(ReadError::InvalidMagic)));
+        assert!(matches!(Executable::from_bytes(b"\x7fELF\x02\x01\x01"), Err(ReadError::InvalidMagic)));
+        assert!(matches!(Executable::from_bytes(&bytes[..10]), Err(ReadError::Truncated(24))));
+        assert!(matches!(Executable::from_bytes(&bytes[..bytes.len()-1]), Err(ReadError::Truncated(_))));
+
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        let err = Executable::from_bytes(&newer).unwrap_err();
+        assert!(matches!(err, ReadError::UnsupportedVersion {found: 2, expected: 1}));
+        assert_eq!(err.to_string(), "Executable has format version 2, but only version 1 is supported. Try assembling the program again.");
+
+        let mut flags = bytes.clone();
+        flags[6] = 0x80;
+        assert!(matches!(Executable::from_bytes(&flags), Err(ReadError::UnsupportedFlags(0x80))));
+    }
+
+    #[test]
+    fn section_table() {
+        let bytes = to_bytes(&executable());
+        // Offset of the kind field of the section table entry for the static section
+        let static_kind = HEADER_SIZE + SECTION_ENTRY_SIZE;
+
+        // Unknown sections are skipped
+        let mut unknown = bytes.clone();
+        unknown[static_kind] = 99;
+        assert!(matches!(Executable::from_bytes(&unknown), Err(ReadError::MissingSection(SectionKind::Static))));
+
+        let mut duplicate = bytes.clone();
+        duplicate[static_kind] = SectionKind::Code as u8;
+        assert!(matches!(Executable::from_bytes(&duplicate), Err(ReadError::DuplicateSection(SectionKind::Code))));
+
+        let mut no_flags = bytes.clone();
+        no_flags[6] = 0;
+        assert!(matches!(Executable::from_bytes(&no_flags), Err(ReadError::UnexpectedSection(SectionKind::DebugInfo))));
+
+        let mut garbage = bytes;
+        let len = garbage.len();
+        garbage[len-1] = 0xff;
+        assert!(matches!(Executable::from_bytes(&garbage), Err(ReadError::InvalidSection {kind: SectionKind::DebugInfo, ..})));
+    }
+}
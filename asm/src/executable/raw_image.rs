@@ -0,0 +1,78 @@
+//! Converts an executable into the exact bytes that are loaded into memory
+
+use super::{Executable, Stmt, StaticData, StaticBytes, StaticZero, StaticUninit, StaticByteStr};
+
+impl Executable {
+    /// Returns the bytes of the code section followed by the bytes of the static section, exactly
+    /// as they are laid out in memory when the executable is loaded at address 0
+    ///
+    /// Uninitialized data is filled with zeros. The debug info and entry point are not included.
+    pub fn to_raw_image(&self) -> Vec<u8> {
+        let Executable {code_section, static_section, debug_info: _, entry: _} = self;
+
+        let mut image = Vec::new();
+        for stmt in code_section.iter().chain(static_section) {
+            stmt.write_bytes(&mut image);
+        }
+
+        image
+    }
+}
+
+impl Stmt {
+    /// Appends the bytes of this statement to the given buffer
+    pub fn write_bytes(&self, out: &mut Vec<u8>) {
+        match self {
+            Stmt::Instr(instr) => out.extend_from_slice(&instr.to_binary().to_le_bytes()),
+            Stmt::StaticData(data) => data.write_bytes(out),
+        }
+    }
+}
+
+impl StaticData {
+    /// Appends the bytes of this data to the given buffer
+    pub fn write_bytes(&self, out: &mut Vec<u8>) {
+        match self {
+            StaticData::StaticBytes(data) => out.extend_from_slice(data.as_bytes()),
+            StaticData::StaticZero(StaticZero {nbytes}) |
+            StaticData::StaticUninit(StaticUninit {nbytes}) => out.resize(out.len() + *nbytes as usize, 0),
+            StaticData::StaticByteStr(StaticByteStr {bytes}) => out.extend_from_slice(bytes),
+        }
+    }
+}
+
+impl StaticBytes {
+    /// Returns the bytes of the value in little-endian byte order
+    pub fn as_bytes(&self) -> &[u8] {
+        use StaticBytes::*;
+        match self {
+            B1(bytes) => bytes,
+            B2(bytes) => bytes,
+            B4(bytes) => bytes,
+            B8(bytes) => bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_data_bytes() {
+        let exec = Executable {
+            code_section: Vec::new(),
+            static_section: vec![
+                Stmt::StaticData(StaticData::StaticBytes(StaticBytes::B2([0x34, 0x12]))),
+                Stmt::StaticData(StaticData::StaticByteStr(StaticByteStr {bytes: b"hi"[..].into()})),
+                Stmt::StaticData(StaticData::StaticZero(StaticZero {nbytes: 2})),
+                Stmt::StaticData(StaticData::StaticUninit(StaticUninit {nbytes: 3})),
+                Stmt::StaticData(StaticData::StaticBytes(StaticBytes::B1([0xff]))),
+            ],
+            debug_info: None,
+            entry: 0,
+        };
+
+        assert_eq!(exec.to_raw_image(), b"\x34\x12hi\0\0\0\0\0\xff");
+    }
+}
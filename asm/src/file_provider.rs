@@ -0,0 +1,52 @@
+//! Where the assembler reads source files from
+//!
+//! The `wolf-asm` binary reads every file from disk, but programs that embed the assembler can
+//! keep their source code in memory instead.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+/// Provides the contents of the source files being assembled, including any `.include`d files
+pub trait FileProvider {
+    /// Returns the contents of the file at the given path
+    ///
+    /// Included paths are joined to the directory of the file that includes them before being
+    /// passed to this method, so `.include "lib/io.wa"` in `src/main.wa` reads `src/lib/io.wa`.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+}
+
+/// Reads files from the file system
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FsFileProvider;
+
+impl FileProvider for FsFileProvider {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+}
+
+/// Provides files from memory, without ever accessing the file system
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MemoryFileProvider {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFileProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file with the given path and contents, replacing any file with the same path
+    pub fn insert(&mut self, path: impl Into<PathBuf>, source: impl Into<Vec<u8>>) {
+        self.files.insert(path.into(), source.into());
+    }
+}
+
+impl FileProvider for MemoryFileProvider {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files.get(path).cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "file not found"))
+    }
+}
@@ -9,8 +9,9 @@ use parking_lot::RwLock;
 use crate::ast;
 use crate::parser::{SourceFiles, collect_tokens, parse_program};
 use crate::diagnostics::Diagnostics;
+use crate::file_provider::FileProvider;
 
-/// Attempts to expand all `.include` directives in a program
+/// Attempts to expand all `.include` directives in a program, reading included files from `files`
 ///
 /// Recurses up to `depth` times, after which an error will be produced if all `.include`
 /// directives have not been resolved.
@@ -24,6 +25,7 @@ pub fn expand_includes(
     prog_path: &Path,
     prog: ast::Program,
     source_files: &Arc<RwLock<SourceFiles>>,
+    files: &dyn FileProvider,
     diag: &Diagnostics,
     depth: usize,
 ) -> ast::Program {
@@ -31,13 +33,14 @@ pub fn expand_includes(
     // Since we know the maximum number of items that can be added, let's allocate immediately
     path_stack.reserve_exact(depth+1);
 
-    expand_includes_impl(prog_path, prog, source_files, diag, depth, &mut path_stack)
+    expand_includes_impl(prog_path, prog, source_files, files, diag, depth, &mut path_stack)
 }
 
 fn expand_includes_impl(
     prog_path: &Path,
     prog: ast::Program,
     source_files: &Arc<RwLock<SourceFiles>>,
+    files: &dyn FileProvider,
     diag: &Diagnostics,
     depth: usize,
     path_stack: &mut Vec<PathBuf>,
@@ -100,10 +103,8 @@ fn expand_includes_impl(
             Cow::Borrowed(included_path)
         };
 
-        // Need this separate variable so that the lock on source files ends before diag.span_error()
-        let included_file = source_files.write().add_file(&included_path);
-        let included_file = match included_file {
-            Ok(file_handle) => file_handle,
+        let included_file = match files.read(&included_path) {
+            Ok(source) => source_files.write().add_source(&included_path, &source),
             Err(err) => {
                 diag.span_error(path_span, format!("unable to read included source file: `{}`: {}", included_path.display(), err)).emit();
                 // Finish this pass before stopping in case there are further errors
@@ -129,6 +130,7 @@ fn expand_includes_impl(
             &included_path,
             included_prog,
             source_files,
+            files,
             diag,
             depth-1,
             path_stack,
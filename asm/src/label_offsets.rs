@@ -1,39 +1,84 @@
+use std::sync::Arc;
 use std::collections::HashMap;
 
+use crate::ast;
 use crate::asm;
 use crate::diagnostics::Diagnostics;
+use crate::const_expr::eval_expr;
+use crate::local_labels::{Direction, is_numeric_label, numeric_label_ref};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct LabelOffsets {
     offsets: HashMap<asm::Ident, u64>,
+    /// The addresses of every definition of each numeric label, in ascending order
+    numeric: HashMap<Arc<str>, Vec<u64>>,
+    /// The address that the program is loaded at
+    base_addr: u64,
 }
 
 impl LabelOffsets {
+    /// Computes the address of every label, assuming the program is loaded at address 0
     pub fn new(prog: &asm::Program) -> Self {
-        let mut offsets = HashMap::new();
-        let mut current_offset = 0;
+        Self::with_base_addr(prog, 0)
+    }
 
+    /// Computes the address of every label, assuming the program is loaded at `base_addr`
+    pub fn with_base_addr(prog: &asm::Program, base_addr: u64) -> Self {
+        let mut labels = Vec::new();
+        let mut current_offset = base_addr;
         for stmt in prog.iter_all_stmts() {
             for label in &stmt.labels {
-                offsets.insert(label.clone(), current_offset);
+                labels.push((label, current_offset));
             }
 
             current_offset += stmt.size_bytes();
         }
 
-        Self {offsets}
+        for label in &prog.end_labels {
+            labels.push((label, current_offset));
+        }
+
+        Self::from_addrs(labels, base_addr)
+    }
+
+    /// Creates a table from the address of each label, for a program loaded at `base_addr`
+    ///
+    /// The definitions of each numeric label must be given in ascending order of address.
+    pub fn from_addrs<'a>(labels: impl IntoIterator<Item=(&'a asm::Ident, u64)>, base_addr: u64) -> Self {
+        let mut offsets = HashMap::new();
+        let mut numeric: HashMap<_, Vec<_>> = HashMap::new();
+        for (label, addr) in labels {
+            if is_numeric_label(&label.value) {
+                numeric.entry(label.value.clone()).or_default().push(addr);
+            } else {
+                offsets.insert(label.clone(), addr);
+            }
+        }
+
+        Self {offsets, numeric, base_addr}
+    }
+
+    /// Returns the address that the program is loaded at
+    pub fn base_addr(&self) -> u64 {
+        self.base_addr
     }
 
     /// Looks up a label name and returns the immediate value of its offset
-    pub fn lookup(&self, name: &asm::Ident, diag: &Diagnostics) -> asm::Immediate {
+    ///
+    /// References to numeric labels (e.g. `main.1b`) are resolved relative to `addr`, the address
+    /// of the statement containing the reference.
+    pub fn lookup(&self, name: &asm::Ident, addr: u64, diag: &Diagnostics) -> asm::Immediate {
         let value = match self.offsets.get(name).copied() {
             Some(value) => value,
-            None => {
-                diag.span_error(name.span, format!("unknown label `{}`", name)).emit();
+            None => match numeric_label_ref(&name.value) {
+                Some((label, dir)) => self.lookup_numeric(name, label, dir, addr, diag),
+                None => {
+                    diag.span_error(name.span, format!("unknown label `{}`", name)).emit();
 
-                // Error Recovery: default to zero if the label isn't found so we can keep checking
-                // for more errors
-                0
+                    // Error Recovery: default to zero if the label isn't found so we can keep
+                    // checking for more errors
+                    0
+                },
             },
         };
 
@@ -46,4 +91,48 @@ impl LabelOffsets {
             span: name.span,
         }
     }
+
+    /// Finds the nearest definition of the numeric label `label` in the given direction from
+    /// `addr`
+    fn lookup_numeric(&self, name: &asm::Ident, label: &str, dir: Direction, addr: u64, diag: &Diagnostics) -> u64 {
+        let offsets = self.numeric.get(label).map(|offsets| &offsets[..]).unwrap_or(&[]);
+        // A label on the statement containing the reference counts as being before it
+        let value = match dir {
+            Direction::Backward => offsets.iter().rev().find(|&&offset| offset <= addr),
+            Direction::Forward => offsets.iter().find(|&&offset| offset > addr),
+        };
+
+        match value.copied() {
+            Some(value) => value,
+            None => {
+                let position = match dir {
+                    Direction::Backward => "before",
+                    Direction::Forward => "after",
+                };
+                diag.span_error(name.span, format!("unknown label `{}`: there is no `{}` label {} this reference", name, label, position))
+                    .emit();
+
+                // Error Recovery: default to zero if the label isn't found so we can keep checking
+                // for more errors
+                0
+            },
+        }
+    }
+
+    /// Evaluates an expression that may refer to labels
+    ///
+    /// The location counter (`.`) evaluates to `addr`, the address of the statement containing
+    /// the expression.
+    pub fn eval(&self, expr: &asm::Expr, addr: u64, diag: &Diagnostics) -> asm::Immediate {
+        let value = eval_expr(expr, diag, &mut |expr| match &expr.kind {
+            ast::ExprKind::Name(name) => self.lookup(name, addr, diag).value,
+            ast::ExprKind::Here => addr as i128,
+            _ => unreachable!("bug: only names and `.` should need to be looked up"),
+        });
+
+        asm::Integer {
+            value,
+            span: expr.span,
+        }
+    }
 }
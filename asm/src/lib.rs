@@ -3,9 +3,21 @@
 pub mod diagnostics;
 pub mod ast;
 pub mod parser;
+pub mod file_provider;
 pub mod include_expansion;
+pub mod conditional_assembly;
+pub mod macro_expansion;
+pub mod local_labels;
 pub mod asm;
+pub mod const_expr;
 pub mod const_table;
 pub mod validate;
 pub mod label_offsets;
 pub mod executable;
+pub mod object;
+pub mod linker;
+pub mod listing;
+pub mod assembler;
+pub mod cli;
+
+pub use assembler::{assemble, AssembleOptions, Assembled};
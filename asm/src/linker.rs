@@ -0,0 +1,272 @@
+//! Combines object files into a single executable
+
+use std::mem;
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+
+use parking_lot::RwLock;
+
+use crate::asm;
+use crate::parser::{Span, SourceFiles};
+use crate::diagnostics::Diagnostics;
+use crate::label_offsets::LabelOffsets;
+use crate::object::{Object, Section, Symbol, Relocation};
+use crate::executable::{self, Executable, Stmt, StaticData, StaticBytes, DebugInfo, SourceLocation};
+
+/// The addresses that the sections of an object file are placed at
+#[derive(Debug, Clone, Copy)]
+struct SectionAddrs {
+    code: u64,
+    static_data: u64,
+}
+
+impl SectionAddrs {
+    /// Returns the address of the given offset from the start of a section
+    fn addr(self, section: Section, offset: u64) -> u64 {
+        match section {
+            Section::Code => self.code + offset,
+            Section::Static => self.static_data + offset,
+        }
+    }
+}
+
+/// Links the given object files into an executable that will be loaded at `load_addr`
+///
+/// The code sections of every object file are placed first, in the order given, followed by all
+/// of the static sections. Labels may only be used outside of the object file that defines them
+/// if they are declared with `.global`. Each `.extern` label is resolved to the `.global` label
+/// with the same name.
+///
+/// The source files of each object are appended to `source_files` so that errors can refer to
+/// them. The debug info is only included if every object file has debug info.
+pub fn link_objects(
+    mut objects: Vec<Object>,
+    source_files: &Arc<RwLock<SourceFiles>>,
+    diag: &Diagnostics,
+    load_addr: u64,
+) -> Executable {
+    for object in &mut objects {
+        let object_files = mem::take(&mut object.source_files);
+        let offset = source_files.write().append(object_files);
+        object.shift_spans(offset);
+    }
+
+    let mut next_addr = load_addr;
+    let mut section_addrs: Vec<_> = objects.iter().map(|object| {
+        let code = next_addr;
+        next_addr += section_size(&object.code_section);
+        SectionAddrs {code, static_data: 0}
+    }).collect();
+    for (object, addrs) in objects.iter().zip(&mut section_addrs) {
+        addrs.static_data = next_addr;
+        next_addr += section_size(&object.static_section);
+    }
+
+    let globals = global_labels(&objects, &section_addrs, diag);
+    let entry = find_entry(&objects, &section_addrs, load_addr, diag);
+    let debug_info = link_debug_info(&mut objects, &section_addrs, load_addr, diag);
+
+    // Labels that are defined but not declared `.global`, used to produce better errors
+    let local_labels: HashSet<_> = objects.iter()
+        .flat_map(|object| &object.symbols)
+        .filter(|symbol| !symbol.global)
+        .map(|symbol| symbol.label.clone())
+        .collect();
+
+    let mut code_section = Vec::new();
+    let mut static_section = Vec::new();
+    for (object, addrs) in objects.into_iter().zip(section_addrs) {
+        let Object {
+            code_section: mut object_code,
+            static_section: mut object_static,
+            symbols,
+            externs,
+            relocations,
+            entry: _,
+            debug_info: _,
+            source_files: _,
+        } = object;
+
+        let mut labels: Vec<_> = symbols.iter()
+            .map(|symbol| (&symbol.label, addrs.addr(symbol.section, symbol.offset)))
+            .collect();
+        for label in &externs {
+            let addr = match globals.get(label) {
+                Some(&addr) => addr,
+                None => {
+                    undefined_extern(label, &relocations, &local_labels, diag);
+                    // Error recovery: default to zero so we can keep checking for more errors
+                    0
+                },
+            };
+            labels.push((label, addr));
+        }
+        let labels = LabelOffsets::from_addrs(labels, load_addr);
+
+        let code_addrs = stmt_addrs(&object_code, addrs.code);
+        let static_addrs = stmt_addrs(&object_static, addrs.static_data);
+        for Relocation {section, stmt, imm, expr} in relocations {
+            let (stmts, stmt_addrs) = match section {
+                Section::Code => (&mut object_code, &code_addrs),
+                Section::Static => (&mut object_static, &static_addrs),
+            };
+
+            let value = labels.eval(&expr, stmt_addrs[stmt], diag);
+            match &mut stmts[stmt] {
+                Stmt::Instr(instr) => instr.layout.set_immediate(imm, value, diag),
+                Stmt::StaticData(StaticData::StaticBytes(data)) => {
+                    let size = data.as_bytes().len() as u8;
+                    *data = StaticBytes::new(size, value, diag);
+                },
+                Stmt::StaticData(_) => unreachable!("bug: only instructions and `.bN` values can be relocated"),
+            }
+        }
+
+        code_section.extend(object_code);
+        static_section.extend(object_static);
+    }
+
+    Executable {code_section, static_section, debug_info, entry}
+}
+
+/// Returns the address of every `.global` label, producing an error for any label that is
+/// declared `.global` in more than one object file
+fn global_labels(objects: &[Object], section_addrs: &[SectionAddrs], diag: &Diagnostics) -> HashMap<asm::Ident, u64> {
+    let mut globals: HashMap<asm::Ident, u64> = HashMap::new();
+
+    for (object, addrs) in objects.iter().zip(section_addrs) {
+        for symbol in object.symbols.iter().filter(|symbol| symbol.global) {
+            let Symbol {label, section, offset, global: _} = symbol;
+            match globals.get_key_value(label) {
+                Some((other_label, _)) => {
+                    diag.span_error(label.span, format!("duplicate global label `{}`", label))
+                        .span_note(other_label.span, "originally defined here")
+                        .emit();
+                },
+                None => {
+                    globals.insert(label.clone(), addrs.addr(*section, *offset));
+                },
+            }
+        }
+    }
+
+    globals
+}
+
+/// Returns the offset of the first instruction to execute from the start of the program
+///
+/// At most one object file may have an entry point. If none do, execution starts at the
+/// beginning of the program.
+fn find_entry(objects: &[Object], section_addrs: &[SectionAddrs], load_addr: u64, diag: &Diagnostics) -> u64 {
+    let mut entry: Option<(&asm::Ident, u64)> = None;
+
+    let entries = objects.iter().zip(section_addrs)
+        .filter_map(|(object, addrs)| Some((object.entry.as_ref()?, addrs)));
+    for (object_entry, addrs) in entries {
+        match entry {
+            Some((other_label, _)) => {
+                diag.span_error(object_entry.label.span, format!("duplicate entry point `{}`", object_entry.label))
+                    .span_note(other_label.span, format!("entry point `{}` defined here", other_label))
+                    .emit();
+            },
+            None => entry = Some((&object_entry.label, addrs.code + object_entry.offset - load_addr)),
+        }
+    }
+
+    entry.map(|(_, offset)| offset).unwrap_or(0)
+}
+
+/// Produces an error for an `.extern` label that is not declared `.global` in any object file
+///
+/// Nothing is produced if the label is never used.
+fn undefined_extern(label: &asm::Ident, relocations: &[Relocation], local_labels: &HashSet<asm::Ident>, diag: &Diagnostics) {
+    let mut first_use: Option<Span> = None;
+    for reloc in relocations {
+        reloc.expr.for_each_name(&mut |name| if name == label && first_use.is_none() {
+            first_use = Some(name.span);
+        });
+    }
+
+    let first_use = match first_use {
+        Some(span) => span,
+        None => return,
+    };
+
+    let error = diag.span_error(first_use, format!("undefined label `{}`", label));
+    match local_labels.get(label) {
+        Some(local_label) => error.span_note(label.span, "declared `.extern` here")
+            .span_help(local_label.span, "a label with this name is defined here, but it is not declared `.global`")
+            .emit(),
+        None => error.span_note(label.span, "declared `.extern` here, but no object file declares this label `.global`")
+            .emit(),
+    }
+}
+
+/// Merges the debug info of every object file, if every object file has debug info
+fn link_debug_info(
+    objects: &mut [Object],
+    section_addrs: &[SectionAddrs],
+    load_addr: u64,
+    diag: &Diagnostics,
+) -> Option<DebugInfo> {
+    let with_debug_info = objects.iter().filter(|object| object.debug_info.is_some()).count();
+    if with_debug_info == 0 {
+        return None;
+    } else if with_debug_info < objects.len() {
+        diag.warning("debug info is only included if every object file was assembled with `-g`").emit();
+        return None;
+    }
+
+    let mut files = Vec::new();
+    let mut file_indexes = HashMap::new();
+    let mut symbols = Vec::new();
+    let mut locations = Vec::new();
+    for (object, addrs) in objects.iter_mut().zip(section_addrs) {
+        let object_info = object.debug_info.take().unwrap();
+        let code_size = section_size(&object.code_section);
+
+        let object_files: Vec<_> = object_info.files.into_iter().map(|file| {
+            *file_indexes.entry(file.clone()).or_insert_with(|| {
+                files.push(file);
+                files.len() - 1
+            })
+        }).collect();
+
+        // Addresses in the debug info are offsets from the start of the program
+        symbols.extend(object.symbols.iter().map(|symbol| executable::Symbol {
+            name: symbol.label.value.clone(),
+            addr: addrs.addr(symbol.section, symbol.offset) - load_addr,
+        }));
+
+        locations.extend(object_info.locations.into_iter().map(|loc| {
+            // The static section of each object file comes right after its code section
+            let addr = if loc.addr < code_size {
+                addrs.addr(Section::Code, loc.addr)
+            } else {
+                addrs.addr(Section::Static, loc.addr - code_size)
+            };
+
+            SourceLocation {addr: addr - load_addr, file: object_files[loc.file], ..loc}
+        }));
+    }
+
+    // Stable sort so labels for the same address stay in the order they were defined
+    symbols.sort_by_key(|symbol| symbol.addr);
+    locations.sort_by_key(|loc| loc.addr);
+
+    Some(DebugInfo {files, symbols, locations})
+}
+
+/// Returns the total size in bytes of the given statements
+fn section_size(stmts: &[Stmt]) -> u64 {
+    stmts.iter().map(|stmt| stmt.size_bytes()).sum()
+}
+
+/// Returns the address of each statement, given the address of the first statement
+fn stmt_addrs(stmts: &[Stmt], mut addr: u64) -> Vec<u64> {
+    stmts.iter().map(|stmt| {
+        let stmt_addr = addr;
+        addr += stmt.size_bytes();
+        stmt_addr
+    }).collect()
+}
@@ -0,0 +1,419 @@
+//! A human readable listing of an assembled program
+//!
+//! The listing shows the address and encoding of every statement next to the line of source code
+//! that produced it, followed by a table of every label and constant in the program. For example:
+//!
+//! ```text
+//! ADDRESS   ENCODING                  LINE  SOURCE
+//! ; test.wa
+//!                                        1  section .code
+//! 00000100                               3  main:
+//! 00000100  00d0400000000008             4      add $1, SIZE
+//! ```
+//!
+//! Lines from a macro expansion show the line of the macro body that they were copied from,
+//! marked with a `+` (e.g. `+17`). Lines repeated by `.rept` are marked the same way.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+
+use crate::asm;
+use crate::parser::{Span, SourceFiles};
+use crate::label_offsets::LabelOffsets;
+use crate::executable::{Executable, Stmt, StaticData, StaticZero, StaticUninit};
+
+/// The number of data bytes shown on each line of the listing
+const BYTES_PER_LINE: usize = 8;
+/// The width of the encoding column, enough for `BYTES_PER_LINE` bytes separated by spaces
+const ENCODING_WIDTH: usize = BYTES_PER_LINE * 3 - 1;
+
+/// A line of source code in the listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    /// The address of the label or statement on this line, if any
+    addr: Option<u64>,
+    /// The size in bytes of the statement on this line, if any
+    size: Option<u64>,
+    path: PathBuf,
+    /// The 1-based line number
+    line: usize,
+    /// True if the line is part of a macro expansion or is repeated by `.rept`
+    expanded: bool,
+    source: String,
+}
+
+/// A label and the address it refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symbol {
+    name: String,
+    addr: u64,
+    global: bool,
+}
+
+/// A constant and where it was defined
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Constant {
+    name: String,
+    /// The value of the constant as it is written in the source, with any other constants
+    /// substituted
+    ///
+    /// The value is not evaluated since it may refer to labels or to the location counter (`.`).
+    value: String,
+    path: PathBuf,
+    line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    /// The path of the file that was assembled, used to mark lines from included files
+    root_path: PathBuf,
+    lines: Vec<Line>,
+    /// Every label in the program, sorted by address
+    symbols: Vec<Symbol>,
+    constants: Vec<Constant>,
+}
+
+impl Listing {
+    /// Records the address and source line of every statement in the given program
+    ///
+    /// Must be called before the program is laid out into an executable. The addresses are
+    /// computed the same way as `LabelOffsets`, starting from its base address.
+    pub fn new(prog: &asm::Program, labels: &LabelOffsets, source_files: &SourceFiles, root_path: &Path) -> Self {
+        let mut listing = Self {
+            root_path: root_path.to_path_buf(),
+            lines: Vec::new(),
+            symbols: Vec::new(),
+            constants: Vec::new(),
+        };
+
+        let globals: HashSet<_> = prog.globals.iter().collect();
+
+        // Every repetition of a `.rept` block has the same spans as the block itself
+        let mut span_counts: HashMap<Span, usize> = HashMap::new();
+        for stmt in prog.code_section.iter().chain(&prog.static_section).flat_map(|section| &section.stmts) {
+            *span_counts.entry(stmt.kind.span()).or_default() += 1;
+            for label in &stmt.labels {
+                *span_counts.entry(label.span).or_default() += 1;
+            }
+        }
+        for label in &prog.end_labels {
+            *span_counts.entry(label.span).or_default() += 1;
+        }
+        let repeated: HashSet<_> = span_counts.into_iter()
+            .filter(|&(_, count)| count > 1)
+            .map(|(span, _)| span)
+            .collect();
+
+        let mut addr = labels.base_addr();
+        for section in prog.code_section.iter().chain(&prog.static_section) {
+            // Labels at the end of the previous section are attached to the first statement of
+            // this section, so the section header must be listed after them
+            let mut header = Some(section.section_header_span);
+
+            for stmt in &section.stmts {
+                for label in &stmt.labels {
+                    if let Some(span) = header.filter(|span| span.start < label.span.start) {
+                        listing.push_line(source_files, span, None, None, false);
+                        header = None;
+                    }
+                    listing.push_label(source_files, label, addr, globals.contains(label), repeated.contains(&label.span));
+                }
+
+                if let Some(span) = header.take() {
+                    listing.push_line(source_files, span, None, None, false);
+                }
+                let stmt_span = stmt.kind.span();
+                listing.push_line(source_files, stmt_span, Some(addr), Some(stmt.size_bytes()), repeated.contains(&stmt_span));
+                addr += stmt.size_bytes();
+            }
+
+            if let Some(span) = header {
+                listing.push_line(source_files, span, None, None, false);
+            }
+        }
+
+        for label in &prog.end_labels {
+            listing.push_label(source_files, label, addr, globals.contains(label), repeated.contains(&label.span));
+        }
+
+        // Stable sort so labels for the same address stay in the order they were defined
+        listing.symbols.sort_by_key(|symbol| symbol.addr);
+
+        listing.constants = prog.consts.iter().map(|const_stmt| {
+            let pos = source_files.pos(const_stmt.span);
+            Constant {
+                name: const_stmt.name.to_string(),
+                value: const_stmt.value.to_string(),
+                path: pos.path.to_path_buf(),
+                line: pos.start_line,
+            }
+        }).collect();
+
+        listing
+    }
+
+    fn push_label(&mut self, source_files: &SourceFiles, label: &asm::Ident, addr: u64, global: bool, repeated: bool) {
+        self.symbols.push(Symbol {name: label.to_string(), addr, global});
+        self.push_line(source_files, label.span, Some(addr), None, repeated);
+    }
+
+    /// Adds the line containing the given span to the listing
+    ///
+    /// A label on the same line as a statement (e.g. `main: add $1, 2`) is only listed once.
+    fn push_line(&mut self, source_files: &SourceFiles, span: Span, addr: Option<u64>, size: Option<u64>, repeated: bool) {
+        let pos = source_files.pos(span);
+
+        if let Some(prev) = self.lines.last_mut() {
+            if prev.path == pos.path && prev.line == pos.start_line && prev.size.is_none() {
+                prev.addr = prev.addr.or(addr);
+                prev.size = size;
+                prev.expanded |= repeated;
+                return;
+            }
+        }
+
+        self.lines.push(Line {
+            addr,
+            size,
+            path: pos.path.to_path_buf(),
+            line: pos.start_line,
+            expanded: repeated || source_files.expansion(span).is_some(),
+            source: String::from_utf8_lossy(source_files.line_source(span)).into_owned(),
+        });
+    }
+
+    /// Writes the listing, using the encoding of each statement from the laid out executable
+    ///
+    /// The executable must have been laid out from the same program that the listing was
+    /// created from.
+    pub fn write_to(&self, mut writer: impl Write, exec: &Executable) -> io::Result<()> {
+        let Self {root_path, lines, symbols, constants} = self;
+
+        writeln!(writer, "{:<8}  {:<width$}  {:>5}  SOURCE", "ADDRESS", "ENCODING", "LINE", width=ENCODING_WIDTH)?;
+
+        let mut stmts = exec.code_section.iter().chain(&exec.static_section);
+        let mut prev_path = None;
+        for Line {addr, size, path, line, expanded, source} in lines {
+            if prev_path != Some(path) {
+                if path == root_path {
+                    writeln!(writer, "; {}", path.display())?;
+                } else {
+                    writeln!(writer, "; {} (included)", path.display())?;
+                }
+                prev_path = Some(path);
+            }
+
+            // Each statement corresponds to one or more statements in the executable, e.g. a `.b8`
+            // directive with multiple values
+            let mut stmt_encoding = Vec::new();
+            let mut remaining = size.unwrap_or(0);
+            while remaining > 0 {
+                let stmt = stmts.next().expect("bug: listing has more statements than the executable");
+                remaining -= stmt.size_bytes();
+                stmt_encoding.push(stmt);
+            }
+
+            let mut encoding = encoding_lines(&stmt_encoding).into_iter();
+            let addr_column = match addr {
+                Some(addr) => format!("{:08x}", addr),
+                None => String::new(),
+            };
+            let line_column = if *expanded { format!("+{}", line) } else { line.to_string() };
+            writeln!(writer, "{:<8}  {:<width$}  {:>5}  {}", addr_column, encoding.next().unwrap_or_default(),
+                line_column, source, width=ENCODING_WIDTH)?;
+
+            // Any remaining bytes go on their own lines without any source
+            let mut addr = addr.unwrap_or(0) + BYTES_PER_LINE as u64;
+            for encoding in encoding {
+                writeln!(writer, "{:08x}  {}", addr, encoding)?;
+                addr += BYTES_PER_LINE as u64;
+            }
+        }
+
+        writeln!(writer)?;
+        writeln!(writer, "SYMBOLS")?;
+        writeln!(writer, "{:<8}  NAME", "ADDRESS")?;
+        for Symbol {name, addr, global} in symbols {
+            if *global {
+                writeln!(writer, "{:08x}  {} (global)", addr, name)?;
+            } else {
+                writeln!(writer, "{:08x}  {}", addr, name)?;
+            }
+        }
+
+        writeln!(writer)?;
+        writeln!(writer, "CONSTANTS")?;
+        let name_width = constants.iter().map(|constant| constant.name.len()).max().unwrap_or(0).max("NAME".len());
+        let value_width = constants.iter().map(|constant| constant.value.len()).max().unwrap_or(0).max("VALUE".len());
+        writeln!(writer, "{:<name_width$}  {:<value_width$}  DEFINED AT", "NAME", "VALUE",
+            name_width=name_width, value_width=value_width)?;
+        for Constant {name, value, path, line} in constants {
+            writeln!(writer, "{:<name_width$}  {:<value_width$}  {}:{}", name, value, path.display(), line,
+                name_width=name_width, value_width=value_width)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats the encoding of the given statements, split across as many lines as needed
+///
+/// Instructions are shown as their 64-bit encoding and data is shown as bytes in the order they
+/// are laid out in memory.
+fn encoding_lines(stmts: &[&Stmt]) -> Vec<String> {
+    match stmts {
+        [Stmt::Instr(instr)] => return vec![format!("{:016x}", instr.to_binary())],
+        [Stmt::StaticData(StaticData::StaticZero(StaticZero {nbytes}))] => return vec![format!("({} zero bytes)", nbytes)],
+        [Stmt::StaticData(StaticData::StaticUninit(StaticUninit {nbytes}))] => return vec![format!("({} uninitialized bytes)", nbytes)],
+        _ => {},
+    }
+
+    let mut bytes = Vec::new();
+    for stmt in stmts {
+        stmt.write_bytes(&mut bytes);
+    }
+
+    bytes.chunks(BYTES_PER_LINE).map(|chunk| {
+        let mut line = String::new();
+        for (i, byte) in chunk.iter().enumerate() {
+            let sep = if i == 0 { "" } else { " " };
+            write!(line, "{}{:02x}", sep, byte).unwrap();
+        }
+        line
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use parking_lot::RwLock;
+    use termcolor::ColorChoice;
+
+    use crate::diagnostics::Diagnostics;
+    use crate::file_provider::{FileProvider, MemoryFileProvider};
+    use crate::const_table::parse_define_arg;
+    use crate::assembler::expand_program;
+    use crate::validate::validate_program;
+
+    fn listing(source: &str) -> String {
+        let mut files = MemoryFileProvider::new();
+        files.insert("test.wa", source);
+        listing_with_defines(files, &[])
+    }
+
+    /// Assembles `test.wa` from the given files with the given `-D` defines, and returns its
+    /// listing
+    fn listing_with_defines(files: MemoryFileProvider, defines: &[&str]) -> String {
+        let source_files = Arc::new(RwLock::new(SourceFiles::default()));
+        let diag = Diagnostics::new(source_files.clone(), ColorChoice::Never);
+
+        let defines: Vec<_> = defines.iter()
+            .map(|define| parse_define_arg(define, &source_files, &diag).expect("invalid define"))
+            .collect();
+        let path = Path::new("test.wa");
+        let root_file = source_files.write().add_source(path, &files.read(path).unwrap());
+        let program = expand_program(path, root_file, &source_files, &files, &defines, &diag);
+        let program = validate_program(program, &defines, &diag);
+        let labels = LabelOffsets::with_base_addr(&program, 0x100);
+
+        let listing = Listing::new(&program, &labels, &source_files.read(), path);
+        let exec = Executable::layout_executable(program, &diag, &labels);
+        assert_eq!(diag.emitted_errors(), 0);
+
+        let mut out = Vec::new();
+        listing.write_to(&mut out, &exec).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn addresses_and_encodings() {
+        let source = "\
+section .code
+.const SIZE 2 * 4
+main:
+    add $1, SIZE
+    load8 $2, msg ; the first byte
+end_code:
+section .static
+msg: .b1 1, 2, 3, 4, 5, 6, 7, 8, 9
+buf: .zero 8
+";
+
+        let expected = "\
+ADDRESS   ENCODING                  LINE  SOURCE
+; test.wa
+                                       1  section .code
+00000100                               3  main:
+00000100  00d0400000000008             4      add $1, SIZE
+00000108  1390800000000110             5      load8 $2, msg ; the first byte
+00000110                               6  end_code:
+                                       7  section .static
+00000110  01 02 03 04 05 06 07 08      8  msg: .b1 1, 2, 3, 4, 5, 6, 7, 8, 9
+00000118  09
+00000119  (8 zero bytes)               9  buf: .zero 8
+
+SYMBOLS
+ADDRESS   NAME
+00000100  main
+00000110  end_code
+00000110  msg
+00000119  buf
+
+CONSTANTS
+NAME  VALUE  DEFINED AT
+size  2 * 4  test.wa:2
+";
+
+        assert_eq!(listing(source), expected);
+    }
+
+    #[test]
+    fn includes_and_macros() {
+        let mut files = MemoryFileProvider::new();
+        files.insert("test.wa", "\
+section .code
+.include \"lib.wa\"
+main:
+    inc $1
+    .rept 2
+    sub $1, STEP
+    .endr
+    ret
+");
+        files.insert("lib.wa", "\
+.const STEP 2
+.macro inc reg
+    add reg, STEP
+.endm
+");
+
+        let expected = "\
+ADDRESS   ENCODING                  LINE  SOURCE
+; test.wa
+                                       1  section .code
+00000100                               3  main:
+; lib.wa (included)
+00000100  00d0400000000002            +3      add reg, STEP
+; test.wa
+00000108  0190400000000002            +6      sub $1, STEP
+00000110  0190400000000002            +6      sub $1, STEP
+00000118  2700000000000000             8      ret
+
+SYMBOLS
+ADDRESS   NAME
+00000100  main
+
+CONSTANTS
+NAME   VALUE  DEFINED AT
+debug  1      <command line>:1
+step   2      lib.wa:1
+";
+
+        assert_eq!(listing_with_defines(files, &["DEBUG"]), expected);
+    }
+}
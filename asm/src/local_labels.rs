@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use crate::ast;
+
+/// The direction that a numeric label reference searches in for its label
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// `1b` refers to the nearest `1:` label at or before the reference
+    Backward,
+    /// `1f` refers to the nearest `1:` label after the reference
+    Forward,
+}
+
+/// Qualifies every local label (`.loop`) and numeric label (`1:`, `1b`, `1f`) in a program with
+/// the name of the global label that precedes it
+///
+/// For example, `.loop` after `main:` becomes `main.loop` and `1b` becomes `main.1b`. Since
+/// neither global label names nor constant names can contain a `.`, the qualified names can never
+/// conflict with any other name in the program.
+///
+/// Numeric labels may be defined any number of times, so references to them are only resolved
+/// once the address of every label is known (see `LabelOffsets`).
+pub fn qualify_local_labels(prog: ast::Program) -> ast::Program {
+    let ast::Program {stmts} = prog;
+
+    // Local labels that occur before any global label have no scope
+    let mut scope: Arc<str> = "".into();
+    let qualify = |scope: &str, name: ast::Ident| {
+        if is_local_name(&name.value) {
+            // Local label names already start with a `.`
+            let sep = if name.value.starts_with('.') { "" } else { "." };
+            ast::Ident {value: format!("{}{}{}", scope, sep, name.value).into(), span: name.span}
+        } else {
+            name
+        }
+    };
+    let qualify_expr = |scope: &str, expr: ast::Expr| expr.map_names(&mut |name| ast::Expr {
+        span: name.span,
+        kind: ast::ExprKind::Name(qualify(scope, name)),
+    });
+
+    let stmts = stmts.into_iter().map(|stmt| match stmt {
+        ast::Stmt::Label(label) => {
+            if !is_local_name(&label.value) {
+                scope = label.value.clone();
+            }
+            ast::Stmt::Label(qualify(&scope, label))
+        },
+        ast::Stmt::Const(ast::Const {name, value, span}) => {
+            ast::Stmt::Const(ast::Const {name, value: qualify_expr(&scope, value), span})
+        },
+        ast::Stmt::StaticData(ast::StaticData::StaticBytes(ast::StaticBytes {size, values, span})) => {
+            let values = values.into_iter().map(|value| qualify_expr(&scope, value)).collect();
+            ast::Stmt::StaticData(ast::StaticData::StaticBytes(ast::StaticBytes {size, values, span}))
+        },
+        ast::Stmt::Instr(ast::Instr {name, args}) => ast::Stmt::Instr(ast::Instr {
+            name,
+            args: args.into_iter().map(|arg| match arg {
+                ast::InstrArg::Name(name) => ast::InstrArg::Name(qualify(&scope, name)),
+                ast::InstrArg::Expr(expr) => ast::InstrArg::Expr(qualify_expr(&scope, expr)),
+                arg => arg,
+            }).collect(),
+        }),
+        stmt => stmt,
+    }).collect();
+
+    ast::Program {stmts}
+}
+
+/// Returns true if the given name (before qualification) is a local label, a numeric label, or a
+/// reference to a numeric label
+fn is_local_name(name: &str) -> bool {
+    name.starts_with(|ch: char| ch == '.' || ch.is_ascii_digit())
+}
+
+/// Returns true if the given label name (qualified or not) is a numeric label, e.g. `1`,
+/// `main.1`
+///
+/// Numeric labels are the only labels that may be defined more than once.
+pub fn is_numeric_label(name: &str) -> bool {
+    let (_, local) = split_scope(name);
+    !local.is_empty() && local.bytes().all(|ch| ch.is_ascii_digit())
+}
+
+/// If the given qualified name is a reference to a numeric label (e.g. `main.1b`), returns the
+/// name of the label it refers to (e.g. `main.1`) and the direction to search in
+pub fn numeric_label_ref(name: &str) -> Option<(&str, Direction)> {
+    let (label, dir) = match name.as_bytes().last()? {
+        b'b' => (&name[..name.len()-1], Direction::Backward),
+        b'f' => (&name[..name.len()-1], Direction::Forward),
+        _ => return None,
+    };
+
+    if is_numeric_label(label) {
+        Some((label, dir))
+    } else {
+        None
+    }
+}
+
+/// Returns the name of a label as it would be written within its scope, e.g. `main.loop` becomes
+/// `.loop` and `main.1` becomes `1`
+pub fn unqualify(name: &str) -> &str {
+    match name.rfind('.') {
+        Some(index) if is_numeric_label(name) => &name[index+1..],
+        Some(index) => &name[index..],
+        None => name,
+    }
+}
+
+/// Splits a qualified name into its scope and the rest of its name
+fn split_scope(name: &str) -> (&str, &str) {
+    match name.rsplit_once('.') {
+        Some((scope, local)) => (scope, local),
+        None => ("", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numeric_labels() {
+        assert!(is_numeric_label("1"));
+        assert!(is_numeric_label("main.12"));
+        assert!(is_numeric_label(".0"));
+        assert!(!is_numeric_label("main"));
+        assert!(!is_numeric_label("main.loop"));
+        assert!(!is_numeric_label("main.1b"));
+        assert!(!is_numeric_label("main."));
+
+        assert_eq!(numeric_label_ref("main.1b"), Some(("main.1", Direction::Backward)));
+        assert_eq!(numeric_label_ref("main.12f"), Some(("main.12", Direction::Forward)));
+        assert_eq!(numeric_label_ref(".3f"), Some((".3", Direction::Forward)));
+        assert_eq!(numeric_label_ref("main.1"), None);
+        assert_eq!(numeric_label_ref("main.b"), None);
+        assert_eq!(numeric_label_ref("sub"), None);
+        assert_eq!(numeric_label_ref("main.loopb"), None);
+    }
+
+    #[test]
+    fn unqualified_names() {
+        assert_eq!(unqualify("main"), "main");
+        assert_eq!(unqualify("main.loop"), ".loop");
+        assert_eq!(unqualify(".loop"), ".loop");
+        assert_eq!(unqualify("main.12"), "12");
+        assert_eq!(unqualify(".3"), "3");
+    }
+}
@@ -0,0 +1,447 @@
+use std::sync::Arc;
+use std::collections::HashMap;
+
+use parking_lot::RwLock;
+
+use crate::ast;
+use crate::asm::InstrKind;
+use crate::parser::{Span, SourceFiles, Expansion, collect_tokens, parse_program};
+use crate::conditional_assembly::Conditionals;
+use crate::local_labels::is_numeric_label;
+use crate::diagnostics::Diagnostics;
+
+/// The maximum number of statements that expanding the macros and `.rept` blocks in a program may
+/// produce, so that a few lines of code cannot make the assembler run out of memory
+pub const MAX_EXPANDED_STMTS: usize = 1_000_000;
+
+/// The maximum number of times that macros and `.rept` blocks may be expanded in a program, so
+/// that expansions that produce no statements cannot make the assembler run forever
+pub const MAX_EXPANSIONS: usize = 1_000_000;
+
+/// Attempts to expand all `.macro` and `.rept` directives in a program
+///
+/// Macros may invoke other macros up to `depth` levels deep, after which an error will be produced.
+///
+/// Each expansion of a macro body is parsed from a copy of the body's source so that every
+/// expansion has unique spans. Diagnostics for code in an expansion point to both the macro body
+/// and to the invocation of the macro.
+///
+/// Labels declared within a macro body or `.rept` block are renamed so that they are unique to
+/// each expansion (e.g. `loop` becomes `loop@3`). Since names cannot contain an `@`, the renamed
+/// labels can never conflict with any other name in the program. Numeric labels (e.g. `1:`) are
+/// left as-is since they may be defined any number of times.
+///
+/// Expansion stops with an error once `MAX_EXPANDED_STMTS` statements have been produced or after
+/// `MAX_EXPANSIONS` expansions.
+///
+/// Conditional directives in a macro body are evaluated with `conditionals` each time the macro
+/// is expanded, before any macros invoked in the body are expanded. Only the constants defined
+/// before the macro was invoked are used.
+///
+/// If no errors occur, the returned program is guaranteed to not have any remaining macro
+/// definitions, invocations, or `.rept` directives in it.
+pub fn expand_macros(
+    prog: ast::Program,
+    conditionals: &mut Conditionals,
+    source_files: &Arc<RwLock<SourceFiles>>,
+    diag: &Diagnostics,
+    depth: usize,
+) -> ast::Program {
+    let ast::Program {stmts} = prog;
+
+    let mut expander = MacroExpander {
+        conditionals,
+        source_files,
+        diag,
+        max_depth: depth,
+        macros: HashMap::new(),
+        expansions: 0,
+        reached_limit: false,
+    };
+
+    let stmts = expander.collect_macros(stmts);
+    let mut expanded_stmts = Vec::with_capacity(stmts.len());
+    expander.expand_stmts(stmts, 0, &mut expanded_stmts);
+
+    ast::Program {stmts: expanded_stmts}
+}
+
+#[derive(Debug)]
+struct MacroDef {
+    header: ast::Macro,
+    /// The span of the source code between `.macro` and `.endm`
+    body: Span,
+    /// False if the body contains errors, in which case the macro will expand to nothing
+    is_valid: bool,
+}
+
+struct MacroExpander<'a> {
+    conditionals: &'a mut Conditionals,
+    source_files: &'a Arc<RwLock<SourceFiles>>,
+    diag: &'a Diagnostics,
+    max_depth: usize,
+    macros: HashMap<Arc<str>, MacroDef>,
+    /// The number of expansions so far, used to generate unique label names
+    expansions: usize,
+    /// True if an expansion limit has been reached, after which nothing else is expanded
+    reached_limit: bool,
+}
+
+impl<'a> MacroExpander<'a> {
+    /// Removes every macro definition from the given statements and records it so that it can be
+    /// expanded later
+    fn collect_macros(&mut self, stmts: Vec<ast::Stmt>) -> Vec<ast::Stmt> {
+        let mut remaining_stmts = Vec::with_capacity(stmts.len());
+        let mut stmts = stmts.into_iter();
+
+        while let Some(stmt) = stmts.next() {
+            let header = match stmt {
+                ast::Stmt::Macro(header) => header,
+                stmt => {
+                    remaining_stmts.push(stmt);
+                    continue;
+                },
+            };
+
+            // Find the end of the macro definition
+            let mut end_span = None;
+            let mut is_valid = true;
+            for stmt in &mut stmts {
+                match stmt {
+                    ast::Stmt::EndMacro(span) => {
+                        end_span = Some(span);
+                        break;
+                    },
+
+                    ast::Stmt::Macro(nested) => {
+                        self.diag.span_error(nested.span, "macro definitions cannot be nested")
+                            .span_note(header.span, "inside the definition of this macro").emit();
+                        is_valid = false;
+                    },
+
+                    _ => {},
+                }
+            }
+
+            let end_span = match end_span {
+                Some(end_span) => end_span,
+                None => {
+                    self.diag.span_error(header.span, format!("macro `{}` is missing `.endm`", header.name)).emit();
+                    break;
+                },
+            };
+
+            if !self.source_files.read().same_file(header.span, end_span) {
+                self.diag.span_error(end_span, "`.endm` must be in the same file as `.macro`")
+                    .span_note(header.span, "macro defined here").emit();
+                continue;
+            }
+
+            let body = Span {start: header.span.end, end: end_span.start};
+            is_valid &= self.check_body(&header, body);
+            self.define_macro(MacroDef {header, body, is_valid});
+        }
+
+        remaining_stmts
+    }
+
+    /// Checks for statements that are not allowed inside a macro body, returning true if there
+    /// were no errors
+    fn check_body(&self, header: &ast::Macro, body: Span) -> bool {
+        // Parse the body separately since `.include` directives have already been expanded
+        let stmts = {
+            let files = self.source_files.read();
+            let tokens = collect_tokens(files.span_source(body), self.diag);
+            parse_program(&tokens, self.diag).stmts
+        };
+
+        let mut is_valid = true;
+        for stmt in stmts {
+            if let ast::Stmt::Include(include) = stmt {
+                self.diag.span_error(include.span, "`.include` cannot be used inside a macro")
+                    .span_note(header.span, "inside the definition of this macro").emit();
+                is_valid = false;
+            }
+        }
+
+        is_valid
+    }
+
+    fn define_macro(&mut self, def: MacroDef) {
+        let name = &def.header.name;
+        if InstrKind::is_name(&name.value) {
+            self.diag.span_error(name.span, format!("macro `{}` has the same name as an instruction", name)).emit();
+            return;
+        }
+
+        for (i, param) in def.header.params.iter().enumerate() {
+            if let Some(prev) = def.header.params[..i].iter().find(|prev| *prev == param) {
+                self.diag.span_error(param.span, format!("duplicate parameter `{}`", param))
+                    .span_note(prev.span, "previously declared here").emit();
+            }
+        }
+
+        match self.macros.get(&name.value) {
+            Some(prev) => {
+                self.diag.span_error(name.span, format!("duplicate macro `{}`", name))
+                    .span_note(prev.header.name.span, "previously defined here").emit();
+            },
+
+            None => {
+                self.macros.insert(name.value.clone(), def);
+            },
+        }
+    }
+
+    /// Expands every macro invocation and `.rept` block in the given statements
+    fn expand_stmts(&mut self, stmts: Vec<ast::Stmt>, depth: usize, expanded_stmts: &mut Vec<ast::Stmt>) {
+        let mut stmts = stmts.into_iter();
+
+        while let Some(stmt) = stmts.next() {
+            match stmt {
+                ast::Stmt::Instr(instr) if self.macros.contains_key(&instr.name.value) => {
+                    self.expand_macro(instr, depth, expanded_stmts);
+                },
+
+                ast::Stmt::Rept(rept) => {
+                    let body = match take_rept_body(&mut stmts) {
+                        Some(body) => body,
+                        None => {
+                            self.diag.span_error(rept.span, "`.rept` is missing `.endr`").emit();
+                            break;
+                        },
+                    };
+
+                    self.expand_rept(rept, body, depth, expanded_stmts);
+                },
+
+                ast::Stmt::EndMacro(span) => {
+                    self.diag.span_error(span, "`.endm` without a matching `.macro`").emit();
+                },
+
+                ast::Stmt::EndRept(span) => {
+                    self.diag.span_error(span, "`.endr` without a matching `.rept`").emit();
+                },
+
+                // Nested definitions have already been reported as errors in `collect_macros`
+                ast::Stmt::Macro(_) => {},
+
+                stmt => expanded_stmts.push(stmt),
+            }
+        }
+    }
+
+    fn expand_macro(&mut self, call: ast::Instr, depth: usize, expanded_stmts: &mut Vec<ast::Stmt>) {
+        let call_site = call.span();
+        if !self.check_limits(call_site, expanded_stmts) {
+            return;
+        }
+
+        let def = &self.macros[&call.name.value];
+        if !def.is_valid {
+            return;
+        }
+
+        if depth >= self.max_depth {
+            self.diag.span_error(call_site, format!("maximum macro recursion depth reached while expanding macro `{}`", call.name)).emit();
+            return;
+        }
+
+        let params = &def.header.params;
+        if call.args.len() != params.len() {
+            self.diag.span_error(call_site, format!("expected {} arguments for `{}` macro, found {} arguments", params.len(), call.name, call.args.len()))
+                .span_note(def.header.span, "macro defined here").emit();
+            return;
+        }
+
+        // An empty body cannot be copied into a separate file, but there is nothing to expand anyway
+        if def.body.start == def.body.end {
+            return;
+        }
+
+        let args: HashMap<_, _> = params.iter().map(|param| param.value.clone()).zip(call.args).collect();
+        let stmts = {
+            let mut files = self.source_files.write();
+            let handle = files.add_expansion(Expansion {
+                name: call.name.value.clone(),
+                call_site,
+                body: def.body,
+            });
+
+            let tokens = collect_tokens(files.source(handle), self.diag);
+            parse_program(&tokens, self.diag).stmts
+        };
+
+        let stmts = self.rename_local_labels(stmts);
+        let stmts = stmts.into_iter().map(|stmt| substitute_args(stmt, &args, self.diag)).collect();
+        let stmts = self.conditionals.expand_macro_body(stmts, call_site, self.diag);
+
+        self.expand_stmts(stmts, depth + 1, expanded_stmts);
+    }
+
+    fn expand_rept(&mut self, rept: ast::Rept, body: Vec<ast::Stmt>, depth: usize, expanded_stmts: &mut Vec<ast::Stmt>) {
+        let ast::Rept {count, span: _} = rept;
+        if count.value < 0 {
+            self.diag.span_error(count.span, format!("`.rept` count must not be negative, found `{}`", count.value)).emit();
+            return;
+        }
+
+        if count.value > MAX_EXPANSIONS as i128 {
+            self.diag.span_error(count.span, format!("`.rept` count must be at most {}, found `{}`", MAX_EXPANSIONS, count.value)).emit();
+            return;
+        }
+
+        for _ in 0..count.value {
+            if !self.check_limits(count.span, expanded_stmts) {
+                return;
+            }
+
+            let stmts = self.rename_local_labels(body.clone());
+            self.expand_stmts(stmts, depth, expanded_stmts);
+        }
+    }
+
+    /// Returns true if another expansion is allowed, or reports an error at the given span (once)
+    /// if an expansion limit has been reached
+    fn check_limits(&mut self, span: Span, expanded_stmts: &[ast::Stmt]) -> bool {
+        if self.reached_limit {
+            return false;
+        }
+
+        let message = if expanded_stmts.len() >= MAX_EXPANDED_STMTS {
+            format!("expanding macros and `.rept` blocks produced more than {} statements", MAX_EXPANDED_STMTS)
+        } else if self.expansions >= MAX_EXPANSIONS {
+            format!("macros and `.rept` blocks were expanded more than {} times", MAX_EXPANSIONS)
+        } else {
+            return true;
+        };
+
+        self.diag.span_error(span, message).emit();
+        self.reached_limit = true;
+        false
+    }
+
+    /// Renames every label declared in the given statements to a name that is unique to this
+    /// expansion, and updates every reference to those labels
+    fn rename_local_labels(&mut self, stmts: Vec<ast::Stmt>) -> Vec<ast::Stmt> {
+        self.expansions += 1;
+        let expansion = self.expansions;
+
+        let renamed: HashMap<Arc<str>, ast::Ident> = stmts.iter().filter_map(|stmt| match stmt {
+            ast::Stmt::Label(label) if is_numeric_label(&label.value) => None,
+            ast::Stmt::Label(label) => Some((label.value.clone(), ast::Ident {
+                value: format!("{}@{}", label.value, expansion).into(),
+                span: label.span,
+            })),
+            _ => None,
+        }).collect();
+
+        let rename = |ident: ast::Ident| match renamed.get(&ident.value) {
+            Some(new_ident) => ast::Ident {span: ident.span, ..new_ident.clone()},
+            None => ident,
+        };
+        let rename_expr = |expr: ast::Expr| expr.map_names(&mut |name| ast::Expr {
+            span: name.span,
+            kind: ast::ExprKind::Name(rename(name)),
+        });
+
+        stmts.into_iter().map(|stmt| match stmt {
+            ast::Stmt::Label(label) => ast::Stmt::Label(rename(label)),
+            ast::Stmt::Entry(ast::Entry {label, span}) => ast::Stmt::Entry(ast::Entry {label: rename(label), span}),
+            ast::Stmt::Global(ast::Global {label, span}) => ast::Stmt::Global(ast::Global {label: rename(label), span}),
+            ast::Stmt::Const(ast::Const {name, value, span}) => ast::Stmt::Const(ast::Const {name, value: rename_expr(value), span}),
+            ast::Stmt::StaticData(ast::StaticData::StaticBytes(ast::StaticBytes {size, values, span})) => {
+                let values = values.into_iter().map(rename_expr).collect();
+                ast::Stmt::StaticData(ast::StaticData::StaticBytes(ast::StaticBytes {size, values, span}))
+            },
+            ast::Stmt::Instr(ast::Instr {name, args}) => ast::Stmt::Instr(ast::Instr {
+                name,
+                args: args.into_iter().map(|arg| match arg {
+                    ast::InstrArg::Name(name) => ast::InstrArg::Name(rename(name)),
+                    ast::InstrArg::Expr(expr) => ast::InstrArg::Expr(rename_expr(expr)),
+                    arg => arg,
+                }).collect(),
+            }),
+            stmt => stmt,
+        }).collect()
+    }
+}
+
+/// Takes every statement up to the `.endr` that matches a `.rept` that was just taken
+///
+/// Returns `None` if there is no matching `.endr`
+fn take_rept_body(stmts: &mut impl Iterator<Item=ast::Stmt>) -> Option<Vec<ast::Stmt>> {
+    let mut body = Vec::new();
+    let mut nested = 0;
+    for stmt in stmts {
+        match stmt {
+            ast::Stmt::Rept(_) => nested += 1,
+            ast::Stmt::EndRept(_) if nested == 0 => return Some(body),
+            ast::Stmt::EndRept(_) => nested -= 1,
+            _ => {},
+        }
+
+        body.push(stmt);
+    }
+
+    None
+}
+
+/// Replaces every use of a macro parameter with the corresponding argument
+fn substitute_args(stmt: ast::Stmt, args: &HashMap<Arc<str>, ast::InstrArg>, diag: &Diagnostics) -> ast::Stmt {
+    let substitute_expr = |expr: ast::Expr| expr.map_names(&mut |name| match args.get(&name.value) {
+        Some(arg) => arg_to_expr(arg.clone(), name.span, diag),
+        None => ast::Expr {span: name.span, kind: ast::ExprKind::Name(name)},
+    });
+
+    match stmt {
+        ast::Stmt::Const(ast::Const {name, value, span}) => ast::Stmt::Const(ast::Const {name, value: substitute_expr(value), span}),
+        ast::Stmt::If(ast::If {cond: ast::Condition::Expr(cond), span}) => ast::Stmt::If(ast::If {cond: ast::Condition::Expr(substitute_expr(cond)), span}),
+        ast::Stmt::StaticData(ast::StaticData::StaticBytes(ast::StaticBytes {size, values, span})) => {
+            let values = values.into_iter().map(substitute_expr).collect();
+            ast::Stmt::StaticData(ast::StaticData::StaticBytes(ast::StaticBytes {size, values, span}))
+        },
+        ast::Stmt::Instr(ast::Instr {name, args: instr_args}) => ast::Stmt::Instr(ast::Instr {
+            name,
+            args: instr_args.into_iter().map(|arg| match arg {
+                ast::InstrArg::Name(name) => match args.get(&name.value) {
+                    // The argument takes the span of the parameter so that every span in the
+                    // instruction stays within the same file
+                    Some(arg) => with_span(arg.clone(), name.span),
+                    None => ast::InstrArg::Name(name),
+                },
+                ast::InstrArg::Expr(expr) => substitute_expr(expr).into(),
+                arg => arg,
+            }).collect(),
+        }),
+
+        stmt => stmt,
+    }
+}
+
+fn with_span(arg: ast::InstrArg, span: Span) -> ast::InstrArg {
+    match arg {
+        ast::InstrArg::Register(reg) => ast::InstrArg::Register(ast::Register {span, ..reg}),
+        ast::InstrArg::Immediate(imm) => ast::InstrArg::Immediate(ast::Immediate {span, ..imm}),
+        ast::InstrArg::Name(name) => ast::InstrArg::Name(ast::Ident {span, ..name}),
+        ast::InstrArg::Expr(expr) => ast::InstrArg::Expr(ast::Expr {span, ..expr}),
+    }
+}
+
+/// Converts a macro argument so it can be used in place of a parameter within an expression
+fn arg_to_expr(arg: ast::InstrArg, span: Span, diag: &Diagnostics) -> ast::Expr {
+    let kind = match arg {
+        ast::InstrArg::Register(reg) => {
+            diag.span_error(span, format!("register `{}` cannot be used in an expression", reg))
+                .span_note(reg.span, "register passed to the macro here").emit();
+
+            // Error recovery: use any value so more errors can be found
+            ast::ExprKind::Integer(0)
+        },
+        ast::InstrArg::Immediate(imm) => ast::ExprKind::Integer(imm.value),
+        ast::InstrArg::Name(name) => ast::ExprKind::Name(ast::Ident {span, ..name}),
+        ast::InstrArg::Expr(expr) => expr.kind,
+    };
+
+    ast::Expr {kind, span}
+}
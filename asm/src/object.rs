@@ -0,0 +1,266 @@
+//! Object files, which contain a program that has been laid out but may still refer to labels in
+//! other object files
+//!
+//! Every immediate that refers to a label or to the location counter (`.`) is left as a
+//! placeholder and recorded as a relocation. The linker (see `linker`) fills in these immediates
+//! once the final address of every label is known.
+
+mod file_format;
+
+pub use file_format::*;
+
+use std::collections::HashSet;
+
+use serde::{Serialize, Deserialize};
+
+use crate::ast;
+use crate::asm;
+use crate::parser::SourceFiles;
+use crate::diagnostics::Diagnostics;
+use crate::label_offsets::LabelOffsets;
+use crate::executable::{Stmt, StaticData, StaticBytes, DebugInfo, entry_offset};
+
+/// A section of an object file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Section {
+    Code,
+    Static,
+}
+
+/// A label defined in an object file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Symbol {
+    pub label: asm::Ident,
+    /// The section containing the statement that the label refers to
+    pub section: Section,
+    /// The offset of the label from the start of its section
+    pub offset: u64,
+    /// True if the label was declared with `.global`, and so may be used by other object files
+    pub global: bool,
+}
+
+/// An immediate whose value can only be computed once the address of every label is known
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Relocation {
+    /// The section containing the statement with the immediate
+    pub section: Section,
+    /// The index of the statement within its section
+    pub stmt: usize,
+    /// The index of the immediate within the layout of the instruction (see
+    /// `Layout::set_immediate`)
+    ///
+    /// Always zero for static data.
+    pub imm: usize,
+    /// The expression that computes the value of the immediate
+    pub expr: asm::Expr,
+}
+
+/// The first instruction to execute, from either the `.entry` directive or the `main` label
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub label: asm::Ident,
+    /// The offset of the labelled instruction from the start of the code section
+    pub offset: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Object {
+    pub code_section: Vec<Stmt>,
+    pub static_section: Vec<Stmt>,
+    /// Every label defined in the object file, in order of address within each section
+    pub symbols: Vec<Symbol>,
+    /// Labels declared with `.extern`
+    pub externs: Vec<asm::Ident>,
+    pub relocations: Vec<Relocation>,
+    pub entry: Option<Entry>,
+    /// Labels and source locations, only included if requested
+    ///
+    /// Addresses are offsets from the start of the object file, with the static section placed
+    /// right after the code section.
+    pub debug_info: Option<DebugInfo>,
+    /// The source code that every span in the object file refers to
+    ///
+    /// Included so that the linker can report errors in the same way as the assembler.
+    pub source_files: SourceFiles,
+}
+
+impl Object {
+    /// Lays out the program, recording a relocation for every immediate that refers to a label or
+    /// to the location counter
+    ///
+    /// Produces an error for any label that is neither defined in the program nor declared with
+    /// `.extern`. The debug info and source files are left empty.
+    pub fn layout_object(prog: asm::Program, diag: &Diagnostics) -> Self {
+        // Only used to check that labels are defined, since the final addresses are not known yet
+        let labels = LabelOffsets::new(&prog);
+        let symbols = object_symbols(&prog);
+
+        let asm::Program {code_section, static_section, entry, end_labels: _, globals: _, externs, consts: _} = prog;
+
+        let entry = entry.map(|label| {
+            let offset = entry_offset(code_section.as_ref(), &label);
+            Entry {label, offset}
+        });
+
+        let mut layout = ObjectLayout {
+            diag,
+            labels: &labels,
+            externs: externs.iter().collect(),
+            relocations: Vec::new(),
+            addr: 0,
+        };
+        let code_section = code_section.map(|section| layout.layout_section(section, Section::Code)).unwrap_or_default();
+        let static_section = static_section.map(|section| layout.layout_section(section, Section::Static)).unwrap_or_default();
+        let relocations = layout.relocations;
+
+        Self {
+            code_section,
+            static_section,
+            symbols,
+            externs,
+            relocations,
+            entry,
+            debug_info: None,
+            source_files: SourceFiles::default(),
+        }
+    }
+
+    /// Moves every span in this object forward by `offset` bytes
+    ///
+    /// Used after the source files of this object have been appended to another set of source
+    /// files (see `SourceFiles::append`).
+    pub fn shift_spans(&mut self, offset: usize) {
+        for symbol in &mut self.symbols {
+            shift_ident(&mut symbol.label, offset);
+        }
+        for label in &mut self.externs {
+            shift_ident(label, offset);
+        }
+        for reloc in &mut self.relocations {
+            shift_expr(&mut reloc.expr, offset);
+        }
+        if let Some(entry) = &mut self.entry {
+            shift_ident(&mut entry.label, offset);
+        }
+    }
+}
+
+/// Returns a symbol for every label in the program, in order
+fn object_symbols(prog: &asm::Program) -> Vec<Symbol> {
+    let globals: HashSet<_> = prog.globals.iter().collect();
+    let mut symbols = Vec::new();
+
+    let sections = [(Section::Code, &prog.code_section), (Section::Static, &prog.static_section)];
+    let mut end_label_section = (Section::Code, 0);
+    for (section, stmts) in sections.iter().filter_map(|(section, stmts)| Some((*section, &stmts.as_ref()?.stmts))) {
+        let mut offset = 0;
+        for stmt in stmts {
+            for label in &stmt.labels {
+                let global = globals.contains(label);
+                symbols.push(Symbol {label: label.clone(), section, offset, global});
+            }
+
+            offset += stmt.size_bytes();
+        }
+
+        end_label_section = (section, offset);
+    }
+
+    // Labels after the last statement refer to the end of the last section
+    let (section, offset) = end_label_section;
+    for label in &prog.end_labels {
+        let global = globals.contains(label);
+        symbols.push(Symbol {label: label.clone(), section, offset, global});
+    }
+
+    symbols
+}
+
+struct ObjectLayout<'a> {
+    diag: &'a Diagnostics,
+    labels: &'a LabelOffsets,
+    externs: HashSet<&'a asm::Ident>,
+    relocations: Vec<Relocation>,
+    /// The offset of the next statement from the start of the object
+    addr: u64,
+}
+
+impl<'a> ObjectLayout<'a> {
+    fn layout_section(&mut self, section: asm::Section, kind: Section) -> Vec<Stmt> {
+        let asm::Section {section_header_span: _, stmts} = section;
+        let mut laid_out_stmts = Vec::with_capacity(stmts.len());
+
+        for stmt in stmts {
+            let stmt_addr = self.addr;
+            self.addr += stmt.size_bytes();
+
+            let data = match stmt.kind {
+                asm::StmtKind::StaticData(asm::StaticData::StaticBytes(data)) => {
+                    let asm::StaticBytes {size, values, span: _} = data;
+                    let mut addr = stmt_addr;
+                    for value in values {
+                        let value = if value.depends_on_layout() {
+                            self.relocate(kind, laid_out_stmts.len(), 0, value, addr)
+                        } else {
+                            self.labels.eval(&value, addr, self.diag)
+                        };
+                        laid_out_stmts.push(Stmt::StaticData(StaticData::StaticBytes(StaticBytes::new(size, value, self.diag))));
+                        addr += size as u64;
+                    }
+                    continue;
+                },
+
+                asm::StmtKind::StaticData(asm::StaticData::StaticZero(data)) => StaticData::StaticZero(data.into()),
+                asm::StmtKind::StaticData(asm::StaticData::StaticUninit(data)) => StaticData::StaticUninit(data.into()),
+                asm::StmtKind::StaticData(asm::StaticData::StaticByteStr(data)) => StaticData::StaticByteStr(data.into()),
+
+                asm::StmtKind::Instr(mut instr) => {
+                    for (imm, expr) in instr.take_relocations() {
+                        self.relocate(kind, laid_out_stmts.len(), imm, expr, stmt_addr);
+                    }
+                    laid_out_stmts.push(Stmt::Instr(instr.layout(self.diag, self.labels, stmt_addr)));
+                    continue;
+                },
+            };
+
+            laid_out_stmts.push(Stmt::StaticData(data));
+        }
+
+        laid_out_stmts
+    }
+
+    /// Records a relocation for the given expression and returns a placeholder value to use until
+    /// the relocation is resolved
+    fn relocate(&mut self, section: Section, stmt: usize, imm: usize, expr: asm::Expr, addr: u64) -> asm::Immediate {
+        expr.for_each_name(&mut |name| {
+            if !self.externs.contains(name) {
+                // Produces an error if the label is not defined
+                self.labels.lookup(name, addr, self.diag);
+            }
+        });
+
+        let span = expr.span;
+        self.relocations.push(Relocation {section, stmt, imm, expr});
+
+        asm::Immediate {value: 0, span}
+    }
+}
+
+fn shift_ident(ident: &mut asm::Ident, offset: usize) {
+    ident.span = ident.span.shift(offset);
+}
+
+fn shift_expr(expr: &mut asm::Expr, offset: usize) {
+    expr.span = expr.span.shift(offset);
+    match &mut expr.kind {
+        ast::ExprKind::Name(name) => shift_ident(name, offset),
+        ast::ExprKind::Neg(expr) => shift_expr(expr, offset),
+        ast::ExprKind::BinOp(_, lhs, rhs) => {
+            shift_expr(lhs, offset);
+            shift_expr(rhs, offset);
+        },
+        ast::ExprKind::Integer(_) |
+        ast::ExprKind::Float(_) |
+        ast::ExprKind::Here => {},
+    }
+}
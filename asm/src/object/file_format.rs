@@ -0,0 +1,150 @@
+//! The on-disk format for object files
+//!
+//! Object files are only read by the linker from the same version of the toolchain, so the format
+//! is just a fixed size header followed by the `bincode` encoding of `Object`. All integers are
+//! little-endian.
+//!
+//! ```text
+//! offset  size  field
+//!      0     4  magic number: the bytes `WOBJ`
+//!      4     2  format version (see `FORMAT_VERSION`)
+//!      6     2  reserved (must be zero)
+//!      8        contents
+//! ```
+//!
+//! Any change to `Object` or to the types it contains must increment the format version.
+
+use std::io::{self, Read, Write};
+use std::convert::TryInto;
+
+use thiserror::Error;
+
+use super::Object;
+
+/// The first four bytes of every object file
+pub const MAGIC: [u8; 4] = *b"WOBJ";
+
+/// The version of the format written by this version of the assembler
+///
+/// Only object files with exactly this version can be read.
+pub const FORMAT_VERSION: u16 = 1;
+
+const HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error(transparent)]
+    IOError(#[from] io::Error),
+    #[error("Not a wolf object file (missing magic number)")]
+    InvalidMagic,
+    #[error("Object file has format version {found}, but only version {expected} is supported. Try assembling it again.")]
+    UnsupportedVersion {found: u16, expected: u16},
+    #[error("Object file is truncated: expected at least {0} bytes")]
+    Truncated(u64),
+    #[error("Object file has invalid contents: {0}")]
+    InvalidContents(bincode::Error),
+}
+
+impl Object {
+    /// Writes the object file in the format described in this module
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+
+        writer.write_all(&header)?;
+        bincode::serialize_into(writer, self).map_err(|err| match *err {
+            bincode::ErrorKind::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        })
+    }
+
+    /// Reads an object file in the format described in this module
+    pub fn read_from(mut reader: impl Read) -> Result<Self, ReadError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Decodes an object file in the format described in this module
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReadError> {
+        // Check the magic number before anything else so that random files get a clear error
+        if bytes.get(..MAGIC.len()) != Some(&MAGIC[..]) {
+            return Err(ReadError::InvalidMagic);
+        }
+
+        let header = bytes.get(..HEADER_SIZE).ok_or(ReadError::Truncated(HEADER_SIZE as u64))?;
+        let version = u16::from_le_bytes(header[4..6].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(ReadError::UnsupportedVersion {found: version, expected: FORMAT_VERSION});
+        }
+
+        bincode::deserialize(&bytes[HEADER_SIZE..]).map_err(ReadError::InvalidContents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ast;
+    use crate::parser::{Span, SourceFiles};
+    use crate::executable::{Stmt, StaticData, StaticBytes};
+    use crate::object::{Symbol, Section, Relocation};
+
+    fn object() -> Object {
+        let mut source_files = SourceFiles::default();
+        source_files.add_source("lib.wa", b"msg: .b8 msg\n");
+        let span = |start, end| Span {start, end};
+        let msg = |span| ast::Ident {value: "msg".into(), span};
+
+        Object {
+            code_section: Vec::new(),
+            static_section: vec![Stmt::StaticData(StaticData::StaticBytes(StaticBytes::B8([0; 8])))],
+            symbols: vec![Symbol {label: msg(span(0, 3)), section: Section::Static, offset: 0, global: true}],
+            externs: Vec::new(),
+            relocations: vec![Relocation {
+                section: Section::Static,
+                stmt: 0,
+                imm: 0,
+                expr: ast::Expr {kind: ast::ExprKind::Name(msg(span(9, 12))), span: span(9, 12)},
+            }],
+            entry: None,
+            debug_info: None,
+            source_files,
+        }
+    }
+
+    fn to_bytes(object: &Object) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        object.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let object = object();
+        let bytes = to_bytes(&object);
+        assert_eq!(&bytes[..4], b"WOBJ");
+        assert_eq!(Object::from_bytes(&bytes).unwrap(), object);
+        assert_eq!(Object::read_from(&bytes[..]).unwrap(), object);
+    }
+
+    #[test]
+    fn invalid_header() {
+        let bytes = to_bytes(&object());
+
+        assert!(matches!(Object::from_bytes(b""), Err(ReadError::InvalidMagic)));
+        // Executables are not object files
+        assert!(matches!(Object::from_bytes(b"WOLF\x01\x00\x00\x00"), Err(ReadError::InvalidMagic)));
+        assert!(matches!(Object::from_bytes(&bytes[..6]), Err(ReadError::Truncated(8))));
+        assert!(matches!(Object::from_bytes(&bytes[..bytes.len()-1]), Err(ReadError::InvalidContents(_))));
+
+        let mut newer = bytes;
+        newer[4] = 2;
+        let err = Object::from_bytes(&newer).unwrap_err();
+        assert!(matches!(err, ReadError::UnsupportedVersion {found: 2, expected: 1}));
+        assert_eq!(err.to_string(), "Object file has format version 2, but only version 1 is supported. Try assembling it again.");
+    }
+}
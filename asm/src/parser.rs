@@ -90,6 +90,8 @@ enum Expected {
     Kind(TokenKind),
     /// Any arbitrary syntax (rendered with backticks in error message)
     Syntax(&'static str),
+    /// A description of the syntax (rendered as-is in error message)
+    Description(&'static str),
 }
 
 impl From<TokenKind> for Expected {
@@ -110,6 +112,7 @@ impl fmt::Display for Expected {
         match self {
             Kind(kind) => write!(f, "{}", kind),
             Syntax(syntax) => write!(f, "`{}`", syntax),
+            Description(desc) => write!(f, "{}", desc),
         }
     }
 }
@@ -192,6 +195,27 @@ pub fn parse_program(tokens: &[Token], diag: &Diagnostics) -> ast::Program {
     prog
 }
 
+/// Parses a constant definition of the form `NAME value`, where `value` is an expression
+///
+/// This is used for constants defined outside of a source file (e.g. on the command line).
+/// Returns `None` if an error occurred.
+pub fn parse_define(tokens: &[Token], diag: &Diagnostics) -> Option<ast::Const> {
+    let res = ident(tokens).and_parse(expr)
+        .and_parse(|input| tk(input, TokenKind::Eof));
+
+    match res {
+        Ok((_, ((name, value), _))) => {
+            let span = name.span.to(value.span);
+            Some(ast::Const {name, value, span})
+        },
+
+        Err((_, err)) => {
+            diag.span_error(err.actual.span, err.to_string()).emit();
+            None
+        },
+    }
+}
+
 fn program<'a>(mut input: Input<'a>, diag: &Diagnostics) -> (Input<'a>, ast::Program) {
     let mut stmts = Vec::new();
 
@@ -262,15 +286,34 @@ fn extend_stmts<'a>(
 
 fn label(input: Input) -> ParseResult<ast::Ident> {
     ident(input)
+        .or_parse(|| local_label(input))
+        .or_parse(|| numeric_label(input))
         .and_parse(|input| tk(input, TokenKind::Colon))
         .map_output(|(label, _)| label)
 }
 
+/// The names of every directive, none of which may be used as a local label name
+const DIRECTIVES: &[&str] = &[
+    ".static", ".code", ".include", ".const", ".entry", ".global", ".extern", ".macro", ".endm",
+    ".rept", ".endr", ".if", ".ifdef", ".else", ".endif", ".b1", ".b2", ".b4", ".b8", ".zero",
+    ".uninit", ".bytes",
+];
+
 /// Parses the "body" of a statement (i.e. the portion of the stmt without labels or newline)
 fn stmt_body(input: Input) -> ParseResult<ast::Stmt> {
     section_header(input).map_output(ast::Stmt::Section)
         .or_parse(|| include(input).map_output(ast::Stmt::Include))
         .or_parse(|| const_directive(input).map_output(ast::Stmt::Const))
+        .or_parse(|| entry_directive(input).map_output(ast::Stmt::Entry))
+        .or_parse(|| global_directive(input).map_output(ast::Stmt::Global))
+        .or_parse(|| extern_directive(input).map_output(ast::Stmt::Extern))
+        .or_parse(|| macro_directive(input).map_output(ast::Stmt::Macro))
+        .or_parse(|| dot_ident(input, ".endm").map_output(|token| ast::Stmt::EndMacro(token.span)))
+        .or_parse(|| rept_directive(input).map_output(ast::Stmt::Rept))
+        .or_parse(|| dot_ident(input, ".endr").map_output(|token| ast::Stmt::EndRept(token.span)))
+        .or_parse(|| if_directive(input).map_output(ast::Stmt::If))
+        .or_parse(|| dot_ident(input, ".else").map_output(|token| ast::Stmt::Else(token.span)))
+        .or_parse(|| dot_ident(input, ".endif").map_output(|token| ast::Stmt::EndIf(token.span)))
         .or_parse(|| static_data(input).map_output(ast::Stmt::StaticData))
         .or_parse(|| instr(input).map_output(ast::Stmt::Instr))
 }
@@ -294,13 +337,88 @@ fn include(input: Input) -> ParseResult<ast::Include> {
 }
 
 fn const_directive(input: Input) -> ParseResult<ast::Const> {
-    dot_ident(input, ".const").and_parse(ident).and_parse(immediate)
+    dot_ident(input, ".const").and_parse(ident).and_parse(expr)
         .map_output(|((dir, name), value)| {
             let span = dir.span.to(value.span);
             ast::Const {name, value, span}
         })
 }
 
+fn entry_directive(input: Input) -> ParseResult<ast::Entry> {
+    dot_ident(input, ".entry").and_parse(qualified_name)
+        .map_output(|(dir, label)| {
+            let span = dir.span.to(label.span);
+            ast::Entry {label, span}
+        })
+}
+
+fn global_directive(input: Input) -> ParseResult<ast::Global> {
+    dot_ident(input, ".global").and_parse(ident)
+        .map_output(|(dir, label)| {
+            let span = dir.span.to(label.span);
+            ast::Global {label, span}
+        })
+}
+
+fn extern_directive(input: Input) -> ParseResult<ast::Extern> {
+    dot_ident(input, ".extern").and_parse(ident)
+        .map_output(|(dir, label)| {
+            let span = dir.span.to(label.span);
+            ast::Extern {label, span}
+        })
+}
+
+fn macro_directive(input: Input) -> ParseResult<ast::Macro> {
+    let (mut input, (dir, name)) = dot_ident(input, ".macro").and_parse(ident)?;
+    let mut span = dir.span.to(name.span);
+    let mut params = Vec::new();
+
+    // Parameters are optional, but must be separated by commas (no trailing commas allowed)
+    let mut newline_res = match newline(input) {
+        // Do not update `input` so another parser up the stack can consume the newline
+        Ok(_) => return Ok((input, ast::Macro {name, params, span})),
+        Err(newline_err) => Err(newline_err).map(|()| panic!()),
+    };
+
+    loop {
+        // Incorporating the newline error gives a better error message
+        let (next_input, param) = newline_res.or_parse(|| ident(input))?;
+        span = span.to(param.span);
+        params.push(param);
+        input = next_input;
+
+        newline_res = match newline(input) {
+            Ok(_) => break,
+            Err(newline_err) => Err(newline_err),
+        };
+
+        let (next_input, _) = newline_res.clone().map(|_| panic!())
+            .or_parse(|| tk(input, TokenKind::Comma))?;
+        input = next_input;
+    }
+
+    Ok((input, ast::Macro {name, params, span}))
+}
+
+fn rept_directive(input: Input) -> ParseResult<ast::Rept> {
+    dot_ident(input, ".rept").and_parse(integer_lit)
+        .map_output(|(dir, count)| {
+            let span = dir.span.to(count.span);
+            ast::Rept {count, span}
+        })
+}
+
+fn if_directive(input: Input) -> ParseResult<ast::If> {
+    dot_ident(input, ".if").and_parse(expr)
+        .map_output(|(dir, cond)| (dir, cond.span, ast::Condition::Expr(cond)))
+        .or_parse(|| dot_ident(input, ".ifdef").and_parse(ident)
+            .map_output(|(dir, name)| (dir, name.span, ast::Condition::Defined(name))))
+        .map_output(|(dir, cond_span, cond)| {
+            let span = dir.span.to(cond_span);
+            ast::If {cond, span}
+        })
+}
+
 fn static_data(input: Input) -> ParseResult<ast::StaticData> {
     static_bytes(input).map_output(ast::StaticData::StaticBytes)
         .or_parse(|| static_zero(input).map_output(ast::StaticData::StaticZero))
@@ -309,15 +427,31 @@ fn static_data(input: Input) -> ParseResult<ast::StaticData> {
 }
 
 fn static_bytes(input: Input) -> ParseResult<ast::StaticBytes> {
-    dot_ident(input, ".b1").map_output(|tk| (1, tk.span))
+    let (mut input, ((size, dir_span), value)) = dot_ident(input, ".b1").map_output(|tk| (1, tk.span))
         .or_parse(|| dot_ident(input, ".b2").map_output(|tk| (2, tk.span)))
         .or_parse(|| dot_ident(input, ".b4").map_output(|tk| (4, tk.span)))
         .or_parse(|| dot_ident(input, ".b8").map_output(|tk| (8, tk.span)))
-        .and_parse(immediate)
-        .map_output(|((size, dir_span), value)| {
-            let span = dir_span.to(value.span);
-            ast::StaticBytes {size, value, span}
-        })
+        .and_parse(expr)?;
+    let mut span = dir_span.to(value.span);
+    let mut values = vec![value];
+
+    // Any additional values must be separated by commas (no trailing commas allowed)
+    loop {
+        let newline_res = match newline(input) {
+            // Do not update `input` so another parser up the stack can consume the newline
+            Ok(_) => break,
+            Err(newline_err) => Err(newline_err).map(|()| panic!()),
+        };
+
+        // Incorporating the newline error gives a better error message
+        let (next_input, _) = newline_res.or_parse(|| tk(input, TokenKind::Comma))?;
+        let (next_input, value) = expr(next_input)?;
+        span = span.to(value.span);
+        values.push(value);
+        input = next_input;
+    }
+
+    Ok((input, ast::StaticBytes {size, values, span}))
 }
 
 fn static_zero(input: Input) -> ParseResult<ast::StaticZero> {
@@ -393,8 +527,7 @@ fn instr(input: Input) -> ParseResult<ast::Instr> {
 fn instr_arg(input: Input) -> ParseResult<ast::InstrArg> {
     offset_register(input).map_output(ast::InstrArg::Register)
         .or_parse(|| register(input).map_output(ast::InstrArg::Register))
-        .or_parse(|| immediate(input).map_output(ast::InstrArg::Immediate))
-        .or_parse(|| ident(input).map_output(ast::InstrArg::Name))
+        .or_parse(|| expr(input).map_output(ast::InstrArg::from))
 }
 
 fn offset_register(input: Input) -> ParseResult<ast::Register> {
@@ -408,8 +541,108 @@ fn offset_register(input: Input) -> ParseResult<ast::Register> {
         })
 }
 
+/// Binary operators, grouped from lowest to highest precedence
+const BIN_OPS: &[&[(TokenKind, ast::BinOp)]] = &[
+    &[(TokenKind::Pipe, ast::BinOp::Or)],
+    &[(TokenKind::Ampersand, ast::BinOp::And)],
+    &[(TokenKind::ShiftLeft, ast::BinOp::Shl), (TokenKind::ShiftRight, ast::BinOp::Shr)],
+    &[(TokenKind::Plus, ast::BinOp::Add), (TokenKind::Minus, ast::BinOp::Sub)],
+    &[(TokenKind::Star, ast::BinOp::Mul), (TokenKind::Slash, ast::BinOp::Div)],
+];
+
+/// Parses a constant expression, e.g. `end - start`, `(SIZE + 1) * 8`, `. + 16`
+fn expr(input: Input) -> ParseResult<ast::Expr> {
+    binary_expr(input, BIN_OPS)
+}
+
+/// Parses operands separated by any of the operators in the first group of `ops`
+///
+/// Each operand may contain operators from the remaining (higher precedence) groups.
+fn binary_expr<'a>(input: Input<'a>, ops: &[&[(TokenKind, ast::BinOp)]]) -> ParseResult<'a, ast::Expr> {
+    let (level_ops, higher_ops) = match ops.split_first() {
+        Some(split) => split,
+        None => return unary_expr(input),
+    };
+
+    let (mut input, mut lhs) = binary_expr(input, higher_ops)?;
+    loop {
+        let op = level_ops.iter()
+            .find_map(|&(kind, op)| tk(input, kind).ok().map(|(next_input, _)| (next_input, op)));
+
+        let (next_input, op) = match op {
+            Some(op) => op,
+
+            // The lexer treats a `-` immediately followed by a digit as part of a negative
+            // literal, so `a -1` is parsed as `a + -1` (without consuming the literal)
+            None if level_ops.contains(&(TokenKind::Minus, ast::BinOp::Sub)) && negative_integer_lit(input) => {
+                (input, ast::BinOp::Add)
+            },
+
+            None => break,
+        };
+
+        let (next_input, rhs) = binary_expr(next_input, higher_ops)?;
+        input = next_input;
+
+        let span = lhs.span.to(rhs.span);
+        lhs = ast::Expr {
+            kind: ast::ExprKind::BinOp(op, Box::new(lhs), Box::new(rhs)),
+            span,
+        };
+    }
+
+    Ok((input, lhs))
+}
+
+fn unary_expr(input: Input) -> ParseResult<ast::Expr> {
+    tk(input, TokenKind::Minus).and_parse(unary_expr)
+        .map_output(|(minus, expr)| ast::Expr {
+            span: minus.span.to(expr.span),
+            kind: ast::ExprKind::Neg(Box::new(expr)),
+        })
+        .or_parse(|| primary_expr(input))
+}
+
+fn primary_expr(input: Input) -> ParseResult<ast::Expr> {
+    integer_lit(input).map_output(|lit| ast::Expr {
+        kind: ast::ExprKind::Integer(lit.value),
+        span: lit.span,
+    })
+        .or_parse(|| tk(input, TokenKind::Literal(LitKind::Float)).map_output(|token| ast::Expr {
+            kind: ast::ExprKind::Float(token.unwrap_float()),
+            span: token.span,
+        }))
+        .or_parse(|| qualified_name(input).map_output(|name| ast::Expr {
+            span: name.span,
+            kind: ast::ExprKind::Name(name),
+        }))
+        .or_parse(|| local_label(input).or_parse(|| numeric_label_ref(input)).map_output(|name| ast::Expr {
+            span: name.span,
+            kind: ast::ExprKind::Name(name),
+        }))
+        .or_parse(|| tk(input, TokenKind::Dot).map_output(|token| ast::Expr {
+            kind: ast::ExprKind::Here,
+            span: token.span,
+        }))
+        .or_parse(|| {
+            tk(input, TokenKind::ParenOpen)
+                .and_parse(expr)
+                .and_parse(|input| tk(input, TokenKind::ParenClose))
+                .map_output(|((open, expr), close)| ast::Expr {
+                    span: open.span.to(close.span),
+                    ..expr
+                })
+        })
+}
+
+/// Returns true if the next token is a negative integer literal
+fn negative_integer_lit(input: Input) -> bool {
+    matches!(integer_lit(input), Ok((_, lit)) if lit.value < 0)
+}
+
 fn immediate(input: Input) -> ParseResult<ast::Immediate> {
     integer_lit(input)
+        .or_parse(|| float_lit(input))
 }
 
 fn ident(input: Input) -> ParseResult<ast::Ident> {
@@ -419,6 +652,64 @@ fn ident(input: Input) -> ParseResult<ast::Ident> {
     })
 }
 
+/// Parses the name of a local label, e.g. `.loop`
+///
+/// The name includes the leading `.` until it is qualified with the name of its enclosing label.
+/// Directive names cannot be used as local label names.
+fn local_label(input: Input) -> ParseResult<ast::Ident> {
+    let (next_input, token) = advance(input);
+    match token.kind {
+        TokenKind::DotIdent if !DIRECTIVES.contains(&&**token.unwrap_ident()) => {
+            Ok((next_input, ast::Ident {
+                value: token.unwrap_ident().clone(),
+                span: token.span,
+            }))
+        },
+
+        _ => Err((input, ParseError {
+            expected: vec![Expected::Description("a local label")],
+            actual: token,
+        })),
+    }
+}
+
+/// Parses a name that may refer to a local label in any scope, e.g. `main.loop`
+///
+/// There must be no whitespace between the global label name and the local label name.
+fn qualified_name(input: Input) -> ParseResult<ast::Ident> {
+    let (next_input, name) = ident(input)?;
+    match local_label(next_input) {
+        Ok((next_input, local)) if name.span.end == local.span.start => {
+            Ok((next_input, ast::Ident {
+                value: format!("{}{}", name.value, local.value).into(),
+                span: name.span.to(local.span),
+            }))
+        },
+        _ => Ok((next_input, name)),
+    }
+}
+
+fn numeric_label(input: Input) -> ParseResult<ast::Ident> {
+    integer_lit(input).and_then(|(next_input, lit)| {
+        if lit.value >= 0 {
+            Ok((next_input, ast::Ident {value: lit.value.to_string().into(), span: lit.span}))
+        } else {
+            // Report the error at the literal rather than at the `:` after it
+            Err((input, ParseError {
+                expected: vec![TokenKind::Ident.into()],
+                actual: &input[0],
+            }))
+        }
+    })
+}
+
+fn numeric_label_ref(input: Input) -> ParseResult<ast::Ident> {
+    tk(input, TokenKind::NumericLabelRef).map_output(|token| ast::Ident {
+        value: token.unwrap_ident().clone(),
+        span: token.span,
+    })
+}
+
 fn register(input: Input) -> ParseResult<ast::Register> {
     tk(input, TokenKind::Register).map_output(|token| ast::Register {
         kind: token.unwrap_register().into(),
@@ -441,6 +732,14 @@ fn integer_lit(input: Input) -> ParseResult<ast::Integer> {
     })
 }
 
+/// Parses a floating point literal as an integer containing its IEEE 754 binary64 bit pattern
+fn float_lit(input: Input) -> ParseResult<ast::Integer> {
+    tk(input, TokenKind::Literal(LitKind::Float)).map_output(|token| ast::Integer {
+        value: token.unwrap_float().to_bits() as i128,
+        span: token.span,
+    })
+}
+
 fn newline(input: Input) -> ParseResult<()> {
     tk(input, TokenKind::Newline).map_output(|_| ())
 }
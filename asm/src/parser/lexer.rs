@@ -42,6 +42,14 @@ impl<'a> Lexer<'a> {
             (b'(', _) => Ok(self.byte_token(start, ParenOpen)),
             (b')', _) => Ok(self.byte_token(start, ParenClose)),
 
+            (b'+', _) => Ok(self.byte_token(start, Plus)),
+            (b'*', _) => Ok(self.byte_token(start, Star)),
+            (b'/', _) => Ok(self.byte_token(start, Slash)),
+            (b'&', _) => Ok(self.byte_token(start, Ampersand)),
+            (b'|', _) => Ok(self.byte_token(start, Pipe)),
+            (b'<', Some(b'<')) => Ok(self.two_byte_token(start, ShiftLeft)),
+            (b'>', Some(b'>')) => Ok(self.two_byte_token(start, ShiftRight)),
+
             (b'\n', _) => Ok(self.byte_token(start, Newline)),
 
             (b'"', _) |
@@ -49,10 +57,12 @@ impl<'a> Lexer<'a> {
 
             (b'0' ..= b'9', _) |
             (b'-', Some(b'0' ..= b'9')) => self.integer_lit(start, current_char),
+            (b'-', _) => Ok(self.byte_token(start, Minus)),
 
             (b'.', Some(b'a' ..= b'z')) |
             (b'.', Some(b'A' ..= b'Z')) |
             (b'.', Some(b'_')) => Ok(self.dot_ident(start)),
+            (b'.', _) => Ok(self.byte_token(start, Dot)),
 
             (b'a' ..= b'z', _) |
             (b'A' ..= b'Z', _) |
@@ -244,7 +254,7 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    /// Parses an integer literal, given a starting digit or negative sign
+    /// Parses an integer or floating point literal, given a starting digit or negative sign
     ///
     /// The produced value will be 128-bits, but it will not exceed the range [i64::min(), u64::max()]
     fn integer_lit(&mut self, start: usize, start_byte: u8) -> Result<Token, Token> {
@@ -252,7 +262,20 @@ impl<'a> Lexer<'a> {
         let value = match (start_byte, self.scanner.peek()) {
             (b'0', Some(b'x')) => self.hex_lit_value(start)?,
             (b'0', Some(b'b')) => self.binary_lit_value(start)?,
-            _ => self.decimal_lit_value(start, start_byte)?,
+            _ => {
+                let value = self.decimal_lit_value(start, start_byte)?;
+
+                // A decimal point or an exponent turns the literal into a floating point literal
+                if self.float_lit_follows() {
+                    return self.float_lit(start);
+                }
+                // A `b` or `f` suffix turns the literal into a numeric label reference
+                if start_byte.is_ascii_digit() && self.numeric_label_ref_follows() {
+                    return Ok(self.numeric_label_ref(start, value));
+                }
+
+                value
+            },
         };
 
         if value < i64::MIN as i128 || value > u64::MAX as i128 {
@@ -261,7 +284,82 @@ impl<'a> Lexer<'a> {
             return Err(token);
         }
 
-        // An integer cannot be directly followed by an identifier with no whitespace in between
+        self.literal_end(start, "invalid integer literal")?;
+
+        let value = TokenValue::Integer(value);
+        Ok(self.token_to_current(start, Literal(LitKind::Integer), value))
+    }
+
+    /// Returns true if the next characters continue a decimal literal into a floating point literal
+    fn float_lit_follows(&self) -> bool {
+        matches!(
+            (self.scanner.peek(), self.scanner.peek_second()),
+            (Some(b'.'), Some(b'0' ..= b'9')) |
+            (Some(b'e'), Some(b'0' ..= b'9' | b'+' | b'-')) |
+            (Some(b'E'), Some(b'0' ..= b'9' | b'+' | b'-'))
+        )
+    }
+
+    /// Returns true if the next character is the `b` or `f` suffix of a numeric label reference
+    fn numeric_label_ref_follows(&self) -> bool {
+        matches!(self.scanner.peek(), Some(b'b' | b'f' | b'B' | b'F')) &&
+            !matches!(self.scanner.peek_second(), Some(ch) if ch.is_ascii_alphanumeric() || ch == b'_')
+    }
+
+    /// Parses the suffix of a numeric label reference (e.g. `1b`, `2f`), assuming that the label
+    /// number has already been parsed
+    fn numeric_label_ref(&mut self, start: usize, label: i128) -> Token {
+        let dir = self.scanner.next().expect("bug: expected a numeric label reference suffix");
+        // Identifiers are case-insensitive
+        let value = format!("{}{}", label, dir.to_ascii_lowercase() as char);
+        let value = TokenValue::Ident(self.intern_str(value));
+        self.token_to_current(start, NumericLabelRef, value)
+    }
+
+    /// Parses the remainder of a floating point literal, assuming that the integer part (and its
+    /// sign) has already been parsed
+    fn float_lit(&mut self, start: usize) -> Result<Token, Token> {
+        // Fractional part
+        if self.scanner.peek() == Some(b'.') {
+            self.scanner.next();
+            self.digits(false, None);
+        }
+
+        // Exponent
+        if matches!(self.scanner.peek(), Some(b'e') | Some(b'E')) {
+            self.scanner.next();
+            if matches!(self.scanner.peek(), Some(b'+') | Some(b'-')) {
+                self.scanner.next();
+            }
+
+            let digits = self.digits(false, None);
+            if digits == 0 {
+                let token = self.token_to_current(start, Error, None);
+                self.diag.span_error(token.span, "invalid floating point literal, expected exponent").emit();
+                return Err(token);
+            }
+        }
+
+        self.literal_end(start, "invalid floating point literal")?;
+
+        let text = self.scanner.slice(start, self.scanner.current_pos()).replace('_', "");
+        // The code above guarantees that the text has valid floating point syntax
+        let value: f64 = text.parse()
+            .expect("bug: should have had a valid floating point literal");
+
+        if !value.is_finite() {
+            let token = self.token_to_current(start, Error, None);
+            self.diag.span_error(token.span, "floating point literal out of 64-bit range").emit();
+            return Err(token);
+        }
+
+        let value = TokenValue::Float(value);
+        Ok(self.token_to_current(start, Literal(LitKind::Float), value))
+    }
+
+    /// Checks that a numeric literal is not directly followed by an identifier
+    fn literal_end(&mut self, start: usize, message: &str) -> Result<(), Token> {
+        // A number cannot be directly followed by an identifier with no whitespace in between
         if matches!(self.scanner.peek(), Some(b'a'..=b'z') | Some(b'A'..=b'Z')) {
             // Skip the first character
             self.scanner.next();
@@ -279,12 +377,11 @@ impl<'a> Lexer<'a> {
             }
 
             let token = self.token_to_current(start, Error, None);
-            self.diag.span_error(token.span, "invalid integer literal").emit();
+            self.diag.span_error(token.span, message).emit();
             return Err(token);
         }
 
-        let value = TokenValue::Integer(value);
-        Ok(self.token_to_current(start, Literal(LitKind::Integer), value))
+        Ok(())
     }
 
     fn hex_lit_value(&mut self, start: usize) -> Result<i128, Token> {
@@ -507,6 +604,12 @@ impl<'a> Lexer<'a> {
         Token {kind, span, value: None}
     }
 
+    fn two_byte_token(&mut self, start: usize, kind: TokenKind) -> Token {
+        // Consume the second byte
+        self.scanner.next();
+        self.token_to_current(start, kind, None)
+    }
+
     fn token_to_current(&self, start: usize, kind: TokenKind, value: impl Into<Option<TokenValue>>) -> Token {
         let span = self.scanner.span(start, self.scanner.current_pos());
         Token {kind, span, value: value.into()}
@@ -567,12 +670,24 @@ mod tests {
         );
     }
 
+    macro_rules! label_ref {
+        ($value:expr) => (
+            t!(NumericLabelRef, TokenValue::Ident($value.into()))
+        );
+    }
+
     macro_rules! int {
         ($value:expr) => (
             t!(Literal(LitKind::Integer), TokenValue::Integer($value))
         );
     }
 
+    macro_rules! float {
+        ($value:expr) => (
+            t!(Literal(LitKind::Float), TokenValue::Float($value))
+        );
+    }
+
     macro_rules! reg {
         ($value:expr) => (
             t!(Register, TokenValue::Register($value.into()))
@@ -651,6 +766,24 @@ mod tests {
             t!(ParenClose), t!(ParenClose), t!(ParenClose), t!(ParenClose)]);
     }
 
+    #[test]
+    fn operators() {
+        expect_tokens!(b"+ - * / << >> & |", &[t!(Plus), t!(Minus), t!(Star), t!(Slash),
+            t!(ShiftLeft), t!(ShiftRight), t!(Ampersand), t!(Pipe)]);
+        // A `-` immediately followed by a digit is still a negative literal
+        expect_tokens!(b"a-1 a - 1 -(1)", &[ident!("a"), int!(-1), ident!("a"), t!(Minus), int!(1),
+            t!(Minus), t!(ParenOpen), int!(1), t!(ParenClose)]);
+        expect_error!(b"<");
+        expect_error!(b">");
+    }
+
+    #[test]
+    fn location_counter() {
+        expect_token!(b".", t!(Dot));
+        expect_tokens!(b". + 8", &[t!(Dot), t!(Plus), int!(8)]);
+        expect_tokens!(b".-8", &[t!(Dot), int!(-8)]);
+    }
+
     #[test]
     fn newline() {
         expect_token!(b"\n", t!(Newline));
@@ -737,6 +870,38 @@ mod tests {
         expect_tokens!(b"0bF", &[t!(Error), ident!("f")]);
     }
 
+    #[test]
+    fn float_literals() {
+        expect_token!(b"0.0", float!(0.0));
+        expect_token!(b"1.5", float!(1.5));
+        expect_token!(b"-0.25", float!(-0.25));
+        expect_token!(b"003.125", float!(3.125));
+        expect_token!(b"1_000.000_5", float!(1000.0005));
+        expect_token!(b"1e9", float!(1e9));
+        expect_token!(b"1E9", float!(1e9));
+        expect_token!(b"2.5e-3", float!(2.5e-3));
+        expect_token!(b"-6.022_140e+23", float!(-6.022140e23));
+        expect_token!(b"1.7976931348623157e308", float!(f64::MAX));
+    }
+
+    #[test]
+    fn float_literals_invalid() {
+        // out of range
+        expect_error!(b"1e309");
+        expect_error!(b"-1.8e308");
+
+        // missing exponent digits
+        expect_error!(b"1e+");
+        expect_error!(b"1.5e-");
+
+        // directly followed by an identifier
+        expect_error!(b"1.5f");
+        expect_error!(b"2e3abc");
+
+        // not a float literal, the `.` must be followed by a digit
+        expect_tokens!(b"1.a", &[int!(1), dot_ident!(".a")]);
+    }
+
     #[test]
     fn bytes() {
         let bytes_value = |bytes: &[u8]| TokenValue::Bytes(bytes.into());
@@ -804,9 +969,29 @@ mod tests {
         expect_token!(b".abc_efod_fso2190_123___", dot_ident!(".abc_efod_fso2190_123___"));
     }
 
+    #[test]
+    fn numeric_label_refs() {
+        expect_token!(b"1b", label_ref!("1b"));
+        expect_token!(b"2f", label_ref!("2f"));
+        expect_token!(b"10F", label_ref!("10f"));
+        expect_token!(b"0f", label_ref!("0f"));
+        expect_token!(b"01b", label_ref!("1b"));
+        expect_tokens!(b"1b+2f", &[label_ref!("1b"), t!(Plus), label_ref!("2f")]);
+    }
+
+    #[test]
+    fn numeric_label_refs_invalid() {
+        // `0b` is always the start of a binary literal
+        expect_error!(b"0b");
+        expect_error!(b"1bf");
+        expect_error!(b"1b2");
+        expect_error!(b"-1b");
+    }
+
     #[test]
     fn dot_idents_invalid() {
-        expect_tokens!(b".1ab132c", &[t!(Error), t!(Error)]);
+        // A `.` on its own is the location counter
+        expect_tokens!(b".1ab132c", &[t!(Dot), t!(Error)]);
     }
 
     #[test]
@@ -34,6 +34,12 @@ impl<'a> Scanner<'a> {
         self.source.get(self.current)
     }
 
+    /// Returns the character after the next character in the source text, but does not advance
+    /// the scanner
+    pub fn peek_second(&self) -> Option<u8> {
+        self.source.get(self.current + 1)
+    }
+
     /// Creates a new span that is empty (from `index` to `index`)
     pub fn empty_span(&self, index: usize) -> Span {
         self.span(index, index)
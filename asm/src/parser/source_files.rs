@@ -1,8 +1,11 @@
 use std::fs;
+use std::sync::Arc;
 use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 use std::ops::Range;
 
+use serde::{Serialize, Deserialize};
+
 use super::span::Span;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -56,7 +59,7 @@ impl<'a> FileSource<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct LineNumbers {
     /// The index in `SourceFiles::source` of the first byte in each line
     ///
@@ -110,15 +113,58 @@ impl LineNumbers {
 
         (line, offset)
     }
+
+    /// Returns the range of indexes in the source file that make up the given 1-based line
+    /// number, including its trailing newline (if any)
+    pub fn line_range(&self, line: usize) -> Range<usize> {
+        let start = self.offsets[line-1];
+        // The index one past the end of the file is on a line of its own
+        let end = self.offsets.get(line).copied().unwrap_or(start);
+        start..end
+    }
 }
 
-#[derive(Debug)]
+/// Describes where the source of a macro expansion came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Expansion {
+    /// The name of the macro that was expanded
+    pub name: Arc<str>,
+    /// The span of the code that invoked the macro
+    pub call_site: Span,
+    /// The span of the macro body that was copied to produce the expansion
+    pub body: Span,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 struct File {
     path: PathBuf,
     /// The index into `SourceFiles::source` that represents the start of this file
     start_offset: usize,
     /// An index of the line numbers for all offsets in the file
     line_numbers: LineNumbers,
+    /// If this file is a copy of a macro body, describes the expansion that it is a part of
+    expansion: Option<Expansion>,
+}
+
+impl File {
+    /// Moves every offset in this file forward by `offset` bytes
+    fn shift(self, offset: usize) -> Self {
+        let Self {path, start_offset, line_numbers, expansion} = self;
+        let LineNumbers {offsets} = line_numbers;
+
+        Self {
+            path,
+            start_offset: start_offset + offset,
+            line_numbers: LineNumbers {
+                offsets: offsets.into_iter().map(|line_offset| line_offset + offset).collect(),
+            },
+            expansion: expansion.map(|Expansion {name, call_site, body}| Expansion {
+                name,
+                call_site: call_site.shift(offset),
+                body: body.shift(offset),
+            }),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -130,7 +176,7 @@ pub struct FilePos<'a> {
     pub end_offset: usize,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct SourceFiles {
     /// The source code of all files concatenated together.
     ///
@@ -168,6 +214,36 @@ impl SourceFiles {
         self.create_handle(path, start, len)
     }
 
+    /// Adds a copy of a macro body to the set of source files so that it can be parsed with spans
+    /// that are unique to this expansion of the macro. Returns a handle to the copy.
+    ///
+    /// Positions within the copy are reported as positions in the original macro body.
+    pub fn add_expansion(&mut self, expansion: Expansion) -> FileHandle {
+        let start = self.source.len();
+        let len = expansion.body.end - expansion.body.start;
+
+        self.source.extend_from_within(expansion.body.start..expansion.body.end);
+
+        let path = self.path(expansion.body.start).to_path_buf();
+        let handle = self.create_handle(&path, start, len);
+        self.files.last_mut().unwrap().expansion = Some(expansion);
+        handle
+    }
+
+    /// Appends every file in `other` to this set of source files
+    ///
+    /// Returns the offset that must be added to every span from `other` (see `Span::shift`) to
+    /// get the corresponding span in this set of source files.
+    pub fn append(&mut self, other: SourceFiles) -> usize {
+        let offset = self.source.len();
+        let SourceFiles {source, files} = other;
+
+        self.source.extend(source);
+        self.files.extend(files.into_iter().map(|file| file.shift(offset)));
+
+        offset
+    }
+
     fn create_handle(&mut self, path: &Path, start: usize, len: usize) -> FileHandle {
         let handle = FileHandle {start, len};
         let source = self.source(handle);
@@ -176,12 +252,52 @@ impl SourceFiles {
             path: path.to_path_buf(),
             start_offset: start,
             line_numbers,
+            expansion: None,
         });
         handle
     }
 
+    /// Returns the macro expansion that the given span is a part of, if any
+    pub fn expansion(&self, span: Span) -> Option<&Expansion> {
+        self.file(span.start).expansion.as_ref()
+    }
+
+    /// Returns true if both spans are in the same file
+    pub fn same_file(&self, span1: Span, span2: Span) -> bool {
+        let file1 = self.file(span1.start);
+        let file2 = self.file(span2.start);
+        file1.start_offset == file2.start_offset
+    }
+
+    /// Returns the source for the given span, which may be any part of a file
+    pub fn span_source(&self, span: Span) -> FileSource<'_> {
+        FileSource {
+            bytes: &self.source[span.start..span.end],
+            offset: span.start,
+        }
+    }
+
+    /// Maps a span in a macro expansion back to the corresponding span in the original source
+    fn original_span(&self, mut span: Span) -> Span {
+        // Loop since a macro body may itself be part of a macro expansion
+        loop {
+            let file = self.file(span.start);
+            match &file.expansion {
+                Some(expansion) => {
+                    let offset = expansion.body.start.wrapping_sub(file.start_offset);
+                    span = Span {
+                        start: span.start.wrapping_add(offset),
+                        end: span.end.wrapping_add(offset),
+                    };
+                },
+                None => return span,
+            }
+        }
+    }
+
     /// Returns the resolved file and position information for a span
     pub fn pos(&self, span: Span) -> FilePos {
+        let span = self.original_span(span);
         let File {path, line_numbers, ..} = self.file(span.start);
         let (start_line, start_offset) = line_numbers.number_offset(span.start);
         // Subtract 1 because end actually represents one past the end of the span
@@ -190,6 +306,29 @@ impl SourceFiles {
         FilePos {path, start_line, start_offset, end_line, end_offset}
     }
 
+    /// Returns the range of bytes within its file that the given span covers
+    ///
+    /// Spans in a macro expansion return the range in the original macro body.
+    pub fn file_range(&self, span: Span) -> Range<usize> {
+        let span = self.original_span(span);
+        let File {start_offset, ..} = self.file(span.start);
+        span.start - start_offset..span.end - start_offset
+    }
+
+    /// Returns the source code of the line containing the start of the given span, without its
+    /// trailing newline
+    ///
+    /// Spans in a macro expansion return the line from the original macro body.
+    pub fn line_source(&self, span: Span) -> &[u8] {
+        let span = self.original_span(span);
+        let File {line_numbers, ..} = self.file(span.start);
+        let (line, _) = line_numbers.number_offset(span.start);
+        let line_source = &self.source[line_numbers.line_range(line)];
+
+        let line_source = line_source.strip_suffix(b"\n").unwrap_or(line_source);
+        line_source.strip_suffix(b"\r").unwrap_or(line_source)
+    }
+
     /// Returns the path of the file whose source contains the given index
     pub fn path(&self, index: usize) -> &Path {
         &self.file(index).path
@@ -1,8 +1,10 @@
+use serde::{Serialize, Deserialize};
+
 /// A span of a source file
 ///
 /// The only invariant is that a span `start` and `end` indexes MUST remain within the boundaries
 /// of a single file. That is, you can never span two files at the same time.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Span {
     /// The start index of the span (inclusive)
     pub start: usize,
@@ -20,4 +22,15 @@ impl Span {
             end: other.end,
         }
     }
+
+    /// Moves this span forward by `offset` bytes
+    ///
+    /// Used when the source files that this span refers to are appended to another set of source
+    /// files (see `SourceFiles::append`).
+    pub fn shift(self, offset: usize) -> Self {
+        Self {
+            start: self.start + offset,
+            end: self.end + offset,
+        }
+    }
 }
@@ -32,6 +32,11 @@ pub enum LitKind {
     /// An integer literal, e.g. `0`, `1`, `-402`, `1_000_000`, `0x1f3`, `0b0100_1000`
     Integer,
 
+    /// A 64-bit floating point literal, e.g. `0.5`, `-3.25`, `1e9`, `6.022_140e23`, `1.5E-3`
+    ///
+    /// Must contain either a decimal point or an exponent to be distinguished from an integer.
+    Float,
+
     /// A string literal, interpreted as a series of bytes
     ///
     /// The literal may contain escaped characters which will be unescaped during lexing.
@@ -45,6 +50,7 @@ impl fmt::Display for LitKind {
         use LitKind::*;
         match self {
             Integer => write!(f, "an integer"),
+            Float => write!(f, "a floating point number"),
             Bytes => write!(f, "a byte string literal"),
         }
     }
@@ -60,6 +66,11 @@ pub enum TokenKind {
     /// An identifier
     Ident,
 
+    /// A reference to the nearest numeric label before or after it, e.g. `1b`, `2f`
+    ///
+    /// The value is stored as an identifier without any leading zeros in the label number.
+    NumericLabelRef,
+
     /// A register, e.g. `$0`, `$1`, `$63`, `$sp`, `$fp`, etc.
     Register,
 
@@ -76,6 +87,25 @@ pub enum TokenKind {
     /// A `)` character
     ParenClose,
 
+    /// A `+` character
+    Plus,
+    /// A `-` character that is not part of a negative literal
+    Minus,
+    /// A `*` character
+    Star,
+    /// A `/` character
+    Slash,
+    /// The `<<` operator
+    ShiftLeft,
+    /// The `>>` operator
+    ShiftRight,
+    /// A `&` character
+    Ampersand,
+    /// A `|` character
+    Pipe,
+    /// A `.` character that is not part of a dot identifier, used as the location counter
+    Dot,
+
     /// The `\n` character
     Newline,
 
@@ -93,12 +123,22 @@ impl fmt::Display for TokenKind {
             Keyword(kw) => write!(f, "{}", kw),
             DotIdent => write!(f, "`.`"),
             Ident => write!(f, "an identifier"),
+            NumericLabelRef => write!(f, "a numeric label reference"),
             Register => write!(f, "a register"),
             Literal(lit) => write!(f, "{}", lit),
             Colon => write!(f, "`:`"),
             Comma => write!(f, "`,`"),
             ParenOpen => write!(f, "`(`"),
             ParenClose => write!(f, "`)`"),
+            Plus => write!(f, "`+`"),
+            Minus => write!(f, "`-`"),
+            Star => write!(f, "`*`"),
+            Slash => write!(f, "`/`"),
+            ShiftLeft => write!(f, "`<<`"),
+            ShiftRight => write!(f, "`>>`"),
+            Ampersand => write!(f, "`&`"),
+            Pipe => write!(f, "`|`"),
+            Dot => write!(f, "`.`"),
             Newline => write!(f, "a newline"),
             Eof => write!(f, "end of file"),
 
@@ -150,6 +190,11 @@ pub enum TokenValue {
     /// Needs to be 128 bits to fit the range of both i64 and u64
     Integer(i128),
 
+    /// A floating point literal value
+    ///
+    /// Guaranteed to be finite
+    Float(f64),
+
     /// The unescaped bytes from a byte string literal
     Bytes(Arc<[u8]>),
 }
@@ -187,6 +232,14 @@ impl Token {
         }
     }
 
+    /// Returns the value of this token as a floating point number or panics
+    pub fn unwrap_float(&self) -> f64 {
+        match self.value {
+            Some(TokenValue::Float(value)) => value,
+            _ => unreachable!("bug: expected a floating point number"),
+        }
+    }
+
     /// Returns the value of this token as a byte string or panics
     pub fn unwrap_bytes(&self) -> &Arc<[u8]> {
         match &self.value {
@@ -4,23 +4,33 @@ use crate::ast;
 use crate::asm;
 use crate::diagnostics::Diagnostics;
 use crate::const_table::ConstTable;
+use crate::local_labels::{qualify_local_labels, is_numeric_label};
 
 /// Validates the program to ensure that it is valid assembly
 ///
 /// Constant names will be subsituted for their immediate values. The only remaining identifiers
 /// in the body of a statement will be a label name. The remaining label names will still need to
 /// be checked later to make sure that they are defined somewhere in the program.
-pub fn validate_program(prog: ast::Program, diag: &Diagnostics) -> asm::Program {
-    let labels = unique_labels(&prog, &diag);
+///
+/// Local and numeric labels are qualified with the name of the global label that precedes them
+/// (e.g. `.loop` after `main:` becomes `main.loop`).
+///
+/// The constants in `defines` are defined before any of the constants in the program.
+pub fn validate_program(prog: ast::Program, defines: &[ast::Const], diag: &Diagnostics) -> asm::Program {
+    let prog = qualify_local_labels(prog);
+    let all_labels = unique_labels(&prog, &diag);
     // Error recovery: No checking if the unique labels generated errors because we can still
     // continue processing the program even if errors occurred during that process.
-    let consts = ConstTable::new(&prog, diag, &labels);
+    let consts = ConstTable::new(&prog, defines, diag, &all_labels);
     // Error recovery: No checking if the constant table generated errors because we still want to
     // continue and potentially find more errors if we can during the validation process. This may
     // result in some false negatives, but is still a better user experience overall in many cases.
 
     let mut code_section: Option<asm::Section> = None;
     let mut static_section: Option<asm::Section> = None;
+    let mut entry: Option<ast::Entry> = None;
+    let mut globals = Vec::new();
+    let mut externs = Vec::new();
     let mut stmts = None;
     let mut labels = Vec::new();
     for stmt in prog.stmts {
@@ -65,12 +75,49 @@ pub fn validate_program(prog: ast::Program, diag: &Diagnostics) -> asm::Program
             },
 
             ast::Stmt::Include(_) => unreachable!("bug: all includes should be resolved by now"),
+            ast::Stmt::Macro(_) |
+            ast::Stmt::EndMacro(_) |
+            ast::Stmt::Rept(_) |
+            ast::Stmt::EndRept(_) => unreachable!("bug: all macros should be expanded by now"),
+            ast::Stmt::If(_) |
+            ast::Stmt::Else(_) |
+            ast::Stmt::EndIf(_) => unreachable!("bug: all conditionals should be expanded by now"),
 
             // Already handled above
             ast::Stmt::Const(_) => continue,
 
+            ast::Stmt::Entry(entry_stmt) => {
+                match &entry {
+                    Some(prev) => diag.span_error(entry_stmt.span, "duplicate `.entry` directive")
+                        .span_note(prev.span, "previously declared here").emit(),
+                    None => entry = Some(entry_stmt),
+                }
+
+                continue;
+            },
+
+            ast::Stmt::Global(ast::Global {label, span: _}) => {
+                if !all_labels.contains(&label) {
+                    diag.span_error(label.span, format!("unknown label `{}`", label)).emit();
+                }
+
+                globals.push(label);
+                continue;
+            },
+
+            ast::Stmt::Extern(ast::Extern {label, span: _}) => {
+                if let Some(def) = all_labels.get(&label) {
+                    diag.span_error(label.span, format!("label `{}` is declared `.extern` but is also defined in this file", label))
+                        .span_note(def.span, "label defined here")
+                        .emit();
+                }
+
+                externs.push(label);
+                continue;
+            },
+
             ast::Stmt::StaticData(static_data) => {
-                asm::StmtKind::StaticData(validate_static_data(static_data, diag))
+                asm::StmtKind::StaticData(validate_static_data(static_data, &consts, diag))
             },
 
             ast::Stmt::Instr(instr) => {
@@ -91,13 +138,61 @@ pub fn validate_program(prog: ast::Program, diag: &Diagnostics) -> asm::Program
         }
     }
 
-    asm::Program {code_section, static_section}
+    let entry = validate_entry(entry, code_section.as_ref(), &all_labels, diag);
+
+    // Any remaining labels were not followed by a statement
+    let end_labels = labels;
+
+    // Kept so that they can be included in listings
+    let consts = consts.into_consts();
+
+    asm::Program {code_section, static_section, entry, end_labels, globals, externs, consts}
+}
+
+/// Finds the label of the first instruction to execute
+///
+/// This is the label given to the `.entry` directive (if any), or otherwise the `main` label (if
+/// it is defined in the `.code` section).
+fn validate_entry(
+    entry: Option<ast::Entry>,
+    code_section: Option<&asm::Section>,
+    all_labels: &HashSet<ast::Ident>,
+    diag: &Diagnostics,
+) -> Option<asm::Ident> {
+    let code_label = |name: &str| code_section.into_iter()
+        .flat_map(|section| &section.stmts)
+        .flat_map(|stmt| &stmt.labels)
+        .find(|label| &*label.value == name)
+        .cloned();
+
+    let ast::Entry {label, span} = match entry {
+        Some(entry) => entry,
+        None => return code_label("main"),
+    };
+
+    match code_label(&label.value) {
+        Some(_) => Some(label),
+
+        None if all_labels.contains(&label) => {
+            diag.span_error(span, format!("entry point `{}` must label an instruction in the `.code` section", label))
+                .span_note(all_labels.get(&label).unwrap().span, "label defined here")
+                .emit();
+            None
+        },
+
+        None => {
+            diag.span_error(label.span, format!("unknown label `{}`", label)).emit();
+            None
+        },
+    }
 }
 
 /// Attempts to ensure that all label names are unique
 ///
 /// Returns the set of all label names in the program, including, in the case of an error, label
 /// names that may have been defined more than once.
+///
+/// Numeric labels (e.g. `main.1`) may be defined any number of times.
 fn unique_labels(prog: &ast::Program, diag: &Diagnostics) -> HashSet<ast::Ident> {
     let mut labels: HashSet<ast::Ident> = HashSet::new();
 
@@ -108,6 +203,8 @@ fn unique_labels(prog: &ast::Program, diag: &Diagnostics) -> HashSet<ast::Ident>
         };
 
         match labels.get(label) {
+            Some(_) if is_numeric_label(&label.value) => continue,
+
             Some(other_label) => {
                 diag.span_error(label.span, format!("duplicate label name `{}`", label))
                     .span_note(other_label.span, "originally defined here")
@@ -126,10 +223,10 @@ fn unique_labels(prog: &ast::Program, diag: &Diagnostics) -> HashSet<ast::Ident>
 }
 
 /// Validates a static data directive to ensure that it is valid assembly language
-fn validate_static_data(stmt: ast::StaticData, diag: &Diagnostics) -> asm::StaticData {
+fn validate_static_data(stmt: ast::StaticData, consts: &ConstTable, diag: &Diagnostics) -> asm::StaticData {
     match stmt {
         ast::StaticData::StaticBytes(static_bytes) => {
-            asm::StaticData::StaticBytes(validate_static_bytes(static_bytes, diag))
+            asm::StaticData::StaticBytes(validate_static_bytes(static_bytes, consts))
         },
 
         ast::StaticData::StaticZero(static_zero) => {
@@ -146,64 +243,14 @@ fn validate_static_data(stmt: ast::StaticData, diag: &Diagnostics) -> asm::Stati
     }
 }
 
-fn validate_static_bytes(static_bytes: ast::StaticBytes, diag: &Diagnostics) -> asm::StaticBytes {
-    let ast::StaticBytes {size, value, span} = static_bytes;
-    let ast::Integer {value, span: value_span} = value;
-
-    match size {
-        1 => {
-            if value < 0 || value > u8::max_value() as i128 {
-                diag.span_error(span, format!("value `{}` for `.b1` must be in the range `0` to `{}`", value, u8::max_value())).emit();
-            }
-
-            asm::StaticBytes {
-                // Error recovery: if an error is produced above, we'll just end up with the result
-                // of `as` when casting with overflow
-                value: asm::StaticBytesValue::B1((value as u8).to_le_bytes(), value_span),
-                span,
-            }
-        },
+fn validate_static_bytes(static_bytes: ast::StaticBytes, consts: &ConstTable) -> asm::StaticBytes {
+    let ast::StaticBytes {size, values, span} = static_bytes;
 
-        2 => {
-            if value < 0 || value > u16::max_value() as i128 {
-                diag.span_error(span, format!("value `{}` for `.b2` must be in the range `0` to `{}`", value, u16::max_value())).emit();
-            }
-
-            asm::StaticBytes {
-                // Error recovery: if an error is produced above, we'll just end up with the result
-                // of `as` when casting with overflow
-                value: asm::StaticBytesValue::B2((value as u16).to_le_bytes(), value_span),
-                span,
-            }
-        },
-
-        4 => {
-            if value < 0 || value > u32::max_value() as i128 {
-                diag.span_error(span, format!("value `{}` for `.b4` must be in the range `0` to `{}`", value, u32::max_value())).emit();
-            }
-
-            asm::StaticBytes {
-                // Error recovery: if an error is produced above, we'll just end up with the result
-                // of `as` when casting with overflow
-                value: asm::StaticBytesValue::B4((value as u32).to_le_bytes(), value_span),
-                span,
-            }
-        },
-
-        8 => {
-            if value < 0 || value > u64::max_value() as i128 {
-                diag.span_error(span, format!("value `{}` for `.b8` must be in the range `0` to `{}`", value, u64::max_value())).emit();
-            }
-
-            asm::StaticBytes {
-                // Error recovery: if an error is produced above, we'll just end up with the result
-                // of `as` when casting with overflow
-                value: asm::StaticBytesValue::B8((value as u64).to_le_bytes(), value_span),
-                span,
-            }
-        },
-
-        _ => unreachable!("bug: unexpected size of static bytes: `{}`", size),
+    // The range of each value is checked during layout, once any labels have been resolved
+    asm::StaticBytes {
+        size,
+        values: values.into_iter().map(|value| consts.subst_expr(value)).collect(),
+        span,
     }
 }
 
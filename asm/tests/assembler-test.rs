@@ -105,17 +105,51 @@ fn run_pass() {
     });
 }
 
+#[test]
+fn listing_object_format() {
+    let object = NamedTempFile::new()
+        .unwrap_or_else(|err| panic!("Failed to created temporary file: {}", err));
+    let listing = NamedTempFile::new()
+        .unwrap_or_else(|err| panic!("Failed to created temporary file: {}", err));
+
+    let output = Command::new(EXEC_PATH)
+        .arg("tests/run-pass/fib.wa")
+        .arg("--color=never")
+        .arg("--format=object")
+        .arg("-l")
+        .arg(listing.path())
+        .arg("-o")
+        .arg(object.path())
+        .output()
+        .unwrap_or_else(|err| panic!("Failed to run assembler: {}", err));
+
+    // The addresses of an object file are not known until it is linked
+    assert!(!output.status.success(), "Assembler should have failed to write a listing for an object file");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("listings cannot be generated for object files"), "unexpected error: {}", stderr);
+}
+
 /// Runs the assembler on a single file, returning (path to the generated
 /// executable, stdout contents) if the assembler succeeded. Returns the
 /// assembler error message if the assembler failed.
+///
+/// Extra arguments can be passed to the assembler with a comment on the first
+/// line of the file, e.g. `# args: -D DEBUG`.
 fn run_assembler(source_path: &Path) -> Result<(TempPath, String), String> {
     // The path to the executable that will be generated
     // Using temp file so this is reliably cleaned up
     let executable = NamedTempFile::new()
         .unwrap_or_else(|err| panic!("Failed to created temporary file: {}", err));
 
+    let source = fs::read_to_string(source_path)
+        .unwrap_or_else(|err| panic!("Failed to read '{}': {}", source_path.display(), err));
+    let args = source.lines().next()
+        .and_then(|line| line.strip_prefix("# args:"))
+        .unwrap_or("");
+
     let output = Command::new(EXEC_PATH)
         .arg(source_path)
+        .args(args.split_whitespace())
         .arg("--color=never")
         .arg("-o")
         .arg(executable.path())
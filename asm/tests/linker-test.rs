@@ -0,0 +1,146 @@
+use std::fs;
+use std::env;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use pretty_assertions::assert_eq as eq;
+use rayon::prelude::*;
+use tempfile::TempDir;
+
+const ASSEMBLER_PATH: &str = env!("CARGO_BIN_EXE_wolf-asm");
+const LINKER_PATH: &str = env!("CARGO_BIN_EXE_wolf-ld");
+
+#[test]
+fn ui() {
+    // Pass the environment variable TESTASSEMBLER=overwrite to overwrite the stderr files
+    let overwrite_expected_output = env::var("TESTASSEMBLER")
+        .map(|val| val == "overwrite")
+        .unwrap_or(false);
+
+    for_each_test_dir(Path::new("tests/link/ui"), |test_dir| {
+        println!("[link-ui] Linking {}", test_dir.display());
+        match assemble_and_link(&test_dir) {
+            Ok(_) => {
+                panic!("Linker should have failed for '{}'", test_dir.display());
+            },
+            Err(stderr) => {
+                // Check the stderr output against what's expected
+                let stderr_file = test_dir.with_extension("stderr");
+
+                if overwrite_expected_output {
+                    fs::write(&stderr_file, &stderr)
+                        .unwrap_or_else(|err| panic!("Failed to write expected output to '{}': {}", stderr_file.display(), err));
+                    return;
+                }
+
+                let expected_stderr = fs::read_to_string(&stderr_file)
+                    .unwrap_or_else(|err| panic!("Failed to open '{}': {}", stderr_file.display(), err))
+                    .replace("\r\n", "\n");
+
+                eq!(
+                    stderr,
+                    expected_stderr,
+                    "Error for '{}' did not match '{}'",
+                    test_dir.display(),
+                    stderr_file.display(),
+                );
+
+                println!("[link-ui] Finished linking {}", test_dir.display());
+            },
+        }
+    });
+}
+
+#[test]
+fn run_pass() {
+    for_each_test_dir(Path::new("tests/link/run-pass"), |test_dir| {
+        println!("[link-run-pass] Linking {}", test_dir.display());
+        match assemble_and_link(&test_dir) {
+            Ok((_out_dir, exec_path)) => {
+                let exec_meta = fs::metadata(&exec_path)
+                    .unwrap_or_else(|err| panic!("Failed to read metadata for '{}': {}", exec_path.display(), err));
+                assert!(exec_meta.len() > 0, "Generated executable for '{}' should be non-empty", test_dir.display());
+
+                println!("[link-run-pass] Linker succeeded for {}", test_dir.display());
+            },
+            Err(err) => panic!("Linker failed for '{}'\n--- ERROR MESSAGE START --\n{}--- ERROR MESSAGE END ---\n", test_dir.display(), err),
+        }
+    });
+}
+
+/// Calls `f` with the path of each directory in `tests_dir`
+///
+/// Each directory contains the assembly language files that make up a single program.
+fn for_each_test_dir(tests_dir: &Path, f: impl Fn(PathBuf) + Sync) {
+    let test_dirs = tests_dir.read_dir()
+        .unwrap_or_else(|err| panic!("Failed to read test files directory '{}': {}", tests_dir.display(), err));
+    test_dirs.par_bridge().panic_fuse().for_each(|entry| {
+        let entry = entry.unwrap_or_else(|err| panic!("Failed to read directory entry in '{}': {}", tests_dir.display(), err));
+        let entry_path = entry.path();
+        if !entry_path.is_dir() {
+            return;
+        }
+
+        let entry_path = entry_path.strip_prefix(tests_dir).map(|f| {
+            // A bad hack to make sure we get paths with `/`s instead of `\`s on Windows.
+            PathBuf::from(format!("{}/{}", tests_dir.display(), f.display()))
+        }).unwrap();
+
+        f(entry_path);
+    });
+}
+
+/// Assembles every file in the given directory into an object file and then links the objects
+/// together (in order of file name). Returns the path to the generated executable (and the
+/// temporary directory containing it) if the assembler and linker both succeeded, or the error
+/// message if either failed.
+fn assemble_and_link(test_dir: &Path) -> Result<(TempDir, PathBuf), String> {
+    // Using a temporary directory so the generated files are reliably cleaned up
+    let out_dir = TempDir::new()
+        .unwrap_or_else(|err| panic!("Failed to created temporary directory: {}", err));
+
+    let mut source_paths: Vec<_> = test_dir.read_dir()
+        .unwrap_or_else(|err| panic!("Failed to read test directory '{}': {}", test_dir.display(), err))
+        .map(|entry| entry.unwrap_or_else(|err| panic!("Failed to read directory entry in '{}': {}", test_dir.display(), err)).path())
+        .filter(|path| path.extension() == Some(OsStr::new("wa")))
+        .map(|path| test_dir.join(path.file_name().unwrap()))
+        .collect();
+    source_paths.sort();
+
+    let mut object_paths = Vec::new();
+    for source_path in &source_paths {
+        let object_path = out_dir.path().join(source_path.with_extension("o").file_name().unwrap());
+        run(Command::new(ASSEMBLER_PATH)
+            .arg(source_path)
+            .arg("--format=object")
+            .arg("-o")
+            .arg(&object_path))?;
+        object_paths.push(object_path);
+    }
+
+    let exec_path = out_dir.path().join("a.out");
+    run(Command::new(LINKER_PATH)
+        .args(&object_paths)
+        .arg("-o")
+        .arg(&exec_path))?;
+
+    Ok((out_dir, exec_path))
+}
+
+/// Runs the given command with coloring disabled, returning its stderr if it fails
+fn run(command: &mut Command) -> Result<(), String> {
+    let output = command
+        .arg("--color=never")
+        .output()
+        .unwrap_or_else(|err| panic!("Failed to run {:?}: {}", command, err));
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8(output.stderr)
+            .unwrap_or_else(|err| panic!("Stderr of {:?} was not valid UTF-8: {}", command, err))
+            .replace("\r\n", "\n");
+        Err(stderr)
+    }
+}
@@ -0,0 +1,346 @@
+//! Runs the assembler on a file to find its diagnostics and the symbols it uses
+
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::collections::HashSet;
+
+use parking_lot::RwLock;
+use lsp_types::{
+    Position,
+    Range,
+    Location,
+    Url,
+    DiagnosticSeverity,
+    DiagnosticRelatedInformation,
+};
+use wolf_asm::{
+    diagnostics::{Diagnostics, Diagnostic, Fragment, Message, Level},
+    parser::{Span, SourceFiles},
+    file_provider::FsFileProvider,
+    assembler::expand_program,
+    validate::validate_program,
+    label_offsets::LabelOffsets,
+    executable::Executable,
+    object::Object,
+    asm::InstrKind,
+};
+
+use crate::convert::{span_range, range_contains, same_file};
+use crate::symbols::{SymbolIndex, SymbolKind};
+
+/// The results of assembling a file
+pub struct Analysis {
+    root_path: PathBuf,
+    source_files: Arc<RwLock<SourceFiles>>,
+    diagnostics: Vec<Diagnostic<'static>>,
+    symbols: SymbolIndex,
+}
+
+impl Analysis {
+    /// Assembles the file at `path`, using `source` instead of the contents of the file on disk
+    ///
+    /// Any included files are read from disk. Like `wolf-asm`, assembling stops after the first
+    /// stage that produces errors, so only the symbols found up to that point are available.
+    pub fn new(path: &Path, source: &str) -> Self {
+        let source_files = Arc::new(RwLock::new(SourceFiles::default()));
+        let diag = Diagnostics::collecting(source_files.clone());
+        let symbols = assemble(path, source, &source_files, &diag);
+
+        Self {
+            root_path: path.to_path_buf(),
+            source_files,
+            diagnostics: diag.take_collected(),
+            symbols,
+        }
+    }
+
+    /// Returns the diagnostics for each file, converted to the language server protocol
+    ///
+    /// The assembled file is always included, even if it has no diagnostics, so that any
+    /// previous diagnostics can be cleared.
+    pub fn diagnostics(&self) -> Vec<(PathBuf, Vec<lsp_types::Diagnostic>)> {
+        let source_files = self.source_files.read();
+
+        let mut files: Vec<(PathBuf, Vec<_>)> = vec![(self.root_path.clone(), Vec::new())];
+        for diagnostic in &self.diagnostics {
+            let (path, diagnostic) = convert_diagnostic(&source_files, &self.root_path, diagnostic);
+            match files.iter_mut().find(|(file, _)| same_file(file, &path)) {
+                Some((_, diagnostics)) => diagnostics.push(diagnostic),
+                None => files.push((path, vec![diagnostic])),
+            }
+        }
+
+        files
+    }
+
+    /// Returns the location of the definition of the label or constant at the given position
+    pub fn definition(&self, path: &Path, pos: Position) -> Option<(PathBuf, Range)> {
+        let source_files = self.source_files.read();
+        let def = self.symbols.def_at(|span| self.span_contains(&source_files, span, path, pos))?;
+
+        Some(span_range(&source_files, self.symbols.defs[def].span))
+    }
+
+    /// Returns the location of every use of the label or constant at the given position
+    ///
+    /// Uses from different expansions of the same macro are only returned once.
+    pub fn references(&self, path: &Path, pos: Position, include_declaration: bool) -> Vec<(PathBuf, Range)> {
+        let source_files = self.source_files.read();
+        let def = match self.symbols.def_at(|span| self.span_contains(&source_files, span, path, pos)) {
+            Some(def) => def,
+            None => return Vec::new(),
+        };
+
+        let decl_span = Some(self.symbols.defs[def].span).filter(|_| include_declaration);
+        let spans = decl_span.into_iter().chain(self.symbols.refs_to(def).map(|reference| reference.span));
+
+        let mut seen = HashSet::new();
+        spans.map(|span| span_range(&source_files, span))
+            .filter(|(path, range)| seen.insert((path.clone(), *range)))
+            .collect()
+    }
+
+    /// Returns the kind of instruction at the given position, along with the range of its name
+    pub fn instr_at(&self, path: &Path, pos: Position) -> Option<(InstrKind, Range)> {
+        let source_files = self.source_files.read();
+        self.symbols.instrs.iter().find_map(|instr| {
+            let (instr_path, range) = span_range(&source_files, instr.span);
+            if !range_contains(range, pos) || !same_file(&instr_path, path) {
+                return None;
+            }
+
+            Some((InstrKind::from_name(&instr.name)?, range))
+        })
+    }
+
+    /// Returns the name of every label and constant that can be used anywhere in the program
+    ///
+    /// Local labels and labels defined by macros are not included since they can only be used
+    /// within part of the program.
+    pub fn global_symbols(&self) -> Vec<(Arc<str>, SymbolKind)> {
+        let source_files = self.source_files.read();
+
+        let mut seen = HashSet::new();
+        self.symbols.defs.iter()
+            .filter(|def| !def.name.contains('.') && source_files.expansion(def.span).is_none())
+            .filter(|def| seen.insert(def.name.clone()))
+            .map(|def| (def.name.clone(), def.kind))
+            .collect()
+    }
+
+    fn span_contains(&self, source_files: &SourceFiles, span: Span, path: &Path, pos: Position) -> bool {
+        let (span_path, range) = span_range(source_files, span);
+        // Check the range first since comparing paths may need to access the file system
+        range_contains(range, pos) && same_file(&span_path, path)
+    }
+}
+
+/// Runs every stage of the assembler up to and including layout, and returns the symbols found
+fn assemble(
+    path: &Path,
+    source: &str,
+    source_files: &Arc<RwLock<SourceFiles>>,
+    diag: &Diagnostics,
+) -> SymbolIndex {
+    let root_file = source_files.write().add_source(path, source.as_bytes());
+    let program = expand_program(path, root_file, source_files, &FsFileProvider, &[], diag);
+    let symbols = SymbolIndex::new(program.clone());
+    if diag.emitted_errors() > 0 {
+        return symbols;
+    }
+
+    let program = validate_program(program, &[], diag);
+    if diag.emitted_errors() > 0 {
+        return symbols;
+    }
+
+    // Layout finds any labels that are not defined. Files that use `.extern` labels can only be
+    // assembled into object files.
+    if program.externs.is_empty() {
+        let labels = LabelOffsets::new(&program);
+        Executable::layout_executable(program, diag, &labels);
+    } else {
+        Object::layout_object(program, diag);
+    }
+
+    symbols
+}
+
+/// Converts a diagnostic to the language server protocol, returning the path of the file that
+/// it should be shown in
+///
+/// Diagnostics without a span are shown at the start of the assembled file.
+fn convert_diagnostic(
+    source_files: &SourceFiles,
+    root_path: &Path,
+    diagnostic: &Diagnostic,
+) -> (PathBuf, lsp_types::Diagnostic) {
+    let Diagnostic {title, fragments} = diagnostic;
+
+    // The first fragment is usually the title with a span
+    let (primary, fragments) = match fragments.split_first() {
+        Some((first, rest)) if first.message.level == title.level => (Some(first.span), rest),
+        _ => (None, &fragments[..]),
+    };
+    let (path, range) = match primary {
+        Some(span) => span_range(source_files, span),
+        None => (root_path.to_path_buf(), Range::default()),
+    };
+
+    let mut related = Vec::new();
+    for &Fragment {span, ref message} in fragments {
+        let Message {level, label} = message;
+        related.extend(related_info(source_files, span, format!("{}: {}", level_name(*level), label)));
+    }
+
+    // Point to every macro invocation that led to the code with the primary span
+    let mut expansion_span = primary;
+    while let Some(expansion) = expansion_span.and_then(|span| source_files.expansion(span)) {
+        let message = format!("note: in this expansion of macro `{}`", expansion.name);
+        related.extend(related_info(source_files, expansion.call_site, message));
+        expansion_span = Some(expansion.call_site);
+    }
+
+    let severity = match title.level {
+        Level::Error => DiagnosticSeverity::ERROR,
+        Level::Warning => DiagnosticSeverity::WARNING,
+        Level::Info => DiagnosticSeverity::INFORMATION,
+        Level::Note |
+        Level::Help => DiagnosticSeverity::HINT,
+    };
+
+    let diagnostic = lsp_types::Diagnostic {
+        range,
+        severity: Some(severity),
+        source: Some("wolf-asm".to_string()),
+        message: title.label.to_string(),
+        related_information: Some(related).filter(|related| !related.is_empty()),
+        ..Default::default()
+    };
+
+    (path, diagnostic)
+}
+
+fn related_info(source_files: &SourceFiles, span: Span, message: String) -> Option<DiagnosticRelatedInformation> {
+    let (path, range) = span_range(source_files, span);
+    let uri = Url::from_file_path(path).ok()?;
+
+    Some(DiagnosticRelatedInformation {location: Location {uri, range}, message})
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warning => "warning",
+        Level::Info => "info",
+        Level::Note => "note",
+        Level::Help => "help",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    fn pos(line: u32, character: u32) -> Position {
+        Position::new(line, character)
+    }
+
+    fn range(line: u32, start: u32, end: u32) -> Range {
+        Range::new(pos(line, start), pos(line, end))
+    }
+
+    #[test]
+    fn labels_and_consts() {
+        let source = "\
+section .code
+.const COUNT 3
+main:
+    mov $1, COUNT
+.loop:
+    sub $1, 1
+    jnz .loop
+print:
+.loop:
+    jmp .loop
+";
+        let path = Path::new("test.wa");
+        let analysis = Analysis::new(path, source);
+        assert_eq!(analysis.diagnostics(), vec![(path.to_path_buf(), Vec::new())]);
+
+        // From a use of a constant
+        assert_eq!(analysis.definition(path, pos(3, 14)), Some((path.to_path_buf(), range(1, 7, 12))));
+        // Local labels with the same name in different scopes
+        assert_eq!(analysis.definition(path, pos(6, 10)), Some((path.to_path_buf(), range(4, 0, 5))));
+        assert_eq!(analysis.definition(path, pos(9, 10)), Some((path.to_path_buf(), range(8, 0, 5))));
+        // Not a label or constant
+        assert_eq!(analysis.definition(path, pos(3, 9)), None);
+
+        assert_eq!(analysis.references(path, pos(4, 2), true), vec![
+            (path.to_path_buf(), range(4, 0, 5)),
+            (path.to_path_buf(), range(6, 8, 13)),
+        ]);
+        assert_eq!(analysis.references(path, pos(1, 8), false), vec![(path.to_path_buf(), range(3, 12, 17))]);
+
+        let (kind, instr_range) = analysis.instr_at(path, pos(3, 5)).unwrap();
+        assert_eq!(kind, InstrKind::Mov);
+        assert_eq!(instr_range, range(3, 4, 7));
+
+        let symbols: Vec<_> = analysis.global_symbols().into_iter().map(|(name, _)| name.to_string()).collect();
+        assert_eq!(symbols, &["count", "main", "print"]);
+    }
+
+    #[test]
+    fn numeric_labels() {
+        let source = "\
+section .code
+main:
+1:
+    jmp 1f
+1:
+    jmp 1b
+";
+        let path = Path::new("test.wa");
+        let analysis = Analysis::new(path, source);
+
+        assert_eq!(analysis.definition(path, pos(3, 9)), Some((path.to_path_buf(), range(4, 0, 1))));
+        assert_eq!(analysis.definition(path, pos(5, 9)), Some((path.to_path_buf(), range(4, 0, 1))));
+        assert_eq!(analysis.references(path, pos(2, 0), false), Vec::new());
+    }
+
+    #[test]
+    fn included_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib_path = dir.path().join("lib.wa");
+        fs::write(&lib_path, "print:\n    ret\n").unwrap();
+        let main_path = dir.path().join("main.wa");
+        let source = "section .code\nmain:\n    call print\n    call missing\n.include \"lib.wa\"\n";
+
+        let analysis = Analysis::new(&main_path, source);
+        assert_eq!(analysis.definition(&main_path, pos(2, 10)), Some((lib_path.clone(), range(0, 0, 5))));
+        assert_eq!(analysis.references(&lib_path, pos(0, 2), false), vec![(main_path.clone(), range(2, 9, 14))]);
+
+        let diagnostics = analysis.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        let (path, diagnostics) = &diagnostics[0];
+        assert_eq!(path, &main_path);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "unknown label `missing`");
+        assert_eq!(diagnostics[0].range, range(3, 9, 16));
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let path = Path::new("test.wa");
+        let analysis = Analysis::new(path, "section .code\nmain:\n    add $1,\n");
+
+        let diagnostics = analysis.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        let (_, diagnostics) = &diagnostics[0];
+        assert!(!diagnostics.is_empty());
+        assert!(diagnostics.iter().all(|diagnostic| diagnostic.range.start.line == 2));
+    }
+}
@@ -0,0 +1,138 @@
+//! Completion for instructions, registers, labels, and constants
+
+use lsp_types::{Position, Range, TextEdit, CompletionItem, CompletionItemKind, CompletionTextEdit};
+use wolf_asm::asm::{InstrKind, REGISTERS};
+
+use crate::analysis::Analysis;
+use crate::convert::{utf16_len, utf16_offset};
+use crate::hover::operand_form;
+use crate::symbols::SymbolKind;
+
+/// Returns the completions for the word being typed at the given position in `line`
+///
+/// Instructions are suggested at the start of a line (after any labels). Registers, labels, and
+/// constants are suggested everywhere else. The labels and constants come from the most recent
+/// analysis of the file, if any.
+pub fn completions(line: &str, pos: Position, analysis: Option<&Analysis>) -> Vec<CompletionItem> {
+    let end = utf16_offset(line, pos.character);
+    let start = line[..end].char_indices().rev()
+        .find(|&(_, ch)| !is_word_char(ch))
+        .map(|(index, ch)| index + ch.len_utf8())
+        .unwrap_or(0);
+    let before = &line[..start];
+
+    // Nothing to complete in a comment
+    if before.contains(';') {
+        return Vec::new();
+    }
+
+    let range = Range::new(Position::new(pos.line, utf16_len(line.as_bytes(), start)), pos);
+    let item = |label: String, kind, detail: String| CompletionItem {
+        text_edit: Some(CompletionTextEdit::Edit(TextEdit {range, new_text: label.clone()})),
+        label,
+        kind: Some(kind),
+        detail: Some(detail),
+        ..Default::default()
+    };
+
+    if skip_labels(before).trim().is_empty() {
+        return InstrKind::ALL.iter()
+            .map(|&kind| item(kind.name().to_string(), CompletionItemKind::KEYWORD, operand_form(kind)))
+            .collect();
+    }
+
+    let mut items: Vec<_> = (0..REGISTERS)
+        .map(|reg| item(format!("${}", reg), CompletionItemKind::VARIABLE, "register".to_string()))
+        .collect();
+    items.push(item("$sp".to_string(), CompletionItemKind::VARIABLE, "stack pointer".to_string()));
+    items.push(item("$fp".to_string(), CompletionItemKind::VARIABLE, "frame pointer".to_string()));
+
+    // Registers cannot be labels or constants
+    if line[start..end].starts_with('$') {
+        return items;
+    }
+
+    for (name, kind) in analysis.map(|analysis| analysis.global_symbols()).unwrap_or_default() {
+        let (kind, detail) = match kind {
+            SymbolKind::Label => (CompletionItemKind::REFERENCE, "label"),
+            SymbolKind::Extern => (CompletionItemKind::REFERENCE, "extern label"),
+            SymbolKind::Const => (CompletionItemKind::CONSTANT, "constant"),
+        };
+        items.push(item(name.to_string(), kind, detail.to_string()));
+    }
+
+    items
+}
+
+/// Returns true if the character can be part of a name, a register, or a local label
+fn is_word_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_' || ch == '.' || ch == '$'
+}
+
+/// Skips any labels at the start of the line, e.g. `main: loop:`
+fn skip_labels(mut text: &str) -> &str {
+    loop {
+        let trimmed = text.trim_start();
+        match trimmed.split_once(':') {
+            Some((label, rest)) if !label.is_empty() && label.chars().all(is_word_char) => text = rest,
+            _ => return trimmed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    fn labels(items: &[CompletionItem]) -> Vec<&str> {
+        items.iter().map(|item| &*item.label).collect()
+    }
+
+    #[test]
+    fn instructions() {
+        let items = completions("main: ad", Position::new(3, 8), None);
+        assert_eq!(items.len(), InstrKind::ALL.len());
+        let add = items.iter().find(|item| item.label == "add").unwrap();
+        assert_eq!(add.detail.as_deref(), Some("add dest, source"));
+        assert_eq!(add.text_edit, Some(CompletionTextEdit::Edit(TextEdit {
+            range: Range::new(Position::new(3, 6), Position::new(3, 8)),
+            new_text: "add".to_string(),
+        })));
+
+        assert_eq!(completions("", Position::new(0, 0), None).len(), InstrKind::ALL.len());
+    }
+
+    #[test]
+    fn arguments() {
+        let analysis = Analysis::new(Path::new("test.wa"), "section .code\n.const SIZE 8\nmain:\n.loop:\n    jmp main\n");
+
+        let items = completions("    add $", Position::new(0, 9), Some(&analysis));
+        assert_eq!(items.len(), REGISTERS as usize + 2);
+        assert!(labels(&items).contains(&"$sp"));
+
+        let items = completions("    jmp m", Position::new(0, 9), Some(&analysis));
+        let labels = labels(&items);
+        assert!(labels.contains(&"$63"));
+        assert!(labels.contains(&"main"));
+        assert!(labels.contains(&"size"));
+        assert!(!labels.contains(&"main.loop"));
+
+        assert_eq!(completions("    jmp main ; m", Position::new(0, 16), Some(&analysis)), Vec::new());
+    }
+
+    #[test]
+    fn non_ascii() {
+        assert_eq!(completions("    mov $1, 0 ; café", Position::new(0, 20), None), Vec::new());
+        assert_eq!(completions("    mov $1, 0 ; é", Position::new(0, 17), None), Vec::new());
+
+        // The start of the word is after the multi-byte character
+        let items = completions("    jmp é$", Position::new(0, 10), None);
+        let sp = items.iter().find(|item| item.label == "$sp").unwrap();
+        assert_eq!(sp.text_edit, Some(CompletionTextEdit::Edit(TextEdit {
+            range: Range::new(Position::new(0, 9), Position::new(0, 10)),
+            new_text: "$sp".to_string(),
+        })));
+    }
+}
@@ -0,0 +1,101 @@
+//! Conversions between spans in the source files and positions in the language server protocol
+//!
+//! The protocol measures columns in UTF-16 code units, while spans are byte offsets.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lsp_types::{Position, Range};
+use wolf_asm::parser::{Span, SourceFiles};
+
+/// Returns the path of the file containing the given span and the range of the span within it
+///
+/// Spans in a macro expansion are mapped back to the original macro body.
+pub fn span_range(source_files: &SourceFiles, span: Span) -> (PathBuf, Range) {
+    // Empty spans (e.g. at the end of a file) still need to point at something
+    let span = if span.end > span.start { span } else { Span {start: span.start, end: span.start + 1} };
+    let pos = source_files.pos(span);
+
+    let start_line = source_files.line_source(span);
+    let end_line = source_files.line_source(Span {start: span.end - 1, end: span.end});
+    let range = Range {
+        start: Position::new(pos.start_line as u32 - 1, utf16_len(start_line, pos.start_offset - 1)),
+        // The end offset is 1-based and inclusive, which is the same as 0-based and exclusive
+        end: Position::new(pos.end_line as u32 - 1, utf16_len(end_line, pos.end_offset)),
+    };
+
+    (pos.path.to_path_buf(), range)
+}
+
+/// Returns the number of UTF-16 code units in the first `nbytes` bytes of the given line
+pub fn utf16_len(line: &[u8], nbytes: usize) -> u32 {
+    let prefix = &line[..nbytes.min(line.len())];
+    String::from_utf8_lossy(prefix).encode_utf16().count() as u32
+}
+
+/// Returns the byte offset in the given line of the given number of UTF-16 code units
+pub fn utf16_offset(line: &str, character: u32) -> usize {
+    let mut units = 0;
+    for (index, ch) in line.char_indices() {
+        if units >= character as usize {
+            return index;
+        }
+        units += ch.len_utf16();
+    }
+
+    line.len()
+}
+
+/// Returns true if the position is within the range, including at its end
+pub fn range_contains(range: Range, pos: Position) -> bool {
+    range.start <= pos && pos <= range.end
+}
+
+/// Returns true if both paths refer to the same file
+///
+/// Included files are found relative to the file that includes them, so their paths may
+/// contain `..` or symbolic links.
+pub fn same_file(path1: &Path, path2: &Path) -> bool {
+    path1 == path2 || match (fs::canonicalize(path1), fs::canonicalize(path2)) {
+        (Ok(path1), Ok(path2)) => path1 == path2,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf16_columns() {
+        // `é` is 2 bytes in UTF-8 and 1 code unit in UTF-16, `𝕏` is 4 bytes and 2 code units
+        let line = "é𝕏 add";
+        assert_eq!(utf16_len(line.as_bytes(), 0), 0);
+        assert_eq!(utf16_len(line.as_bytes(), 2), 1);
+        assert_eq!(utf16_len(line.as_bytes(), 6), 3);
+        assert_eq!(utf16_len(line.as_bytes(), 100), 7);
+
+        assert_eq!(utf16_offset(line, 0), 0);
+        assert_eq!(utf16_offset(line, 1), 2);
+        assert_eq!(utf16_offset(line, 3), 6);
+        assert_eq!(utf16_offset(line, 100), line.len());
+    }
+
+    #[test]
+    fn span_ranges() {
+        let mut source_files = SourceFiles::default();
+        source_files.add_source("test.wa", "é: add $1, 2\n  jmp é\n".as_bytes());
+
+        let (path, range) = span_range(&source_files, Span {start: 0, end: 2});
+        assert_eq!(path, Path::new("test.wa"));
+        assert_eq!(range, Range::new(Position::new(0, 0), Position::new(0, 1)));
+
+        // `jmp` on the second line
+        let (_, range) = span_range(&source_files, Span {start: 16, end: 19});
+        assert_eq!(range, Range::new(Position::new(1, 2), Position::new(1, 5)));
+
+        // The end of the file
+        let (_, range) = span_range(&source_files, Span {start: 23, end: 23});
+        assert_eq!(range.start.character, 0);
+    }
+}
@@ -0,0 +1,53 @@
+//! Documentation shown when hovering over an instruction
+
+use wolf_asm::asm::{InstrKind, ArgKind};
+
+/// Returns the operand form of the instruction, e.g. `add dest, source`
+pub fn operand_form(kind: InstrKind) -> String {
+    let args: Vec<_> = kind.args().iter().map(|&(name, _)| name).collect();
+    if args.is_empty() {
+        kind.name().to_string()
+    } else {
+        format!("{} {}", kind.name(), args.join(", "))
+    }
+}
+
+/// Returns markdown describing the operand form of the instruction and what each argument may be
+pub fn instr_docs(kind: InstrKind) -> String {
+    let mut docs = format!("```wolf-asm\n{}\n```\n", operand_form(kind));
+
+    if kind.args().is_empty() {
+        docs.push_str("\nTakes no arguments\n");
+    } else {
+        docs.push('\n');
+        for &(name, arg_kind) in kind.args() {
+            docs.push_str(&format!("* `{}` - {}\n", name, arg_kind_docs(arg_kind)));
+        }
+    }
+
+    docs
+}
+
+fn arg_kind_docs(kind: ArgKind) -> &'static str {
+    match kind {
+        ArgKind::Source => "a register, an immediate, a label, or an expression",
+        ArgKind::Destination => "a register",
+        ArgKind::Location => "a register with an optional offset (e.g. `8($sp)`), or an address (a label, an immediate, or an expression)",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operand_forms() {
+        assert_eq!(operand_form(InstrKind::Add), "add dest, source");
+        assert_eq!(operand_form(InstrKind::Mull), "mull dest_hi, dest, source");
+        assert_eq!(operand_form(InstrKind::Store8), "store8 loc, source");
+        assert_eq!(operand_form(InstrKind::Ret), "ret");
+
+        assert_eq!(instr_docs(InstrKind::Push), "```wolf-asm\npush source\n```\n\n* `source` - a register, an immediate, a label, or an expression\n");
+        assert_eq!(instr_docs(InstrKind::Syscall), "```wolf-asm\nsyscall\n```\n\nTakes no arguments\n");
+    }
+}
@@ -0,0 +1,319 @@
+//! wolf-lsp - A language server for The Wolf Assembly Language
+//!
+//! Communicates with editors over stdin/stdout using the Language Server Protocol. Every open
+//! `.wa` file is assembled whenever it changes to report diagnostics. Also supports go to
+//! definition and find references for labels and constants, hover documentation for
+//! instructions, and completion for instructions, registers, labels, and constants.
+
+#![deny(unused_must_use)]
+
+mod analysis;
+mod completion;
+mod convert;
+mod hover;
+mod symbols;
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use lsp_server::{Connection, Message, Request, RequestId, Response, Notification, ErrorCode};
+use lsp_types::{
+    notification::{self, Notification as _},
+    request::{self, Request as _},
+    ServerCapabilities,
+    TextDocumentSyncCapability,
+    TextDocumentSyncKind,
+    TextDocumentSyncOptions,
+    SaveOptions,
+    TextDocumentSyncSaveOptions,
+    HoverProviderCapability,
+    CompletionOptions,
+    OneOf,
+    Url,
+    Location,
+    GotoDefinitionParams,
+    GotoDefinitionResponse,
+    ReferenceParams,
+    HoverParams,
+    Hover,
+    HoverContents,
+    MarkupContent,
+    MarkupKind,
+    CompletionParams,
+    CompletionResponse,
+    PublishDiagnosticsParams,
+    DidOpenTextDocumentParams,
+    DidChangeTextDocumentParams,
+    DidSaveTextDocumentParams,
+    DidCloseTextDocumentParams,
+};
+
+use analysis::Analysis;
+
+fn main() -> anyhow::Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
+            open_close: Some(true),
+            change: Some(TextDocumentSyncKind::FULL),
+            save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {include_text: Some(false)})),
+            ..Default::default()
+        })),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["$".to_string()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    Server::new(&connection).run()?;
+
+    // The IO threads only stop once the connection is closed
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+/// An open document and the results of assembling it
+struct Document {
+    text: String,
+    /// `None` if the document is not a file on disk, since included files cannot be found
+    analysis: Option<Analysis>,
+    /// Every file that diagnostics were published for the last time this document was assembled
+    published: Vec<Url>,
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    documents: HashMap<Url, Document>,
+}
+
+impl<'a> Server<'a> {
+    fn new(connection: &'a Connection) -> Self {
+        Self {connection, documents: HashMap::new()}
+    }
+
+    /// Handles messages until the client asks the server to shut down
+    fn run(&mut self) -> anyhow::Result<()> {
+        for msg in &self.connection.receiver {
+            match msg {
+                Message::Request(req) => {
+                    if self.connection.handle_shutdown(&req)? {
+                        return Ok(());
+                    }
+                    self.handle_request(req)?;
+                },
+                Message::Notification(notif) => self.handle_notification(notif)?,
+                // This server never sends any requests, so there are no responses to handle
+                Message::Response(_) => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_request(&mut self, req: Request) -> anyhow::Result<()> {
+        let Request {id, method, params} = req;
+        match &*method {
+            request::GotoDefinition::METHOD => match serde_json::from_value(params) {
+                Ok(params) => self.respond(id, self.definition(params)),
+                Err(err) => self.respond_invalid_params(id, err),
+            },
+
+            request::References::METHOD => match serde_json::from_value(params) {
+                Ok(params) => self.respond(id, self.references(params)),
+                Err(err) => self.respond_invalid_params(id, err),
+            },
+
+            request::HoverRequest::METHOD => match serde_json::from_value(params) {
+                Ok(params) => self.respond(id, self.hover(params)),
+                Err(err) => self.respond_invalid_params(id, err),
+            },
+
+            request::Completion::METHOD => match serde_json::from_value(params) {
+                Ok(params) => self.respond(id, self.completion(params)),
+                Err(err) => self.respond_invalid_params(id, err),
+            },
+
+            _ => self.respond_err(id, ErrorCode::MethodNotFound, format!("unsupported request `{}`", method)),
+        }
+    }
+
+    fn respond(&self, id: RequestId, result: impl serde::Serialize) -> anyhow::Result<()> {
+        self.connection.sender.send(Message::Response(Response::new_ok(id, result)))?;
+        Ok(())
+    }
+
+    fn respond_invalid_params(&self, id: RequestId, err: serde_json::Error) -> anyhow::Result<()> {
+        self.respond_err(id, ErrorCode::InvalidParams, format!("invalid parameters: {}", err))
+    }
+
+    fn respond_err(&self, id: RequestId, code: ErrorCode, message: String) -> anyhow::Result<()> {
+        self.connection.sender.send(Message::Response(Response::new_err(id, code as i32, message)))?;
+        Ok(())
+    }
+
+    fn handle_notification(&mut self, notif: Notification) -> anyhow::Result<()> {
+        let Notification {method, params} = notif;
+        match &*method {
+            notification::DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = match notification_params(&method, params) {
+                    Some(params) => params,
+                    None => return Ok(()),
+                };
+                let document = Document {text: params.text_document.text, analysis: None, published: Vec::new()};
+                self.documents.insert(params.text_document.uri.clone(), document);
+                self.analyze(&params.text_document.uri)
+            },
+
+            notification::DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams = match notification_params(&method, params) {
+                    Some(params) => params,
+                    None => return Ok(()),
+                };
+                let uri = params.text_document.uri;
+                // Only full document sync is supported, so the last change is the entire document
+                if let (Some(document), Some(change)) = (self.documents.get_mut(&uri), params.content_changes.into_iter().last()) {
+                    document.text = change.text;
+                }
+                self.analyze(&uri)
+            },
+
+            notification::DidSaveTextDocument::METHOD => {
+                if notification_params::<DidSaveTextDocumentParams>(&method, params).is_none() {
+                    return Ok(());
+                }
+                // The saved file may be included by any of the other open files
+                let uris: Vec<_> = self.documents.keys().cloned().collect();
+                for uri in uris {
+                    self.analyze(&uri)?;
+                }
+                Ok(())
+            },
+
+            notification::DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = match notification_params(&method, params) {
+                    Some(params) => params,
+                    None => return Ok(()),
+                };
+                if let Some(document) = self.documents.remove(&params.text_document.uri) {
+                    for uri in document.published {
+                        self.publish_diagnostics(uri, Vec::new())?;
+                    }
+                }
+                Ok(())
+            },
+
+            // All other notifications are ignored
+            _ => Ok(()),
+        }
+    }
+
+    /// Assembles the given document and publishes its diagnostics
+    fn analyze(&mut self, uri: &Url) -> anyhow::Result<()> {
+        let document = match self.documents.get_mut(uri) {
+            Some(document) => document,
+            None => return Ok(()),
+        };
+
+        let path = match uri.to_file_path() {
+            Ok(path) => path,
+            Err(()) => return Ok(()),
+        };
+        let analysis = Analysis::new(&path, &document.text);
+
+        let mut published = Vec::new();
+        for (path, diagnostics) in analysis.diagnostics() {
+            if let Ok(file_uri) = Url::from_file_path(&path) {
+                published.push(file_uri);
+                self.publish_diagnostics(published.last().unwrap().clone(), diagnostics)?;
+            }
+        }
+
+        let document = self.documents.get_mut(uri).context("document was closed")?;
+        // Clear the diagnostics in any files that no longer have any
+        let stale: Vec<_> = document.published.drain(..).filter(|uri| !published.contains(uri)).collect();
+        document.published = published;
+        document.analysis = Some(analysis);
+        for uri in stale {
+            self.publish_diagnostics(uri, Vec::new())?;
+        }
+
+        Ok(())
+    }
+
+    fn publish_diagnostics(&self, uri: Url, diagnostics: Vec<lsp_types::Diagnostic>) -> anyhow::Result<()> {
+        let params = PublishDiagnosticsParams {uri, diagnostics, version: None};
+        let notif = Notification::new(notification::PublishDiagnostics::METHOD.to_string(), params);
+        self.connection.sender.send(Message::Notification(notif))?;
+        Ok(())
+    }
+
+    fn analysis(&self, uri: &Url) -> Option<&Analysis> {
+        self.documents.get(uri)?.analysis.as_ref()
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let doc_pos = params.text_document_position_params;
+        let path = doc_pos.text_document.uri.to_file_path().ok()?;
+        let analysis = self.analysis(&doc_pos.text_document.uri)?;
+
+        let (def_path, range) = analysis.definition(&path, doc_pos.position)?;
+        let uri = Url::from_file_path(def_path).ok()?;
+        Some(GotoDefinitionResponse::Scalar(Location {uri, range}))
+    }
+
+    fn references(&self, params: ReferenceParams) -> Option<Vec<Location>> {
+        let doc_pos = params.text_document_position;
+        let path = doc_pos.text_document.uri.to_file_path().ok()?;
+        let analysis = self.analysis(&doc_pos.text_document.uri)?;
+
+        let refs = analysis.references(&path, doc_pos.position, params.context.include_declaration);
+        Some(refs.into_iter().filter_map(|(path, range)| {
+            Some(Location {uri: Url::from_file_path(path).ok()?, range})
+        }).collect())
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let doc_pos = params.text_document_position_params;
+        let path = doc_pos.text_document.uri.to_file_path().ok()?;
+        let analysis = self.analysis(&doc_pos.text_document.uri)?;
+
+        let (kind, range) = analysis.instr_at(&path, doc_pos.position)?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: hover::instr_docs(kind),
+            }),
+            range: Some(range),
+        })
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let doc_pos = params.text_document_position;
+        let document = self.documents.get(&doc_pos.text_document.uri)?;
+
+        let line = document.text.lines().nth(doc_pos.position.line as usize).unwrap_or("");
+        let items = completion::completions(line, doc_pos.position, document.analysis.as_ref());
+        Some(CompletionResponse::Array(items))
+    }
+}
+
+/// Parses the parameters of a notification, or logs an error and returns None if they are invalid
+///
+/// Notifications cannot be responded to, so a malformed notification is ignored instead of
+/// stopping the server.
+fn notification_params<P: serde::de::DeserializeOwned>(method: &str, params: serde_json::Value) -> Option<P> {
+    match serde_json::from_value(params) {
+        Ok(params) => Some(params),
+        Err(err) => {
+            eprintln!("Ignoring `{}` notification with invalid parameters: {}", method, err);
+            None
+        },
+    }
+}
@@ -0,0 +1,141 @@
+//! An index of where every label and constant is defined and used in a program
+
+use std::sync::Arc;
+use std::collections::HashMap;
+
+use wolf_asm::ast;
+use wolf_asm::parser::Span;
+use wolf_asm::local_labels::{Direction, qualify_local_labels, is_numeric_label, numeric_label_ref};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Label,
+    Const,
+    /// A label declared with `.extern`, which is defined in another object file
+    Extern,
+}
+
+/// A definition of a label or constant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    pub kind: SymbolKind,
+    /// The name of the label or constant, qualified with its scope if it is a local label
+    pub name: Arc<str>,
+    /// The span of the name in the definition
+    pub span: Span,
+}
+
+/// A use of a label or constant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// An index into `SymbolIndex::defs`, or `None` if the name is not defined
+    pub def: Option<usize>,
+    pub span: Span,
+}
+
+/// The name of an instruction and where it is used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstrName {
+    pub name: Arc<str>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolIndex {
+    pub defs: Vec<Definition>,
+    pub refs: Vec<Reference>,
+    pub instrs: Vec<InstrName>,
+}
+
+impl SymbolIndex {
+    /// Indexes a program after its includes, conditionals, and macros have been expanded
+    ///
+    /// Local labels are qualified the same way as `validate_program`, so uses of `.loop` in
+    /// different functions refer to different labels.
+    pub fn new(prog: ast::Program) -> Self {
+        let ast::Program {stmts} = qualify_local_labels(prog);
+
+        let mut index = Self::default();
+        // The index of the statement defining each definition, used to resolve numeric labels
+        let mut def_stmts = Vec::new();
+        for (stmt_index, stmt) in stmts.iter().enumerate() {
+            let (kind, name) = match stmt {
+                ast::Stmt::Label(label) => (SymbolKind::Label, label),
+                ast::Stmt::Const(const_stmt) => (SymbolKind::Const, &const_stmt.name),
+                ast::Stmt::Extern(ast::Extern {label, span: _}) => (SymbolKind::Extern, label),
+                _ => continue,
+            };
+
+            index.defs.push(Definition {kind, name: name.value.clone(), span: name.span});
+            def_stmts.push(stmt_index);
+        }
+
+        let mut names = HashMap::new();
+        for (def_index, def) in index.defs.iter().enumerate() {
+            // The first definition wins, any others are errors anyway
+            if !is_numeric_label(&def.name) {
+                names.entry(def.name.clone()).or_insert(def_index);
+            }
+        }
+
+        let mut refs = Vec::new();
+        let mut instrs = Vec::new();
+        for (stmt_index, stmt) in stmts.iter().enumerate() {
+            let mut add_ref = |name: &ast::Ident| {
+                let def = match numeric_label_ref(&name.value) {
+                    Some((label, dir)) => {
+                        let mut defs = index.defs.iter().zip(&def_stmts).enumerate()
+                            .filter(|(_, (def, _))| &*def.name == label);
+                        // A label on the statement containing the reference counts as being
+                        // before it, and labels are always separate statements that come before
+                        match dir {
+                            Direction::Backward => defs.rfind(|(_, (_, &def_stmt))| def_stmt <= stmt_index),
+                            Direction::Forward => defs.find(|(_, (_, &def_stmt))| def_stmt > stmt_index),
+                        }.map(|(def_index, _)| def_index)
+                    },
+                    None => names.get(&name.value).copied(),
+                };
+
+                refs.push(Reference {def, span: name.span});
+            };
+
+            match stmt {
+                ast::Stmt::Const(ast::Const {name: _, value, span: _}) => value.for_each_name(&mut add_ref),
+                ast::Stmt::Entry(ast::Entry {label, span: _}) |
+                ast::Stmt::Global(ast::Global {label, span: _}) => add_ref(label),
+                ast::Stmt::StaticData(ast::StaticData::StaticBytes(data)) => {
+                    for value in &data.values {
+                        value.for_each_name(&mut add_ref);
+                    }
+                },
+                ast::Stmt::Instr(ast::Instr {name, args}) => {
+                    for arg in args {
+                        match arg {
+                            ast::InstrArg::Name(name) => add_ref(name),
+                            ast::InstrArg::Expr(expr) => expr.for_each_name(&mut add_ref),
+                            ast::InstrArg::Register(_) |
+                            ast::InstrArg::Immediate(_) => {},
+                        }
+                    }
+
+                    instrs.push(InstrName {name: name.value.clone(), span: name.span});
+                },
+                _ => {},
+            }
+        }
+
+        Self {refs, instrs, ..index}
+    }
+
+    /// Finds the first definition or reference whose span `contains` returns true for, and
+    /// returns the index of the definition it refers to
+    pub fn def_at(&self, contains: impl Fn(Span) -> bool) -> Option<usize> {
+        self.defs.iter().position(|def| contains(def.span))
+            .or_else(|| self.refs.iter().find(|reference| contains(reference.span))?.def)
+    }
+
+    /// Returns every reference to the given definition
+    pub fn refs_to(&self, def: usize) -> impl Iterator<Item=&Reference> {
+        self.refs.iter().filter(move |reference| reference.def == Some(def))
+    }
+}
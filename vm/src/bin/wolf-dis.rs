@@ -0,0 +1,45 @@
+//! wolf-dis - the wolf disassembler
+//!
+//! Converts executables generated by the wolf-asm assembler back into assembly code
+
+#![deny(unused_must_use)]
+
+use std::io;
+use std::path::PathBuf;
+use std::fs::File;
+
+use anyhow::Context;
+use structopt::StructOpt;
+use wolf_asm::executable::Executable;
+use wolf_vm::disassemble::disassemble;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "wolf-dis", about = "Disassembler for executables generated by the wolf-asm assembler")]
+struct DisassemblerOptions {
+    /// The executable file generated by the wolf-asm assembler
+    #[structopt(name = "input", parse(from_os_str))]
+    executable_path: PathBuf,
+    /// Write output to <file> instead of stdout
+    #[structopt(short = "o", name = "file", parse(from_os_str))]
+    output_path: Option<PathBuf>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let DisassemblerOptions {executable_path, output_path} = DisassemblerOptions::from_args();
+
+    let executable_file = File::open(&executable_path)
+        .with_context(|| format!("Failed to read executable: `{}`", executable_path.display()))?;
+    let exec = Executable::read_from(executable_file)
+        .with_context(|| format!("Failed to load executable: `{}`", executable_path.display()))?;
+
+    match output_path {
+        Some(output_path) => {
+            let mut output_file = File::create(&output_path)
+                .with_context(|| format!("Could not open output path `{}`", output_path.display()))?;
+            disassemble(&exec, &mut output_file)
+        },
+        None => disassemble(&exec, &mut io::stdout().lock()),
+    }.context("Failed to write disassembled code")?;
+
+    Ok(())
+}
@@ -4,25 +4,43 @@
 
 #![deny(unused_must_use)]
 
-use std::path::PathBuf;
-use std::fs::File;
+use std::io::{self, Write};
+use std::process;
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::time::Duration;
+use std::str::FromStr;
 
 use anyhow::Context;
 use structopt::StructOpt;
-use wolf_asm::executable::Executable;
+use wolf_asm::{executable::{Executable, DebugInfo}, cli::parse_addr};
 use wolf_vm::{
-    memory::Memory,
-    write_memory::WriteMemory,
-    registers::Registers,
-    flags::Flags,
-    io::Stdio,
-    machine::{Machine, ProgramStatus},
+    machine::{Machine, MachineConfig, RunLimits, ExecutionError, DEFAULT_MEMORY_SIZE},
+    debugger::Debugger,
+    trace::{Tracer, TraceStep, TraceFormat},
+    profile::Profiler,
 };
 
-const MACHINE_MEMORY: usize = 4 * 1024; // 4 kb
+/// A command line argument that configures the format of the trace
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceFormatArg(pub TraceFormat);
 
-/// The address where program execution should start
-const START_ADDR: u64 = 0;
+impl TraceFormatArg {
+    /// Allowed values the argument
+    pub const VARIANTS: &'static [&'static str] = &["text", "json"];
+}
+
+impl FromStr for TraceFormatArg {
+    type Err = &'static str;
+
+    fn from_str(src: &str) -> Result<TraceFormatArg, &'static str> {
+        match src {
+            _ if src.eq_ignore_ascii_case("text") => Ok(TraceFormatArg(TraceFormat::Text)),
+            _ if src.eq_ignore_ascii_case("json") => Ok(TraceFormatArg(TraceFormat::Json)),
+            _ => Err("valid values: text, json"),
+        }
+    }
+}
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "wolf-vm", about)]
@@ -30,44 +48,314 @@ struct VMOptions {
     /// The executable file generated by the wolf-asm assembler
     #[structopt(name = "input", parse(from_os_str))]
     executable_path: PathBuf,
+    /// Run the program in an interactive debugger
+    #[structopt(long = "debug")]
+    debug: bool,
+    /// Load the input as a raw memory image (generated with `wolf-asm --format=raw`) instead of an
+    /// executable
+    #[structopt(long = "raw")]
+    raw: bool,
+    /// The amount of memory available to the program in bytes (e.g. 4096, 0x1000, 64K, 1M)
+    #[structopt(long = "memory", name = "size", parse(try_from_str = parse_size))]
+    memory_size: Option<usize>,
+    /// The address just past the end of the stack [default: the end of memory]
+    #[structopt(long = "stack-end", name = "stack-addr", parse(try_from_str = parse_addr))]
+    stack_end: Option<u64>,
+    /// The address where the program is loaded into memory. Must match the `--load-addr` passed to
+    /// the assembler.
+    #[structopt(long = "load-addr", name = "load-addr", default_value = "0", parse(try_from_str = parse_addr))]
+    load_addr: u64,
+    /// The address of the first instruction to run [default: the entry point of the program]
+    #[structopt(long = "entry", name = "entry-addr", parse(try_from_str = parse_addr))]
+    entry_addr: Option<u64>,
+    /// Stop the program with an error after it executes this many instructions
+    #[structopt(long = "max-steps", name = "steps", parse(try_from_str = parse_steps))]
+    max_steps: Option<u64>,
+    /// Stop the program with an error if it is still running after this many seconds (e.g. 2, 0.5)
+    #[structopt(long = "timeout", name = "seconds", parse(try_from_str = parse_timeout))]
+    timeout: Option<Duration>,
+    /// Write every instruction executed and the registers, flags, and memory it changed to the
+    /// given file, or to stderr if no file is given
+    #[structopt(long = "trace", name = "trace-file", parse(from_os_str), min_values = 0,
+        max_values = 1, require_equals = true, conflicts_with = "debug")]
+    trace: Option<Vec<PathBuf>>,
+    /// The format of the trace: text for people to read, or one JSON object per line for other
+    /// programs to read
+    #[structopt(long = "trace-format", parse(try_from_str), default_value = "text",
+        possible_values = TraceFormatArg::VARIANTS, case_insensitive = true)]
+    trace_format: TraceFormatArg,
+    /// Only trace the instructions in the given range of addresses (e.g. `0x10..0x40`) or in the
+    /// given label (e.g. `main.loop`, requires an executable assembled with `-g`). May be used
+    /// multiple times.
+    #[structopt(long = "trace-filter", name = "addrs|label", number_of_values = 1)]
+    trace_filters: Vec<String>,
+    /// Count how many instructions are executed by each function and instruction, and print the
+    /// functions and instructions that ran the most to stderr when the program exits
+    #[structopt(long = "profile", conflicts_with = "debug")]
+    profile: bool,
+    /// Write the number of instructions executed in each call stack to the given file in the
+    /// collapsed stack format read by flamegraph tools (e.g. `flamegraph.pl` or `inferno-flamegraph`)
+    #[structopt(long = "profile-stacks", name = "stacks-file", parse(from_os_str), conflicts_with = "debug")]
+    profile_stacks: Option<PathBuf>,
+}
+
+/// Parses a number of bytes with an optional `K`, `M`, or `G` suffix (powers of 1024)
+fn parse_size(src: &str) -> Result<usize, String> {
+    let (digits, multiplier) = match src.char_indices().last() {
+        Some((index, 'k')) | Some((index, 'K')) => (&src[..index], 1 << 10),
+        Some((index, 'm')) | Some((index, 'M')) => (&src[..index], 1 << 20),
+        Some((index, 'g')) | Some((index, 'G')) => (&src[..index], 1 << 30),
+        _ => (src, 1),
+    };
+
+    parse_addr(digits).ok()
+        .and_then(|size| (size as usize).checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size `{}`", src))
+}
+
+/// Parses a decimal or hexadecimal (`0x` prefix) number of instructions
+fn parse_steps(src: &str) -> Result<u64, String> {
+    parse_addr(src).map_err(|_| format!("invalid number of steps `{}`", src))
+}
+
+/// Parses a non-negative number of seconds, which may be fractional
+fn parse_timeout(src: &str) -> Result<Duration, String> {
+    src.parse().ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("invalid timeout `{}`", src))
 }
 
 fn main() -> anyhow::Result<()> {
-    let VMOptions {executable_path} = VMOptions::from_args();
-
-    let executable_file = File::open(&executable_path)
-        .with_context(|| format!("Failed to read executable: `{}`", executable_path.display()))?;
-    let exec: Executable = bincode::deserialize_from(executable_file)
-        .with_context(|| format!("Failed to deserialize executable: `{}`", executable_path.display()))?;
-
-    let mut memory = Memory::new(MACHINE_MEMORY);
-    // Write the executable at the starting address
-    exec.write_into(&mut memory, START_ADDR)
-        .context("Failed to load executable into memory")?;
-
-    // Start with the stack pointer pointing just past the end of the stack
-    let registers = Registers::new(MACHINE_MEMORY);
-    let flags = Flags::default();
-    let io = Stdio::default();
-
-    let mut vm = Machine {
-        program_counter: START_ADDR,
-        memory,
-        registers,
-        flags,
-        io,
+    let VMOptions {
+        executable_path,
+        debug,
+        raw,
+        memory_size,
+        stack_end,
+        load_addr,
+        entry_addr,
+        max_steps,
+        timeout,
+        trace,
+        trace_format,
+        trace_filters,
+        profile,
+        profile_stacks,
+    } = VMOptions::from_args();
+
+    let mut config = MachineConfig::new()
+        .with_memory_size(memory_size.unwrap_or(DEFAULT_MEMORY_SIZE))
+        .with_load_addr(load_addr);
+    if let Some(stack_end) = stack_end {
+        config = config.with_stack_end(stack_end);
+    }
+    if let Some(entry_addr) = entry_addr {
+        config = config.with_entry_addr(entry_addr);
+    }
+
+    let (mut vm, debug_info) = if raw {
+        let image = fs::read(&executable_path)
+            .with_context(|| format!("Failed to read raw memory image: `{}`", executable_path.display()))?;
+        // Raw images always start executing at their first byte and have no debug info
+        let vm = config.build(&image[..], 0)
+            .context("Failed to load raw memory image into memory")?;
+        (vm, None)
+
+    } else {
+        let executable_file = File::open(&executable_path)
+            .with_context(|| format!("Failed to read executable: `{}`", executable_path.display()))?;
+        let exec = Executable::read_from(executable_file)
+            .with_context(|| format!("Failed to load executable: `{}`", executable_path.display()))?;
+
+        let vm = config.build(&exec, exec.entry)
+            .context("Failed to load executable into memory")?;
+        (vm, exec.debug_info)
     };
-    vm.push_quit_addr()
-        .expect("bug: should always be able to push quit address");
 
-    loop {
+    let debug_info = debug_info.as_ref();
+    if debug {
+        run_debugger(&mut vm, debug_info, load_addr).context("Failed to run debugger")?;
+    } else {
+        let mut limits = RunLimits::new();
+        if let Some(max_steps) = max_steps {
+            limits = limits.with_max_steps(max_steps);
+        }
+        if let Some(timeout) = timeout {
+            limits = limits.with_timeout(timeout);
+        }
+
+        let mut tracer = match &trace {
+            // `--trace` without a file writes to stderr
+            Some(trace_paths) => {
+                let trace_path = trace_paths.first().map(|path| path.as_path());
+                Some(create_tracer(trace_path, trace_format.0, &trace_filters, debug_info, load_addr)?)
+            },
+            None => None,
+        };
+
+        let mut profiler = if profile || profile_stacks.is_some() {
+            let profiler = Profiler::new();
+            Some(match debug_info {
+                Some(debug_info) => profiler.with_debug_info(debug_info, load_addr),
+                None => profiler,
+            })
+        } else {
+            None
+        };
+
+        let result = run_program(&mut vm, &limits, tracer.as_mut(), profiler.as_mut(), debug_info, load_addr);
+
+        // The trace and profile are written even if the program fails since they can help explain
+        // why it failed
+        if let Some(tracer) = &mut tracer {
+            tracer.flush().context("Failed to write trace")?;
+        }
+        if let Some(profiler) = &profiler {
+            if profile {
+                let stderr = io::stderr();
+                profiler.write_report(&vm, &mut stderr.lock()).context("Failed to write profile")?;
+            }
+
+            if let Some(stacks_path) = &profile_stacks {
+                let stacks_file = File::create(stacks_path)
+                    .with_context(|| format!("Failed to create profile stacks file: `{}`", stacks_path.display()))?;
+                let mut stacks_file = io::BufWriter::new(stacks_file);
+                profiler.write_collapsed_stacks(&mut stacks_file)
+                    .and_then(|()| stacks_file.flush())
+                    .with_context(|| format!("Failed to write profile stacks file: `{}`", stacks_path.display()))?;
+            }
+        }
+
+        result?;
+    }
+
+    if let Some(code) = vm.exit_code {
+        process::exit(code);
+    }
+
+    Ok(())
+}
+
+/// Creates a tracer that writes to the given file (or to stderr) and only traces the instructions
+/// that match at least one of the given filters
+fn create_tracer<'a>(
+    trace_path: Option<&Path>,
+    format: TraceFormat,
+    filters: &[String],
+    debug_info: Option<&'a DebugInfo>,
+    load_addr: u64,
+) -> anyhow::Result<Tracer<'a>> {
+    let mut tracer = match trace_path {
+        Some(trace_path) => {
+            let trace_file = File::create(trace_path)
+                .with_context(|| format!("Failed to create trace file: `{}`", trace_path.display()))?;
+            Tracer::new(io::BufWriter::new(trace_file), format)
+        },
+        None => Tracer::new(io::LineWriter::new(io::stderr()), format),
+    };
+
+    if let Some(debug_info) = debug_info {
+        tracer = tracer.with_debug_info(debug_info, load_addr);
+    }
+
+    for filter in filters {
+        let range = match filter.split_once("..") {
+            Some((start, end)) => {
+                let range = parse_addr(start).and_then(|start| Ok(start..parse_addr(end)?));
+                range.map_err(anyhow::Error::msg)
+                    .with_context(|| format!("Invalid trace filter `{}`", filter))?
+            },
+
+            None => {
+                let debug_info = debug_info.with_context(|| format!("Cannot trace the label `{}` \
+                    because the executable has no debug info (assemble it with `-g`)", filter))?;
+                let range = debug_info.label_range(filter)
+                    .with_context(|| format!("Cannot trace the label `{}` because it does not exist", filter))?;
+                load_addr + range.start..load_addr + range.end
+            },
+        };
+
+        tracer = tracer.with_filter(range);
+    }
+
+    Ok(tracer)
+}
+
+/// Runs the program until it quits, writing each step to the tracer and the profiler (if any)
+fn run_program(
+    vm: &mut Machine,
+    limits: &RunLimits,
+    mut tracer: Option<&mut Tracer>,
+    mut profiler: Option<&mut Profiler>,
+    debug_info: Option<&DebugInfo>,
+    load_addr: u64,
+) -> anyhow::Result<()> {
+    let describe = |pc: u64| debug_info.and_then(|info| info.describe(pc.wrapping_sub(load_addr)));
+
+    let result = vm.run_with(limits, |vm| {
         let pc = vm.program_counter;
-        let status = vm.step()
-            .with_context(|| format!("Failed to execute instruction at `0x{:x}`", pc))?;
+        if let Some(profiler) = &mut profiler {
+            profiler.record(vm);
+        }
+
+        let status = match &mut tracer {
+            Some(tracer) => {
+                let (step, status) = TraceStep::execute(vm);
+                tracer.write(&step).context("Failed to write trace")?;
+                status
+            },
+            None => vm.step(),
+        };
+        status.with_context(|| match describe(pc) {
+            Some(location) => format!("Runtime error at {}", location),
+            None => format!("Failed to execute instruction at `0x{:x}`", pc),
+        })
+    });
+
+    match result {
+        Ok(_) => Ok(()),
+        // The limits stop the program before it executes the instruction at the program counter
+        Err(err) => match err.downcast_ref() {
+            Some(ExecutionError::OutOfSteps {..}) | Some(ExecutionError::DeadlineExceeded {..}) => {
+                let pc = vm.program_counter;
+                Err(err.context(match describe(pc) {
+                    Some(location) => format!("Program stopped at {}", location),
+                    None => format!("Program stopped at `0x{:x}`", pc),
+                }))
+            },
+            _ => Err(err),
+        },
+    }
+}
+
+fn run_debugger(vm: &mut Machine, debug_info: Option<&DebugInfo>, load_addr: u64) -> io::Result<()> {
+    let mut debugger = Debugger::new();
+    if let Some(debug_info) = debug_info {
+        for symbol in &debug_info.symbols {
+            debugger.add_label(symbol.name.clone(), load_addr + symbol.addr);
+        }
+    }
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    writeln!(stdout, "Type `help` for a list of commands")?;
+    debugger.print_location(vm, &mut stdout)?;
+
+    let mut line = String::new();
+    loop {
+        write!(stdout, "(wolf) ")?;
+        stdout.flush()?;
+
+        // Only lock stdin while reading a line so that the program can still read its own input
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            // Quit at EOF
+            writeln!(stdout)?;
+            break;
+        }
 
-        match status {
-            ProgramStatus::Continue => {},
-            ProgramStatus::Quit => break,
+        if !debugger.execute(vm, &line, &mut stdout)? {
+            break;
         }
     }
 
@@ -0,0 +1,178 @@
+//! Memory mapped devices
+//!
+//! Devices claim a range of addresses on the bus. Any load or store instruction that accesses an
+//! address claimed by a device is handled by that device. The machine memory is not itself on
+//! the bus: it handles every address that no device claims, so a device registered over a range
+//! of memory shadows that range. By default, the bus contains a device for stdin at `STDIN_ADDR`
+//! and one for stdout at `STDOUT_ADDR`.
+//!
+//! Only load and store instructions go through the bus. Instructions are always fetched from
+//! memory, and the stack and syscall buffers must always be in memory, so the machine memory
+//! always starts at address zero and cannot be remapped.
+
+use std::fmt;
+use std::ops::Range;
+
+use thiserror::Error;
+
+use crate::io::Io;
+use crate::memory::Memory;
+use crate::execute::{ExecuteError, STDIN_ADDR, STDOUT_ADDR, EOF_BYTE};
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum RegisterError {
+    #[error("Device address range `0x{start:x}..0x{end:x}` is empty")]
+    EmptyRange {start: u64, end: u64},
+    #[error("Device address range `0x{start:x}..0x{end:x}` overlaps the device at `0x{other_start:x}..0x{other_end:x}`")]
+    Overlap {start: u64, end: u64, other_start: u64, other_end: u64},
+}
+
+/// A memory mapped device
+pub trait Device: fmt::Debug + Send {
+    /// Reads a value of `size` bytes (1, 2, 4, or 8) at the given offset from the start of the
+    /// address range of the device
+    ///
+    /// The value will be truncated to `size` bytes and then sign-extended or zero-extended
+    /// depending on the instruction.
+    fn load(&mut self, offset: u64, size: u64, io: &mut dyn Io) -> Result<u64, ExecuteError>;
+
+    /// Writes the lowest `size` bytes (1, 2, 4, or 8) of the given value at the given offset from
+    /// the start of the address range of the device
+    fn store(&mut self, offset: u64, size: u64, value: u64, io: &mut dyn Io) -> Result<(), ExecuteError>;
+}
+
+/// Memory can be mapped onto the bus to add more memory at a different address, e.g. for a
+/// display buffer
+impl Device for Memory {
+    fn load(&mut self, offset: u64, size: u64, _io: &mut dyn Io) -> Result<u64, ExecuteError> {
+        Ok(match size {
+            1 => self.get(offset)? as u64,
+            2 => self.read_u16(offset)? as u64,
+            4 => self.read_u32(offset)? as u64,
+            8 => self.read_u64(offset)?,
+            _ => unreachable!("bug: invalid load size `{}`", size),
+        })
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64, _io: &mut dyn Io) -> Result<(), ExecuteError> {
+        match size {
+            1 => self.set(offset, value as u8)?,
+            2 => self.write_u16(offset, value as u16)?,
+            4 => self.write_u32(offset, value as u32)?,
+            8 => self.write_u64(offset, value)?,
+            _ => unreachable!("bug: invalid store size `{}`", size),
+        }
+
+        Ok(())
+    }
+}
+
+/// Loading from this device reads a single byte of input, or `EOF_BYTE` if there is no more input
+///
+/// Stores to this device are ignored. They do not write to the memory at the same address.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StdinDevice;
+
+impl Device for StdinDevice {
+    fn load(&mut self, _offset: u64, _size: u64, io: &mut dyn Io) -> Result<u64, ExecuteError> {
+        Ok(io.read_byte()?.unwrap_or(EOF_BYTE) as u64)
+    }
+
+    fn store(&mut self, _offset: u64, _size: u64, _value: u64, _io: &mut dyn Io) -> Result<(), ExecuteError> {
+        Ok(())
+    }
+}
+
+/// Storing to this device writes the lowest 4 bytes of the value as a `char`
+///
+/// Loads from this device always return zero. They do not read the memory at the same address.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StdoutDevice;
+
+impl Device for StdoutDevice {
+    fn load(&mut self, _offset: u64, _size: u64, _io: &mut dyn Io) -> Result<u64, ExecuteError> {
+        Ok(0)
+    }
+
+    fn store(&mut self, _offset: u64, _size: u64, value: u64, io: &mut dyn Io) -> Result<(), ExecuteError> {
+        io.write_bytes(value as u32)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct MappedDevice {
+    range: Range<u64>,
+    device: Box<dyn Device>,
+}
+
+/// The devices mapped into the address space of a machine
+#[derive(Debug, Default)]
+pub struct DeviceBus {
+    /// Sorted by the start of each range, and no two ranges overlap
+    devices: Vec<MappedDevice>,
+}
+
+impl DeviceBus {
+    /// Creates a bus with no devices
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a bus with the devices for stdin and stdout
+    ///
+    /// Each of these devices only claims its exact address, so the bus never handles accesses
+    /// that start anywhere else.
+    pub fn with_standard_devices() -> Self {
+        let mut bus = Self::new();
+        bus.register(STDIN_ADDR..STDIN_ADDR+1, StdinDevice)
+            .expect("bug: standard devices should not overlap");
+        bus.register(STDOUT_ADDR..STDOUT_ADDR+1, StdoutDevice)
+            .expect("bug: standard devices should not overlap");
+        bus
+    }
+
+    /// Maps a device onto the given range of addresses
+    ///
+    /// Any load or store that starts in the range is handled by the device, even if it ends
+    /// past the end of the range.
+    pub fn register(&mut self, range: Range<u64>, device: impl Device + 'static) -> Result<(), RegisterError> {
+        let Range {start, end} = range;
+        if start >= end {
+            return Err(RegisterError::EmptyRange {start, end});
+        }
+
+        if let Some(other) = self.devices.iter().find(|other| start < other.range.end && other.range.start < end) {
+            return Err(RegisterError::Overlap {
+                start,
+                end,
+                other_start: other.range.start,
+                other_end: other.range.end,
+            });
+        }
+
+        let index = self.devices.partition_point(|other| other.range.start < start);
+        self.devices.insert(index, MappedDevice {range, device: Box::new(device)});
+        Ok(())
+    }
+
+    /// Removes the device whose range starts at the given address, returning true if there was
+    /// such a device
+    pub fn unregister(&mut self, start: u64) -> bool {
+        let len = self.devices.len();
+        self.devices.retain(|mapped| mapped.range.start != start);
+        self.devices.len() != len
+    }
+
+    /// Returns the device that claims the given address and the offset of the address from the
+    /// start of its range
+    pub fn device_at(&mut self, addr: u64) -> Option<(&mut dyn Device, u64)> {
+        let index = self.devices.partition_point(|mapped| mapped.range.start <= addr).checked_sub(1)?;
+        let MappedDevice {range, device} = &mut self.devices[index];
+        if range.contains(&addr) {
+            Some((&mut **device, addr - range.start))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,460 @@
+//! An interactive debugger for programs running on the machine
+//!
+//! The debugger runs one command at a time (see `Debugger::execute`) so that the caller can decide
+//! where the commands come from and how the prompt is displayed.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use thiserror::Error;
+
+use crate::machine::{Machine, ProgramStatus};
+use crate::decode::Instr;
+use crate::hexdump::write_hexdump;
+
+/// The number of bytes shown by `examine` if no length is provided
+const DEFAULT_EXAMINE_LEN: u64 = 64;
+/// The number of instructions shown before and after the program counter by `disassemble` if no
+/// count is provided
+const DEFAULT_DISASSEMBLE_COUNT: u64 = 5;
+/// The number of registers shown on each line of the output of `registers`
+const REGISTERS_LINE_LEN: usize = 4;
+
+/// The text displayed by the `help` command
+pub const HELP: &str = "\
+Commands:
+  break <loc>         (b)    set a breakpoint at an address or label
+  delete <loc>        (d)    remove the breakpoint at an address or label
+  breakpoints         (bl)   list all breakpoints
+  step [n]            (s)    execute the next instruction (or the next n instructions)
+  continue            (c)    execute until a breakpoint is reached or the program quits
+  registers           (r)    print the values of the program counter and all registers
+  flags               (f)    print the values of the flags
+  examine <loc> [n]   (x)    print n bytes of memory (default: 64) starting at the given location
+  disassemble [n]     (dis)  print the n instructions (default: 5) before and after the program counter
+  help                (h)    print this message
+  quit                (q)    quit the debugger
+
+Addresses may be written in decimal or in hexadecimal with a `0x` prefix.
+Pressing enter without a command repeats the previous command.";
+
+/// A location in memory given to a debugger command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// An address in memory
+    Address(u64),
+    /// The address of a label in the executable
+    Label(Arc<str>),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Target::*;
+        match self {
+            Address(addr) => write!(f, "0x{:x}", addr),
+            Label(label) => write!(f, "{}", label),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Break(Target),
+    Delete(Target),
+    Breakpoints,
+    Step(u64),
+    Continue,
+    Registers,
+    Flags,
+    Examine {start: Target, len: u64},
+    Disassemble {count: u64},
+    Help,
+    Quit,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    #[error("Unknown command `{0}`. Type `help` for a list of commands.")]
+    UnknownCommand(String),
+    #[error("Missing argument for `{0}`. Type `help` for more information.")]
+    MissingArgument(&'static str),
+    #[error("Too many arguments for `{0}`. Type `help` for more information.")]
+    TooManyArguments(&'static str),
+    #[error("Invalid number `{0}`")]
+    InvalidNumber(String),
+    #[error("Unknown label `{0}`")]
+    UnknownLabel(Arc<str>),
+}
+
+impl Command {
+    /// Parses a single line of input as a command
+    ///
+    /// Returns Ok(None) if the line does not contain a command
+    pub fn parse(line: &str) -> Result<Option<Self>, CommandError> {
+        let mut words = line.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let args: Vec<_> = words.collect();
+
+        use Command::*;
+        let command = match name {
+            "break" | "b" => Break(parse_target(required_arg("break", &args)?)?),
+            "delete" | "d" => Delete(parse_target(required_arg("delete", &args)?)?),
+            "breakpoints" | "bl" => {
+                no_args("breakpoints", &args)?;
+                Breakpoints
+            },
+            "step" | "s" => Step(optional_arg("step", &args)?.map(parse_number).transpose()?.unwrap_or(1)),
+            "continue" | "c" => {
+                no_args("continue", &args)?;
+                Continue
+            },
+            "registers" | "r" => {
+                no_args("registers", &args)?;
+                Registers
+            },
+            "flags" | "f" => {
+                no_args("flags", &args)?;
+                Flags
+            },
+            "examine" | "x" => match args[..] {
+                [] => return Err(CommandError::MissingArgument("examine")),
+                [start] => Examine {start: parse_target(start)?, len: DEFAULT_EXAMINE_LEN},
+                [start, len] => Examine {start: parse_target(start)?, len: parse_number(len)?},
+                _ => return Err(CommandError::TooManyArguments("examine")),
+            },
+            "disassemble" | "dis" => {
+                let count = optional_arg("disassemble", &args)?.map(parse_number).transpose()?;
+                Disassemble {count: count.unwrap_or(DEFAULT_DISASSEMBLE_COUNT)}
+            },
+            "help" | "h" => Help,
+            "quit" | "q" => Quit,
+            _ => return Err(CommandError::UnknownCommand(name.to_string())),
+        };
+
+        Ok(Some(command))
+    }
+}
+
+fn no_args(name: &'static str, args: &[&str]) -> Result<(), CommandError> {
+    if args.is_empty() {
+        Ok(())
+    } else {
+        Err(CommandError::TooManyArguments(name))
+    }
+}
+
+fn optional_arg<'a>(name: &'static str, args: &[&'a str]) -> Result<Option<&'a str>, CommandError> {
+    match *args {
+        [] => Ok(None),
+        [arg] => Ok(Some(arg)),
+        _ => Err(CommandError::TooManyArguments(name)),
+    }
+}
+
+fn required_arg<'a>(name: &'static str, args: &[&'a str]) -> Result<&'a str, CommandError> {
+    optional_arg(name, args)?.ok_or(CommandError::MissingArgument(name))
+}
+
+fn parse_number(value: &str) -> Result<u64, CommandError> {
+    let digits = value.replace('_', "");
+    let parsed = if let Some(hex_digits) = digits.strip_prefix("0x") {
+        u64::from_str_radix(hex_digits, 16)
+    } else {
+        digits.parse()
+    };
+
+    parsed.map_err(|_| CommandError::InvalidNumber(value.to_string()))
+}
+
+fn parse_target(value: &str) -> Result<Target, CommandError> {
+    if value.starts_with(|ch: char| ch.is_ascii_digit()) {
+        parse_number(value).map(Target::Address)
+    } else {
+        // Labels are case-insensitive
+        Ok(Target::Label(value.to_ascii_lowercase().into()))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    /// The addresses of every breakpoint
+    breakpoints: BTreeSet<u64>,
+    /// The address of each label
+    label_addrs: HashMap<Arc<str>, u64>,
+    /// The label at each address (only one label is kept if several labels share an address)
+    addr_labels: BTreeMap<u64, Arc<str>>,
+    /// The command that is repeated if an empty line is entered
+    last_command: Option<Command>,
+    /// True if the program has quit or could not continue because of an error
+    finished: bool,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a label that can be used to refer to the given address
+    pub fn add_label(&mut self, label: Arc<str>, addr: u64) {
+        self.addr_labels.entry(addr).or_insert_with(|| label.clone());
+        self.label_addrs.insert(label, addr);
+    }
+
+    /// Returns true if the program has quit or could not continue because of an error
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Parses and runs a single line of input, writing any output to `out`
+    ///
+    /// Returns Ok(false) if the debugger should quit
+    pub fn execute(&mut self, vm: &mut Machine, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let command = match Command::parse(line) {
+            Ok(Some(command)) => command,
+            Ok(None) => match self.last_command.clone() {
+                Some(command) => command,
+                None => return Ok(true),
+            },
+            Err(err) => {
+                writeln!(out, "{}", err)?;
+                return Ok(true);
+            },
+        };
+
+        self.last_command = Some(command.clone());
+        match self.run_command(vm, command, out) {
+            Ok(keep_going) => Ok(keep_going),
+            Err(CommandErrorOrIo::Command(err)) => {
+                writeln!(out, "{}", err)?;
+                Ok(true)
+            },
+            Err(CommandErrorOrIo::Io(err)) => Err(err),
+        }
+    }
+
+    /// Prints the current location of the program counter and the instruction at that location
+    pub fn print_location(&self, vm: &Machine, out: &mut impl Write) -> io::Result<()> {
+        let pc = vm.program_counter;
+        writeln!(out, "{}: {}", self.describe_addr(pc), disassemble_at(vm, pc))
+    }
+
+    fn run_command(&mut self, vm: &mut Machine, command: Command, out: &mut impl Write) -> Result<bool, CommandErrorOrIo> {
+        use Command::*;
+        match command {
+            Break(target) => {
+                let addr = self.resolve(&target)?;
+                self.breakpoints.insert(addr);
+                writeln!(out, "Breakpoint set at {}", self.describe_addr(addr))?;
+            },
+
+            Delete(target) => {
+                let addr = self.resolve(&target)?;
+                if self.breakpoints.remove(&addr) {
+                    writeln!(out, "Breakpoint removed at {}", self.describe_addr(addr))?;
+                } else {
+                    writeln!(out, "No breakpoint at {}", self.describe_addr(addr))?;
+                }
+            },
+
+            Breakpoints => {
+                if self.breakpoints.is_empty() {
+                    writeln!(out, "No breakpoints")?;
+                }
+                for &addr in &self.breakpoints {
+                    writeln!(out, "{}", self.describe_addr(addr))?;
+                }
+            },
+
+            Step(count) => {
+                if self.check_running(out)? {
+                    for _ in 0..count {
+                        if !self.step(vm, out)? {
+                            break;
+                        }
+                    }
+
+                    if !self.finished {
+                        self.print_location(vm, out)?;
+                    }
+                }
+            },
+
+            Continue => {
+                if self.check_running(out)? {
+                    // Always execute at least one instruction so we can continue from a breakpoint
+                    while self.step(vm, out)? {
+                        if self.breakpoints.contains(&vm.program_counter) {
+                            writeln!(out, "Breakpoint reached")?;
+                            self.print_location(vm, out)?;
+                            break;
+                        }
+                    }
+                }
+            },
+
+            Registers => self.print_registers(vm, out)?,
+
+            Flags => {
+                let flags = &vm.flags;
+                writeln!(out, "CF={} ZF={} SF={} OF={}", flags.carry as u8, flags.zero as u8,
+                    flags.sign as u8, flags.overflow as u8)?;
+            },
+
+            Examine {start, len} => {
+                let start = self.resolve(&start)?;
+                self.print_memory(vm, start, len, out)?;
+            },
+
+            Disassemble {count} => {
+                if self.check_running(out)? {
+                    self.print_disassembly(vm, count, out)?;
+                }
+            },
+
+            Help => writeln!(out, "{}", HELP)?,
+
+            Quit => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Returns Ok(true) if the program is still running, otherwise prints a message
+    fn check_running(&self, out: &mut impl Write) -> io::Result<bool> {
+        if self.finished {
+            writeln!(out, "The program is not running")?;
+        }
+
+        Ok(!self.finished)
+    }
+
+    /// Executes a single instruction, returning Ok(true) if the program can keep running
+    fn step(&mut self, vm: &mut Machine, out: &mut impl Write) -> io::Result<bool> {
+        let pc = vm.program_counter;
+        match vm.step() {
+            Ok(ProgramStatus::Continue) => Ok(true),
+
+            Ok(ProgramStatus::Quit) => {
+                self.finished = true;
+                match vm.exit_code {
+                    Some(code) => writeln!(out, "The program exited with code {}", code)?,
+                    None => writeln!(out, "The program exited")?,
+                }
+
+                Ok(false)
+            },
+
+            Err(err) => {
+                self.finished = true;
+                writeln!(out, "Failed to execute instruction at {}: {}", self.describe_addr(pc), err)?;
+
+                Ok(false)
+            },
+        }
+    }
+
+    fn print_registers(&self, vm: &Machine, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "pc = {}", self.describe_addr(vm.program_counter))?;
+
+        for (reg, (kind, value)) in vm.registers.iter().enumerate() {
+            let name = format!("${}", kind);
+            write!(out, "{:>4} = 0x{:016x}", name, value)?;
+
+            if reg % REGISTERS_LINE_LEN == REGISTERS_LINE_LEN - 1 {
+                writeln!(out)?;
+            } else {
+                write!(out, "  ")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn print_memory(&self, vm: &Machine, start: u64, len: u64, out: &mut impl Write) -> io::Result<()> {
+        let bytes = match vm.memory.slice(start..start.saturating_add(len)) {
+            Ok(bytes) => bytes,
+            Err(err) => return writeln!(out, "{}", err),
+        };
+
+        write_hexdump(out, "", start, bytes)
+    }
+
+    fn print_disassembly(&self, vm: &Machine, count: u64, out: &mut impl Write) -> io::Result<()> {
+        let pc = vm.program_counter;
+        // All instructions are currently 8 bytes
+        let instr_size = 8;
+        let start = pc.saturating_sub(count.saturating_mul(instr_size));
+        let end = pc.saturating_add(count.saturating_mul(instr_size));
+
+        for addr in (start..=end).step_by(instr_size as usize) {
+            // Stop once we run out of memory
+            if vm.memory.read_u64(addr).is_err() {
+                break;
+            }
+
+            if let Some(label) = self.addr_labels.get(&addr) {
+                writeln!(out, "{}:", label)?;
+            }
+
+            let marker = if addr == pc { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&addr) { "*" } else { " " };
+            writeln!(out, "{}{} 0x{:08x}: {}", marker, breakpoint, addr, disassemble_at(vm, addr))?;
+        }
+
+        Ok(())
+    }
+
+    fn resolve(&self, target: &Target) -> Result<u64, CommandError> {
+        match target {
+            &Target::Address(addr) => Ok(addr),
+            Target::Label(label) => self.label_addrs.get(label).copied()
+                .ok_or_else(|| CommandError::UnknownLabel(label.clone())),
+        }
+    }
+
+    /// Formats the address along with the closest label before it (if any)
+    fn describe_addr(&self, addr: u64) -> String {
+        match self.addr_labels.range(..=addr).next_back() {
+            Some((&label_addr, label)) if label_addr == addr => format!("0x{:x} <{}>", addr, label),
+            Some((&label_addr, label)) => format!("0x{:x} <{}+{}>", addr, label, addr - label_addr),
+            None => format!("0x{:x}", addr),
+        }
+    }
+}
+
+/// Decodes the instruction at the given address and formats it as assembly code
+fn disassemble_at(vm: &Machine, addr: u64) -> String {
+    let instr = match vm.memory.read_u64(addr) {
+        Ok(instr) => instr,
+        Err(err) => return format!("<{}>", err),
+    };
+
+    match Instr::decode(instr) {
+        Ok(instr) => instr.to_string(),
+        Err(err) => format!("<{}>", err),
+    }
+}
+
+/// Either a problem with the given command or an IO error while writing output
+#[derive(Debug)]
+enum CommandErrorOrIo {
+    Command(CommandError),
+    Io(io::Error),
+}
+
+impl From<CommandError> for CommandErrorOrIo {
+    fn from(err: CommandError) -> Self {
+        CommandErrorOrIo::Command(err)
+    }
+}
+
+impl From<io::Error> for CommandErrorOrIo {
+    fn from(err: io::Error) -> Self {
+        CommandErrorOrIo::Io(err)
+    }
+}
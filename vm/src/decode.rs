@@ -1,3 +1,5 @@
+use std::fmt;
+
 use wolf_asm::asm::{
     InstrKind,
     layout::{
@@ -68,6 +70,24 @@ macro_rules! instr {
             }
         }
 
+        impl fmt::Display for $instr_enum {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self {
+                    $($instr_enum::$instr_variant($instr_struct {$($instr_field),*}) => {
+                        write!(f, "{}", InstrKind::$instr_variant.name())?;
+
+                        let args: &[&dyn fmt::Display] = &[$($instr_field),*];
+                        for (i, arg) in args.iter().enumerate() {
+                            let sep = if i == 0 { " " } else { ", " };
+                            write!(f, "{}{}", sep, arg)?;
+                        }
+
+                        Ok(())
+                    }),*
+                }
+            }
+        }
+
         impl Execute for $instr_enum {
             fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
                 use $instr_enum::*;
@@ -161,6 +181,28 @@ instr! {
 
         Call(struct Call {loc: Location}),
         Ret(struct Ret {}),
+
+        Shl(struct Shl {dest: Destination, source: Source}),
+        Shr(struct Shr {dest: Destination, source: Source}),
+        Sal(struct Sal {dest: Destination, source: Source}),
+        Sar(struct Sar {dest: Destination, source: Source}),
+        Rol(struct Rol {dest: Destination, source: Source}),
+        Ror(struct Ror {dest: Destination, source: Source}),
+        Rcl(struct Rcl {dest: Destination, source: Source}),
+        Rcr(struct Rcr {dest: Destination, source: Source}),
+
+        Syscall(struct Syscall {}),
+
+        Fadd(struct Fadd {dest: Destination, source: Source}),
+        Fsub(struct Fsub {dest: Destination, source: Source}),
+        Fmul(struct Fmul {dest: Destination, source: Source}),
+        Fdiv(struct Fdiv {dest: Destination, source: Source}),
+        Fsqrt(struct Fsqrt {dest: Destination, source: Source}),
+
+        Fcmp(struct Fcmp {source1: Source, source2: Source}),
+
+        Itof(struct Itof {dest: Destination, source: Source}),
+        Ftoi(struct Ftoi {dest: Destination, source: Source}),
     }
 }
 
@@ -0,0 +1,196 @@
+//! Converts an executable back into assembly code
+//!
+//! The generated code can be assembled again to produce an identical executable. Debug information
+//! is only used to insert labels, so it is only reproduced if the code is assembled with `-g`.
+
+use std::io::{self, Write};
+use std::iter::Peekable;
+use std::sync::Arc;
+use std::collections::HashSet;
+
+use wolf_asm::executable::{self as exec, Executable, Symbol};
+use wolf_asm::local_labels::{is_numeric_label, unqualify};
+
+use crate::decode::Instr;
+use crate::hexdump::write_hexdump;
+
+/// The indent used for each statement
+const INDENT: &str = "    ";
+/// The width that each statement is padded to before the comment on the same line
+const STMT_WIDTH: usize = 32;
+
+/// Writes the statements of the executable as assembly code, with the address and encoding of each
+/// statement in a comment
+pub fn disassemble(exec: &Executable, out: &mut impl Write) -> io::Result<()> {
+    let Executable {code_section, static_section, debug_info, entry} = exec;
+
+    // Symbols are already sorted by address
+    let mut symbols = debug_info.as_ref().map(|info| info.symbols.clone()).unwrap_or_default();
+    rename_expanded_labels(&mut symbols);
+
+    // Without a `.entry` directive, the assembler defaults to `main` or the start of the code
+    // section, so the directive can only be skipped if that would produce the same entry point
+    let has_main = symbols.iter().any(|symbol| &*symbol.name == "main");
+    if !code_section.is_empty() && (*entry != 0 || has_main) {
+        let entry_label = entry_label(&mut symbols, *entry);
+        writeln!(out, ".entry {}", entry_label)?;
+        writeln!(out)?;
+    }
+
+    let mut symbols = symbols.iter().peekable();
+
+    let mut addr = 0;
+    if !code_section.is_empty() {
+        writeln!(out, "section .code")?;
+        writeln!(out)?;
+        addr = write_section(out, code_section, addr, &mut symbols)?;
+    }
+
+    if !static_section.is_empty() {
+        if !code_section.is_empty() {
+            writeln!(out)?;
+        }
+        writeln!(out, "section .static")?;
+        writeln!(out)?;
+        addr = write_section(out, static_section, addr, &mut symbols)?;
+    }
+
+    // Labels may be placed after the last statement in the program
+    write_labels(out, addr, &mut symbols)?;
+
+    Ok(())
+}
+
+/// Renames the labels from macro and `.rept` expansions (e.g. `loop@3`) so that they can be
+/// assembled again, using names that are not already taken (e.g. `loop_3`)
+fn rename_expanded_labels(symbols: &mut [Symbol]) {
+    let mut names: HashSet<Arc<str>> = symbols.iter().map(|symbol| symbol.name.clone()).collect();
+    for symbol in symbols {
+        if !symbol.name.contains('@') {
+            continue;
+        }
+
+        let mut name = symbol.name.replace('@', "_");
+        while names.contains(&*name) {
+            name.push('_');
+        }
+
+        symbol.name = name.into();
+        names.insert(symbol.name.clone());
+    }
+}
+
+/// Returns the name of a label at the entry point, adding a new label if necessary
+fn entry_label(symbols: &mut Vec<Symbol>, entry: u64) -> Arc<str> {
+    // Numeric labels cannot be referred to by `.entry`
+    if let Some(symbol) = symbols.iter().find(|symbol| symbol.addr == entry && !is_numeric_label(&symbol.name)) {
+        return symbol.name.clone();
+    }
+
+    // Find a name that isn't already taken
+    let name: Arc<str> = (0..)
+        .map(|i| if i == 0 { "entry".to_string() } else { format!("entry{}", i) })
+        .find(|name| symbols.iter().all(|symbol| &*symbol.name != name))
+        .unwrap()
+        .into();
+
+    let index = symbols.partition_point(|symbol| symbol.addr <= entry);
+    symbols.insert(index, Symbol {name: name.clone(), addr: entry});
+
+    name
+}
+
+/// Writes each statement starting at the given address, returning the address after the last
+/// statement
+fn write_section<'a>(
+    out: &mut impl Write,
+    stmts: &[exec::Stmt],
+    mut addr: u64,
+    symbols: &mut Peekable<impl Iterator<Item=&'a Symbol>>,
+) -> io::Result<u64> {
+    for stmt in stmts {
+        write_labels(out, addr, symbols)?;
+
+        addr = match stmt {
+            exec::Stmt::Instr(instr) => write_instr(out, instr.to_binary(), addr)?,
+            exec::Stmt::StaticData(data) => write_static_data(out, data, addr)?,
+        };
+    }
+
+    Ok(addr)
+}
+
+/// Writes every label that has not been written yet with an address less than or equal to `addr`
+///
+/// Local labels are always written after the label they are scoped to, so they can be written
+/// without their scope.
+fn write_labels<'a>(
+    out: &mut impl Write,
+    addr: u64,
+    symbols: &mut Peekable<impl Iterator<Item=&'a Symbol>>,
+) -> io::Result<()> {
+    while let Some(symbol) = symbols.next_if(|symbol| symbol.addr <= addr) {
+        writeln!(out, "{}:", unqualify(&symbol.name))?;
+    }
+
+    Ok(())
+}
+
+/// Writes the given instruction, returning the address after the instruction
+fn write_instr(out: &mut impl Write, instr: u64, addr: u64) -> io::Result<u64> {
+    let stmt = match Instr::decode(instr) {
+        Ok(instr) => instr.to_string(),
+        // The assembler should never generate an invalid instruction, but if it does, we can at
+        // least reproduce the same bytes
+        Err(_) => format!(".b8 {}", instr),
+    };
+
+    write_stmt(out, &stmt, &format!("0x{:08x}: 0x{:016x}", addr, instr))?;
+
+    // All instructions are currently 8 bytes
+    Ok(addr + 8)
+}
+
+/// Writes the given static data followed by a hexdump of its value, returning the address after
+/// the data
+fn write_static_data(out: &mut impl Write, data: &exec::StaticData, addr: u64) -> io::Result<u64> {
+    use exec::StaticData::*;
+    let (stmt, bytes, size): (_, &[u8], _) = match data {
+        StaticBytes(exec::StaticBytes::B1(bytes)) => (format!(".b1 {}", u8::from_le_bytes(*bytes)), bytes, 1),
+        StaticBytes(exec::StaticBytes::B2(bytes)) => (format!(".b2 {}", u16::from_le_bytes(*bytes)), bytes, 2),
+        StaticBytes(exec::StaticBytes::B4(bytes)) => (format!(".b4 {}", u32::from_le_bytes(*bytes)), bytes, 4),
+        StaticBytes(exec::StaticBytes::B8(bytes)) => (format!(".b8 {}", u64::from_le_bytes(*bytes)), bytes, 8),
+        StaticZero(exec::StaticZero {nbytes}) => (format!(".zero {}", nbytes), &[], *nbytes),
+        StaticUninit(exec::StaticUninit {nbytes}) => (format!(".uninit {}", nbytes), &[], *nbytes),
+        StaticByteStr(exec::StaticByteStr {bytes}) => (format!(".bytes '{}'", escape_bytes(bytes)), bytes, bytes.len() as u64),
+    };
+
+    write_stmt(out, &stmt, &format!("0x{:08x}", addr))?;
+    write_hexdump(out, &format!("{}# ", INDENT), addr, bytes)?;
+
+    Ok(addr + size)
+}
+
+fn write_stmt(out: &mut impl Write, stmt: &str, comment: &str) -> io::Result<()> {
+    writeln!(out, "{}{:width$} # {}", INDENT, stmt, comment, width=STMT_WIDTH)
+}
+
+/// Escapes the bytes so they can be placed in a single-quoted byte string literal
+fn escape_bytes(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        match byte {
+            b'\\' => escaped.push_str("\\\\"),
+            b'\'' => escaped.push_str("\\'"),
+            b'\n' => escaped.push_str("\\n"),
+            b'\r' => escaped.push_str("\\r"),
+            b'\t' => escaped.push_str("\\t"),
+            b'\0' => escaped.push_str("\\0"),
+            b' ' => escaped.push(' '),
+            _ if byte.is_ascii_graphic() => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{{{:02x}}}", byte)),
+        }
+    }
+
+    escaped
+}
@@ -8,6 +8,7 @@ use crate::memory::OutOfBounds;
 use crate::flags::{Flags, CF, ZF, SF, OF};
 use crate::operands::{StoreDestination, Operand};
 use crate::decode::*;
+use crate::syscall;
 
 /// The address used to indicate that the program should quit
 pub const QUIT_ADDR: u64 = u64::MAX;
@@ -17,6 +18,10 @@ pub const STDOUT_ADDR: u64 = 0xffff_000c;
 pub const STDIN_ADDR: u64 = 0xffff_0004;
 /// The byte used to indicate EOF
 pub const EOF_BYTE: u8 = b'\0';
+/// The bits of the count used by shift and rotate instructions
+const SHIFT_COUNT_MASK: u64 = 0x3f;
+/// The 65 bits used when rotating through the carry flag
+const RCX_MASK: u128 = (1 << 65) - 1;
 
 fn size_bytes_of<T>() -> u64 {
     std::mem::size_of::<T>() as u64
@@ -30,6 +35,8 @@ pub enum ExecuteError {
     OutOfBounds(#[from] OutOfBounds),
     #[error("Divided a number by zero")]
     DivideByZero,
+    #[error("Unknown syscall number `{0}`")]
+    UnknownSyscall(u64),
 }
 
 pub trait Execute {
@@ -455,6 +462,337 @@ impl Execute for Not {
     }
 }
 
+impl Execute for Shl {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Shl {dest, source} = self;
+        let value: u64 = dest.into_value(vm);
+        let count: u64 = source.into_value(vm);
+
+        // Only the lower 6 bits of the count are used
+        let count = (count & SHIFT_COUNT_MASK) as u32;
+        // No flags are affected if the count is zero
+        if count == 0 {
+            return Ok(());
+        }
+
+        let result = value << count;
+
+        // The carry flag contains the last bit shifted out of the operand
+        let carry = if (value >> (64 - count)) & 1 == 1 {
+            CF::Carry
+        } else {
+            CF::NoCarry
+        };
+
+        let zero = if result == 0 {
+            ZF::Zero
+        } else {
+            ZF::NonZero
+        };
+
+        let sign = if (1u64 << 63) & result > 0 {
+            SF::NegativeSign
+        } else {
+            SF::PositiveSign
+        };
+
+        // Overflow is set if the sign bit was changed by the shift (MSB of the result XOR CF)
+        // See: https://www.felixcloutier.com/x86/sal:sar:shl:shr
+        let overflow = if (sign == SF::NegativeSign) != (carry == CF::Carry) {
+            OF::Overflow
+        } else {
+            OF::NoOverflow
+        };
+
+        vm.store_dest(dest, result);
+        vm.flags = Flags {carry, zero, sign, overflow};
+
+        Ok(())
+    }
+}
+
+impl Execute for Shr {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Shr {dest, source} = self;
+        let value: u64 = dest.into_value(vm);
+        let count: u64 = source.into_value(vm);
+
+        // Only the lower 6 bits of the count are used
+        let count = (count & SHIFT_COUNT_MASK) as u32;
+        // No flags are affected if the count is zero
+        if count == 0 {
+            return Ok(());
+        }
+
+        // shr is a logical shift, so the upper bits are filled with zeros
+        let result = value >> count;
+
+        // The carry flag contains the last bit shifted out of the operand
+        let carry = if (value >> (count - 1)) & 1 == 1 {
+            CF::Carry
+        } else {
+            CF::NoCarry
+        };
+
+        let zero = if result == 0 {
+            ZF::Zero
+        } else {
+            ZF::NonZero
+        };
+
+        let sign = if (1u64 << 63) & result > 0 {
+            SF::NegativeSign
+        } else {
+            SF::PositiveSign
+        };
+
+        // Overflow is set to the sign bit of the original operand
+        // See: https://www.felixcloutier.com/x86/sal:sar:shl:shr
+        let overflow = if (1u64 << 63) & value > 0 {
+            OF::Overflow
+        } else {
+            OF::NoOverflow
+        };
+
+        vm.store_dest(dest, result);
+        vm.flags = Flags {carry, zero, sign, overflow};
+
+        Ok(())
+    }
+}
+
+impl Execute for Sal {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        // Note: sal and shl have the same behaviour
+        let Sal {dest, source} = self;
+        Shl {dest, source}.execute(vm)
+    }
+}
+
+impl Execute for Sar {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Sar {dest, source} = self;
+        // sar is an arithmetic shift, so the value is loaded as signed
+        let value: i64 = dest.into_value(vm);
+        let count: u64 = source.into_value(vm);
+
+        // Only the lower 6 bits of the count are used
+        let count = (count & SHIFT_COUNT_MASK) as u32;
+        // No flags are affected if the count is zero
+        if count == 0 {
+            return Ok(());
+        }
+
+        // Shifting a signed value fills the upper bits with the sign bit
+        let result = value >> count;
+
+        // The carry flag contains the last bit shifted out of the operand
+        let carry = if (value >> (count - 1)) & 1 == 1 {
+            CF::Carry
+        } else {
+            CF::NoCarry
+        };
+
+        let zero = if result == 0 {
+            ZF::Zero
+        } else {
+            ZF::NonZero
+        };
+
+        let sign = if result < 0 {
+            SF::NegativeSign
+        } else {
+            SF::PositiveSign
+        };
+
+        // The sign can never change, so overflow is always cleared
+        let overflow = OF::NoOverflow;
+
+        vm.store_dest(dest, result);
+        vm.flags = Flags {carry, zero, sign, overflow};
+
+        Ok(())
+    }
+}
+
+impl Execute for Rol {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Rol {dest, source} = self;
+        let value: u64 = dest.into_value(vm);
+        let count: u64 = source.into_value(vm);
+
+        // Only the lower 6 bits of the count are used
+        let count = (count & SHIFT_COUNT_MASK) as u32;
+        // No flags are affected if the count is zero
+        if count == 0 {
+            return Ok(());
+        }
+
+        let result = value.rotate_left(count);
+
+        // The carry flag contains the last bit rotated out of the MSB (now the LSB)
+        let carry = if result & 1 == 1 {
+            CF::Carry
+        } else {
+            CF::NoCarry
+        };
+
+        // Overflow is the MSB of the result XOR CF
+        // See: https://www.felixcloutier.com/x86/rcl:rcr:rol:ror
+        let overflow = if ((1u64 << 63) & result > 0) != (carry == CF::Carry) {
+            OF::Overflow
+        } else {
+            OF::NoOverflow
+        };
+
+        vm.store_dest(dest, result);
+        // Update carry and overflow only, all other flags are unaffected
+        vm.flags = Flags {
+            carry,
+            overflow,
+            ..vm.flags
+        };
+
+        Ok(())
+    }
+}
+
+impl Execute for Ror {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Ror {dest, source} = self;
+        let value: u64 = dest.into_value(vm);
+        let count: u64 = source.into_value(vm);
+
+        // Only the lower 6 bits of the count are used
+        let count = (count & SHIFT_COUNT_MASK) as u32;
+        // No flags are affected if the count is zero
+        if count == 0 {
+            return Ok(());
+        }
+
+        let result = value.rotate_right(count);
+
+        // The carry flag contains the last bit rotated out of the LSB (now the MSB)
+        let carry = if (1u64 << 63) & result > 0 {
+            CF::Carry
+        } else {
+            CF::NoCarry
+        };
+
+        // Overflow is the XOR of the two most significant bits of the result
+        // See: https://www.felixcloutier.com/x86/rcl:rcr:rol:ror
+        let overflow = if ((1u64 << 63) & result > 0) != ((1u64 << 62) & result > 0) {
+            OF::Overflow
+        } else {
+            OF::NoOverflow
+        };
+
+        vm.store_dest(dest, result);
+        // Update carry and overflow only, all other flags are unaffected
+        vm.flags = Flags {
+            carry,
+            overflow,
+            ..vm.flags
+        };
+
+        Ok(())
+    }
+}
+
+impl Execute for Rcl {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Rcl {dest, source} = self;
+        let value: u64 = dest.into_value(vm);
+        let count: u64 = source.into_value(vm);
+
+        // Only the lower 6 bits of the count are used
+        let count = (count & SHIFT_COUNT_MASK) as u32;
+        // No flags are affected if the count is zero
+        if count == 0 {
+            return Ok(());
+        }
+
+        // The carry flag is rotated along with the value, so we perform a 65-bit rotation with the
+        // carry flag as the most significant bit
+        let carry_bit = vm.flags.carry as u128;
+        let value = (carry_bit << 64) | u128::reinterpret(value);
+        let rotated = ((value << count) | (value >> (65 - count))) & RCX_MASK;
+
+        let result = u64::reinterpret(rotated);
+
+        let carry = if rotated >> 64 == 1 {
+            CF::Carry
+        } else {
+            CF::NoCarry
+        };
+
+        // Overflow is the MSB of the result XOR CF
+        // See: https://www.felixcloutier.com/x86/rcl:rcr:rol:ror
+        let overflow = if ((1u64 << 63) & result > 0) != (carry == CF::Carry) {
+            OF::Overflow
+        } else {
+            OF::NoOverflow
+        };
+
+        vm.store_dest(dest, result);
+        // Update carry and overflow only, all other flags are unaffected
+        vm.flags = Flags {
+            carry,
+            overflow,
+            ..vm.flags
+        };
+
+        Ok(())
+    }
+}
+
+impl Execute for Rcr {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Rcr {dest, source} = self;
+        let value: u64 = dest.into_value(vm);
+        let count: u64 = source.into_value(vm);
+
+        // Only the lower 6 bits of the count are used
+        let count = (count & SHIFT_COUNT_MASK) as u32;
+        // No flags are affected if the count is zero
+        if count == 0 {
+            return Ok(());
+        }
+
+        // The carry flag is rotated along with the value, so we perform a 65-bit rotation with the
+        // carry flag as the most significant bit
+        let carry_bit = vm.flags.carry as u128;
+        let value = (carry_bit << 64) | u128::reinterpret(value);
+        let rotated = ((value >> count) | (value << (65 - count))) & RCX_MASK;
+
+        let result = u64::reinterpret(rotated);
+
+        let carry = if rotated >> 64 == 1 {
+            CF::Carry
+        } else {
+            CF::NoCarry
+        };
+
+        // Overflow is the XOR of the two most significant bits of the result
+        // See: https://www.felixcloutier.com/x86/rcl:rcr:rol:ror
+        let overflow = if ((1u64 << 63) & result > 0) != ((1u64 << 62) & result > 0) {
+            OF::Overflow
+        } else {
+            OF::NoOverflow
+        };
+
+        vm.store_dest(dest, result);
+        // Update carry and overflow only, all other flags are unaffected
+        vm.flags = Flags {
+            carry,
+            overflow,
+            ..vm.flags
+        };
+
+        Ok(())
+    }
+}
+
 impl Execute for Test {
     fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
         let Test {source1, source2} = self;
@@ -543,11 +881,7 @@ impl Execute for Load1 {
 
         let addr: u64 = loc.into_value(vm);
         // load1 loads only 1 byte
-        let value = if addr == STDIN_ADDR {
-            u8::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
-        } else {
-            vm.memory.get(addr)?
-        };
+        let value = vm.load(addr, 1)? as u8;
         // load (unlike loadu) must sign-extend (hence i8)
         let value = i8::reinterpret(value);
         vm.store_dest(dest, value);
@@ -562,11 +896,7 @@ impl Execute for Loadu1 {
 
         let addr: u64 = loc.into_value(vm);
         // loadu1 loads only 1 byte
-        let value = if addr == STDIN_ADDR {
-            u8::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
-        } else {
-            vm.memory.get(addr)?
-        };
+        let value = vm.load(addr, 1)? as u8;
         // loadu (unlike load) must NOT sign-extend (hence u8 is fine)
         vm.store_dest(dest, value);
 
@@ -580,11 +910,7 @@ impl Execute for Load2 {
 
         let addr: u64 = loc.into_value(vm);
         // load2 loads 2 bytes
-        let value = if addr == STDIN_ADDR {
-            u16::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
-        } else {
-            vm.memory.read_u16(addr)?
-        };
+        let value = vm.load(addr, 2)? as u16;
         // load (unlike loadu) must sign-extend (hence i16)
         let value = i16::reinterpret(value);
         vm.store_dest(dest, value);
@@ -599,11 +925,7 @@ impl Execute for Loadu2 {
 
         let addr: u64 = loc.into_value(vm);
         // load2 loads 2 bytes
-        let value = if addr == STDIN_ADDR {
-            u16::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
-        } else {
-            vm.memory.read_u16(addr)?
-        };
+        let value = vm.load(addr, 2)? as u16;
         // loadu (unlike load) must NOT sign-extend (hence u16 is fine)
         vm.store_dest(dest, value);
 
@@ -617,11 +939,7 @@ impl Execute for Load4 {
 
         let addr: u64 = loc.into_value(vm);
         // load4 loads 4 bytes
-        let value = if addr == STDIN_ADDR {
-            u32::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
-        } else {
-            vm.memory.read_u32(addr)?
-        };
+        let value = vm.load(addr, 4)? as u32;
         // load (unlike loadu) must sign-extend (hence i32)
         let value = i32::reinterpret(value);
         vm.store_dest(dest, value);
@@ -636,11 +954,7 @@ impl Execute for Loadu4 {
 
         let addr: u64 = loc.into_value(vm);
         // load4 loads 4 bytes
-        let value = if addr == STDIN_ADDR {
-            u32::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
-        } else {
-            vm.memory.read_u32(addr)?
-        };
+        let value = vm.load(addr, 4)? as u32;
         // loadu (unlike load) must NOT sign-extend (hence u32 is fine)
         vm.store_dest(dest, value);
 
@@ -654,13 +968,7 @@ impl Execute for Load8 {
         let Load8 {dest, loc} = self;
 
         let addr: u64 = loc.into_value(vm);
-        let value = if addr == STDIN_ADDR {
-            u64::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
-        } else {
-            // Since the value is already 8 bytes, we don't need to worry about
-            // sign-extension
-            vm.memory.read_u64(addr)?
-        };
+        let value = vm.load(addr, 8)?;
         vm.store_dest(dest, value);
 
         Ok(())
@@ -673,13 +981,7 @@ impl Execute for Loadu8 {
         let Loadu8 {dest, loc} = self;
 
         let addr: u64 = loc.into_value(vm);
-        let value = if addr == STDIN_ADDR {
-            u64::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
-        } else {
-            // Since the value is already 8 bytes, we don't need to worry about
-            // zero-extension
-            vm.memory.read_u64(addr)?
-        };
+        let value = vm.load(addr, 8)?;
         vm.store_dest(dest, value);
 
         Ok(())
@@ -693,11 +995,7 @@ impl Execute for Store1 {
 
         let value: u8 = source.into_value(vm);
 
-        if addr == STDOUT_ADDR {
-            vm.io.write_bytes(u32::reinterpret(value))?;
-        } else {
-            vm.memory.set(addr, value)?;
-        }
+        vm.store(addr, 1, value as u64)?;
 
         Ok(())
     }
@@ -710,11 +1008,7 @@ impl Execute for Store2 {
 
         let value: u16 = source.into_value(vm);
 
-        if addr == STDOUT_ADDR {
-            vm.io.write_bytes(u32::reinterpret(value))?;
-        } else {
-            vm.memory.write_u16(addr, value)?;
-        }
+        vm.store(addr, 2, value as u64)?;
 
         Ok(())
     }
@@ -727,11 +1021,7 @@ impl Execute for Store4 {
 
         let value: u32 = source.into_value(vm);
 
-        if addr == STDOUT_ADDR {
-            vm.io.write_bytes(u32::reinterpret(value))?;
-        } else {
-            vm.memory.write_u32(addr, value)?;
-        }
+        vm.store(addr, 4, value as u64)?;
 
         Ok(())
     }
@@ -744,11 +1034,7 @@ impl Execute for Store8 {
 
         let value: u64 = source.into_value(vm);
 
-        if addr == STDOUT_ADDR {
-            vm.io.write_bytes(u32::reinterpret(value))?;
-        } else {
-            vm.memory.write_u64(addr, value)?;
-        }
+        vm.store(addr, 8, value)?;
 
         Ok(())
     }
@@ -766,6 +1052,7 @@ impl Execute for Push {
         // Store the value at the top of the stack
         let value: u64 = source.into_value(vm);
         vm.memory.write_u64(stack_top, value)?;
+        vm.log_write(stack_top, &value.to_le_bytes());
 
         Ok(())
     }
@@ -1081,6 +1368,7 @@ impl Execute for Call {
 
         // Store the program counter at the top of the stack
         vm.memory.write_u64(stack_top, vm.program_counter)?;
+        vm.log_write(stack_top, &vm.program_counter.to_le_bytes());
 
         // Jump to the given location
         let addr: u64 = loc.into_value(vm);
@@ -1106,3 +1394,125 @@ impl Execute for Ret {
         Ok(())
     }
 }
+
+impl Execute for Syscall {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Syscall {} = self;
+        syscall::dispatch(vm)
+    }
+}
+
+impl Execute for Fadd {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Fadd {dest, source} = self;
+        let lhs: f64 = dest.into_value(vm);
+        let rhs: f64 = source.into_value(vm);
+
+        vm.store_dest(dest, lhs + rhs);
+
+        Ok(())
+    }
+}
+
+impl Execute for Fsub {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Fsub {dest, source} = self;
+        let lhs: f64 = dest.into_value(vm);
+        let rhs: f64 = source.into_value(vm);
+
+        vm.store_dest(dest, lhs - rhs);
+
+        Ok(())
+    }
+}
+
+impl Execute for Fmul {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Fmul {dest, source} = self;
+        let lhs: f64 = dest.into_value(vm);
+        let rhs: f64 = source.into_value(vm);
+
+        vm.store_dest(dest, lhs * rhs);
+
+        Ok(())
+    }
+}
+
+impl Execute for Fdiv {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Fdiv {dest, source} = self;
+        let lhs: f64 = dest.into_value(vm);
+        let rhs: f64 = source.into_value(vm);
+
+        // Dividing by zero is not an error, it produces an infinity or NaN
+        vm.store_dest(dest, lhs / rhs);
+
+        Ok(())
+    }
+}
+
+impl Execute for Fsqrt {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Fsqrt {dest, source} = self;
+        let value: f64 = source.into_value(vm);
+
+        // The square root of a negative number is NaN
+        vm.store_dest(dest, value.sqrt());
+
+        Ok(())
+    }
+}
+
+impl Execute for Fcmp {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Fcmp {source1, source2} = self;
+        let lhs: f64 = source1.into_value(vm);
+        let rhs: f64 = source2.into_value(vm);
+
+        // The flags are set like an unsigned comparison so that `ja`, `jae`, `jb`, `jbe`, `je`,
+        // and `jne` can be used. An unordered comparison (at least one NaN operand) sets every
+        // flag except the sign flag.
+        use std::cmp::Ordering::*;
+        let (carry, zero, overflow) = match lhs.partial_cmp(&rhs) {
+            Some(Less) => (CF::Carry, ZF::NonZero, OF::NoOverflow),
+            Some(Equal) => (CF::NoCarry, ZF::Zero, OF::NoOverflow),
+            Some(Greater) => (CF::NoCarry, ZF::NonZero, OF::NoOverflow),
+            None => (CF::Carry, ZF::Zero, OF::Overflow),
+        };
+
+        vm.flags = Flags {carry, zero, sign: SF::PositiveSign, overflow};
+
+        Ok(())
+    }
+}
+
+impl Execute for Itof {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Itof {dest, source} = self;
+        let value: i64 = source.into_value(vm);
+
+        // Rounds to the nearest representable value if the integer is too large to be exact
+        vm.store_dest(dest, value as f64);
+
+        Ok(())
+    }
+}
+
+impl Execute for Ftoi {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Ftoi {dest, source} = self;
+        let value: f64 = source.into_value(vm);
+
+        // Truncates towards zero. NaN and values out of the range of i64 produce i64::MIN,
+        // just like the "integer indefinite" value on x86.
+        let result = if value.is_nan() || value < i64::MIN as f64 || value >= -(i64::MIN as f64) {
+            i64::MIN
+        } else {
+            value as i64
+        };
+
+        vm.store_dest(dest, result);
+
+        Ok(())
+    }
+}
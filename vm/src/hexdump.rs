@@ -0,0 +1,33 @@
+//! Formatting of raw bytes as a hexdump
+
+use std::io::{self, Write};
+
+/// The number of bytes shown on each line of a hexdump
+pub const LINE_LEN: usize = 16;
+
+/// Writes the given bytes as lines of hexadecimal values followed by the corresponding ASCII
+/// characters, e.g. `0x000003e8: 68 69 0a ... |hi.|`
+///
+/// Each line starts with `prefix` followed by the address of the first byte on that line, counting
+/// from `start`.
+pub fn write_hexdump(out: &mut impl Write, prefix: &str, start: u64, bytes: &[u8]) -> io::Result<()> {
+    for (i, line) in bytes.chunks(LINE_LEN).enumerate() {
+        let addr = start + (i * LINE_LEN) as u64;
+        write!(out, "{}0x{:08x}: ", prefix, addr)?;
+
+        for byte in line {
+            write!(out, "{:02x} ", byte)?;
+        }
+        // Pad short lines so the text column lines up
+        for _ in line.len()..LINE_LEN {
+            write!(out, "   ")?;
+        }
+
+        let text: String = line.iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
+        writeln!(out, "|{}|", text)?;
+    }
+
+    Ok(())
+}
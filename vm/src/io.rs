@@ -1,9 +1,49 @@
-use std::io;
-#[cfg(not(test))]
-use std::io::{BufRead, Write};
-#[cfg(not(test))]
+//! The input and output devices that programs read from and write to
+//!
+//! Programs access IO through memory mapped addresses and the `read`/`write` syscalls. Both go
+//! through the `Io` trait so that the machine can be connected to the terminal, to files, or to
+//! buffers in memory.
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::fs::File;
 use std::char;
+use std::sync::{Arc, Mutex};
+use std::collections::VecDeque;
+
+/// An input and output device for a machine
+pub trait Io: fmt::Debug + Send {
+    /// Reads bytes into the given buffer
+    ///
+    /// Returns the number of bytes read, or Ok(0) if EOF has been reached
+    fn read_buf(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Writes the given bytes exactly as they are
+    fn write_buf(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    /// Reads a single byte
+    ///
+    /// Returns Ok(None) if EOF has been reached
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.read_buf(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Writes the given 4 bytes as a UTF-8 encoded `char`, writing the unicode replacement
+    /// character if the bytes are not a valid `char`
+    fn write_bytes(&mut self, value: u32) -> io::Result<()> {
+        let ch = char::from_u32(value)
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+
+        let mut encoded = [0; 4];
+        self.write_buf(ch.encode_utf8(&mut encoded).as_bytes())
+    }
+}
 
+/// Reads input from stdin one line at a time and writes output to stdout
 #[derive(Debug, Default, PartialEq)]
 pub struct Stdio {
     line: Vec<u8>,
@@ -11,12 +51,13 @@ pub struct Stdio {
     current: usize,
 }
 
-impl Stdio {
-    /// Reads the next line of input from stdin
-    ///
-    /// Returns Ok(None) if EOF has been reached
-    #[cfg(not(test))]
-    pub fn read_byte(&mut self) -> io::Result<Option<u8>> {
+impl Io for Stdio {
+    /// Reads bytes from stdin into the given buffer, stopping at the end of the current line
+    fn read_buf(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
         if self.current >= self.line.len() {
             self.line.clear();
             let stdin = io::stdin();
@@ -24,35 +65,106 @@ impl Stdio {
             self.current = 0;
         }
 
-        Ok(self.line.get(self.current).copied().map(|byte| {
-            // Found a character, advance the current index
-            // This avoids `current` being incremented after EOF
-            self.current += 1;
-            byte
-        }))
-    }
+        let remaining = &self.line[self.current..];
+        let nbytes = remaining.len().min(buf.len());
+        buf[..nbytes].copy_from_slice(&remaining[..nbytes]);
+        self.current += nbytes;
 
-    #[cfg(test)]
-    pub fn read_byte(&mut self) -> io::Result<Option<u8>> {
-        Ok(None)
+        Ok(nbytes)
     }
 
-    /// Writes the given 4 bytes to stdout, printing the unicode replacement
-    /// character if the bytes are not a valid `char`
-    #[cfg(not(test))]
-    pub fn write_bytes(&self, value: u32) -> io::Result<()> {
-        let ch = char::from_u32(value)
-            .unwrap_or(char::REPLACEMENT_CHARACTER);
-
+    fn write_buf(&mut self, buf: &[u8]) -> io::Result<()> {
         let mut stdout = io::stdout();
-        write!(stdout, "{}", ch)?;
+        stdout.write_all(buf)?;
         stdout.flush()?;
 
         Ok(())
     }
+}
+
+/// Reads input from and writes output to buffers in memory
+///
+/// Clones share the same buffers, so a clone can be kept to provide more input or check the
+/// output while the machine is running.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryIo {
+    input: Arc<Mutex<VecDeque<u8>>>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
 
-    #[cfg(test)]
-    pub fn write_bytes(&self, _value: u32) -> io::Result<()> {
+impl MemoryIo {
+    /// Creates a device that provides the given input before reaching EOF
+    pub fn new(input: impl Into<Vec<u8>>) -> Self {
+        Self {
+            input: Arc::new(Mutex::new(input.into().into())),
+            output: Default::default(),
+        }
+    }
+
+    /// Adds more bytes to the end of the input
+    pub fn push_input(&self, input: &[u8]) {
+        self.input.lock().unwrap().extend(input);
+    }
+
+    /// Returns a copy of everything written so far
+    pub fn output(&self) -> Vec<u8> {
+        self.output.lock().unwrap().clone()
+    }
+
+    /// Removes and returns everything written so far
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.output.lock().unwrap())
+    }
+}
+
+impl Io for MemoryIo {
+    fn read_buf(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = self.input.lock().unwrap();
+        let nbytes = input.len().min(buf.len());
+        for (byte, input_byte) in buf.iter_mut().zip(input.drain(..nbytes)) {
+            *byte = input_byte;
+        }
+
+        Ok(nbytes)
+    }
+
+    fn write_buf(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.output.lock().unwrap().extend_from_slice(buf);
         Ok(())
     }
 }
+
+/// Reads input from and writes output to files
+///
+/// Without an input file, reads always reach EOF. Without an output file, all output is
+/// discarded.
+#[derive(Debug, Default)]
+pub struct FileIo {
+    input: Option<io::BufReader<File>>,
+    output: Option<File>,
+}
+
+impl FileIo {
+    pub fn new(input: Option<File>, output: Option<File>) -> Self {
+        Self {
+            input: input.map(io::BufReader::new),
+            output,
+        }
+    }
+}
+
+impl Io for FileIo {
+    fn read_buf(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.input {
+            Some(input) => io::Read::read(input, buf),
+            None => Ok(0),
+        }
+    }
+
+    fn write_buf(&mut self, buf: &[u8]) -> io::Result<()> {
+        match &mut self.output {
+            Some(output) => output.write_all(buf),
+            None => Ok(()),
+        }
+    }
+}
@@ -8,5 +8,12 @@ pub mod flags;
 pub mod operands;
 pub mod decode;
 pub mod io;
+pub mod bus;
 pub mod machine;
 pub mod execute;
+pub mod syscall;
+pub mod debugger;
+pub mod hexdump;
+pub mod disassemble;
+pub mod trace;
+pub mod profile;
@@ -1,15 +1,25 @@
+use std::fmt;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
 use thiserror::Error;
 
 use crate::{
     memory::{Memory, OutOfBounds},
     registers::Registers,
     flags::Flags,
-    io::Stdio,
+    io::{Io, Stdio},
+    bus::{Device, DeviceBus},
     decode::{Instr, DecodeError, Push},
     operands::Source,
     execute::{QUIT_ADDR, Execute, ExecuteError},
+    write_memory::WriteMemory,
 };
 
+/// The default amount of memory available to a machine
+pub const DEFAULT_MEMORY_SIZE: usize = 4 * 1024; // 4 kB
+
 /// Whether the program should continue running
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ProgramStatus {
@@ -18,14 +28,240 @@ pub enum ProgramStatus {
 }
 
 #[derive(Debug, Error)]
-#[error(transparent)]
 pub enum ExecutionError {
+    #[error(transparent)]
     OutOfBounds(#[from] OutOfBounds),
+    #[error(transparent)]
     DecodeError(#[from] DecodeError),
+    #[error(transparent)]
     ExecuteError(#[from] ExecuteError),
+    #[error("Program did not finish within its limit of {steps} instructions")]
+    OutOfSteps {steps: u64},
+    #[error("Program did not finish before its deadline (executed {steps} instructions)")]
+    DeadlineExceeded {steps: u64},
+}
+
+/// The number of instructions between each check of the deadline, since getting the current time
+/// is much slower than executing an instruction
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    #[error("Stack end address `0x{stack_end:x}` is past the end of memory (memory size: {memory_size} bytes)")]
+    StackOutOfMemory {stack_end: u64, memory_size: usize},
+    #[error("Not enough space for the stack below `0x{stack_end:x}`")]
+    NoStackSpace {stack_end: u64},
+    #[error("Program does not fit in memory when loaded at `0x{load_addr:x}` (memory size: {memory_size} bytes)")]
+    ProgramOutOfMemory {load_addr: u64, memory_size: usize},
+    #[error("Program loaded at `0x{load_addr:x}` ends at `0x{program_end:x}`, which overlaps the stack ending at `0x{stack_end:x}`")]
+    ProgramOverlapsStack {load_addr: u64, program_end: u64, stack_end: u64},
+}
+
+/// Configures the memory layout of a machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineConfig {
+    memory_size: usize,
+    stack_end: Option<u64>,
+    load_addr: u64,
+    entry_addr: Option<u64>,
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            memory_size: DEFAULT_MEMORY_SIZE,
+            stack_end: None,
+            load_addr: 0,
+            entry_addr: None,
+        }
+    }
+}
+
+impl MachineConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the size of the machine memory in bytes
+    pub fn with_memory_size(self, memory_size: usize) -> Self {
+        Self {memory_size, ..self}
+    }
+
+    /// Sets the address just past the end of the stack. The stack grows downwards from here.
+    ///
+    /// Defaults to the end of memory.
+    pub fn with_stack_end(self, stack_end: u64) -> Self {
+        Self {stack_end: Some(stack_end), ..self}
+    }
+
+    /// Sets the address where the program is loaded into memory
+    ///
+    /// Defaults to 0.
+    pub fn with_load_addr(self, load_addr: u64) -> Self {
+        Self {load_addr, ..self}
+    }
+
+    /// Sets the address of the first instruction to execute, overriding the entry point of the
+    /// program
+    pub fn with_entry_addr(self, entry_addr: u64) -> Self {
+        Self {entry_addr: Some(entry_addr), ..self}
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.memory_size
+    }
+
+    pub fn stack_end(&self) -> u64 {
+        self.stack_end.unwrap_or(self.memory_size as u64)
+    }
+
+    pub fn load_addr(&self) -> u64 {
+        self.load_addr
+    }
+
+    /// Returns the address of the first instruction to execute, given the offset of the entry
+    /// point from the start of the program
+    pub fn entry_addr(&self, entry_offset: u64) -> u64 {
+        self.entry_addr.unwrap_or_else(|| self.load_addr.wrapping_add(entry_offset))
+    }
+
+    /// Creates a machine with the given program loaded into memory, ready to start executing at
+    /// the entry point
+    pub fn build<P>(&self, program: &P, entry_offset: u64) -> Result<Machine, ConfigError>
+        where P: WriteMemory + ?Sized,
+    {
+        let &Self {memory_size, load_addr, ..} = self;
+        let stack_end = self.stack_end();
+        if stack_end > memory_size as u64 {
+            return Err(ConfigError::StackOutOfMemory {stack_end, memory_size});
+        }
+
+        let mut memory = Memory::new(memory_size);
+        let program_end = program.write_into(&mut memory, load_addr)
+            .map_err(|_| ConfigError::ProgramOutOfMemory {load_addr, memory_size})?;
+        if load_addr < stack_end && program_end > stack_end {
+            return Err(ConfigError::ProgramOverlapsStack {load_addr, program_end, stack_end});
+        }
+
+        let mut vm = Machine {
+            program_counter: self.entry_addr(entry_offset),
+            memory,
+            // Start with the stack pointer pointing just past the end of the stack
+            registers: Registers::new(stack_end as usize),
+            flags: Flags::default(),
+            io: Box::new(Stdio::default()),
+            devices: DeviceBus::with_standard_devices(),
+            // The heap starts right after the program
+            heap_end: program_end,
+            // A program loaded above the stack has a heap that grows towards the end of memory
+            heap_limit: if load_addr >= stack_end { Some(memory_size as u64) } else { None },
+            exit_code: None,
+            write_log: None,
+        };
+        // Returning from the entry point quits the program
+        if stack_end < mem::size_of_val(&QUIT_ADDR) as u64 {
+            return Err(ConfigError::NoStackSpace {stack_end});
+        }
+        vm.push_quit_addr()
+            .map_err(|_| ConfigError::NoStackSpace {stack_end})?;
+
+        Ok(vm)
+    }
+}
+
+/// Limits how long a program may run, e.g. so that untrusted programs cannot loop forever
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RunLimits {
+    max_steps: Option<u64>,
+    deadline: Option<Instant>,
+}
+
+impl RunLimits {
+    /// Creates limits that allow a program to run forever
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum number of instructions that may be executed
+    pub fn with_max_steps(self, max_steps: u64) -> Self {
+        Self {max_steps: Some(max_steps), ..self}
+    }
+
+    /// Sets the time after which the program will be stopped
+    ///
+    /// The deadline is only checked every few instructions, so the program may run slightly past
+    /// it.
+    pub fn with_deadline(self, deadline: Instant) -> Self {
+        Self {deadline: Some(deadline), ..self}
+    }
+
+    /// Sets the deadline to the given amount of time from now
+    ///
+    /// A timeout too long to be represented is treated as no deadline.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.with_deadline(deadline),
+            None => Self {deadline: None, ..self},
+        }
+    }
+
+    pub fn max_steps(&self) -> Option<u64> {
+        self.max_steps
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Returns an error if a program that has executed `steps` instructions must not execute any
+    /// more
+    pub fn check(&self, steps: u64) -> Result<(), ExecutionError> {
+        if let Some(max_steps) = self.max_steps {
+            if steps >= max_steps {
+                return Err(ExecutionError::OutOfSteps {steps});
+            }
+        }
+
+        if let Some(deadline) = self.deadline {
+            if steps.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+                return Err(ExecutionError::DeadlineExceeded {steps});
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A write of one or more bytes to memory or to a device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryWrite {
+    pub addr: u64,
+    /// The bytes that were written, starting at `addr`
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Display for MemoryWrite {
+    /// Writes of up to 8 bytes are displayed as a single little-endian value, e.g.
+    /// `[0xff8] <- 0x18`, and longer writes are displayed byte by byte
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {addr, bytes} = self;
+
+        if bytes.len() <= 8 {
+            let mut value = [0; 8];
+            value[..bytes.len()].copy_from_slice(bytes);
+            write!(f, "[0x{:x}] <- 0x{:x}", addr, u64::from_le_bytes(value))
+        } else {
+            write!(f, "[0x{:x}] <-", addr)?;
+            for byte in bytes {
+                write!(f, " {:02x}", byte)?;
+            }
+
+            Ok(())
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct Machine {
     /// Holds the address of the next instruction to execute
     pub program_counter: u64,
@@ -36,7 +272,26 @@ pub struct Machine {
     /// The machine flags/status register
     pub flags: Flags,
     /// Access to input and output
-    pub io: Stdio,
+    ///
+    /// Connected to stdin and stdout by default. Replace this with a `MemoryIo` to provide input
+    /// and capture output in memory, or with a `FileIo` to use files.
+    pub io: Box<dyn Io>,
+    /// The memory mapped devices, which handle loads and stores to their addresses instead of
+    /// `memory`
+    pub devices: DeviceBus,
+    /// The address one past the end of the memory allocated by the `alloc` syscall
+    ///
+    /// This should initially be set to the address right after the loaded executable.
+    pub heap_end: u64,
+    /// The address that the heap cannot grow past, or None if the heap is below the stack and
+    /// can grow until it reaches the stack pointer
+    pub heap_limit: Option<u64>,
+    /// The exit code passed to the `exit` syscall, if the program quit that way
+    pub exit_code: Option<i32>,
+    /// If this is set, every write to memory or to a device made by the program is appended to it
+    ///
+    /// Used to trace what each instruction changes.
+    pub write_log: Option<Vec<MemoryWrite>>,
 }
 
 impl Machine {
@@ -55,6 +310,67 @@ impl Machine {
         }
     }
 
+    /// Reads a value of `size` bytes (1, 2, 4, or 8) at the given address from the device mapped
+    /// to that address, or from memory if there is no such device
+    pub fn load(&mut self, addr: u64, size: u64) -> Result<u64, ExecuteError> {
+        match self.devices.device_at(addr) {
+            Some((device, offset)) => device.load(offset, size, &mut *self.io),
+            None => self.memory.load(addr, size, &mut *self.io),
+        }
+    }
+
+    /// Writes the lowest `size` bytes (1, 2, 4, or 8) of the given value at the given address to
+    /// the device mapped to that address, or to memory if there is no such device
+    pub fn store(&mut self, addr: u64, size: u64, value: u64) -> Result<(), ExecuteError> {
+        match self.devices.device_at(addr) {
+            Some((device, offset)) => device.store(offset, size, value, &mut *self.io)?,
+            None => self.memory.store(addr, size, value, &mut *self.io)?,
+        }
+
+        self.log_write(addr, &value.to_le_bytes()[..size as usize]);
+        Ok(())
+    }
+
+    /// Records a write of the given bytes to the given address in `write_log`, if it is set
+    pub fn log_write(&mut self, addr: u64, bytes: &[u8]) {
+        if let Some(write_log) = &mut self.write_log {
+            write_log.push(MemoryWrite {addr, bytes: bytes.to_vec()});
+        }
+    }
+
+    /// Runs the program until it quits or exceeds the given limits
+    ///
+    /// Returns the number of instructions that were executed.
+    pub fn run(&mut self, limits: &RunLimits) -> Result<u64, ExecutionError> {
+        self.run_with(limits, Self::step)
+    }
+
+    /// Runs the program like `run`, but calls `step` to execute each instruction instead of
+    /// `Machine::step`
+    ///
+    /// This allows each step to be recorded or inspected (e.g. with `TraceStep::execute`). If the
+    /// limits stop the program, the program counter is left at the instruction that would have
+    /// been executed next.
+    pub fn run_with<F, E>(&mut self, limits: &RunLimits, mut step: F) -> Result<u64, E>
+        where F: FnMut(&mut Self) -> Result<ProgramStatus, E>,
+              E: From<ExecutionError>,
+    {
+        let mut steps = 0;
+        loop {
+            limits.check(steps)?;
+
+            let status = step(self)?;
+            steps += 1;
+
+            match status {
+                ProgramStatus::Continue => {},
+                ProgramStatus::Quit => return Ok(steps),
+            }
+        }
+    }
+
+    /// Pushes the address that quits the program onto the stack so that the program quits when it
+    /// returns from its entry point
     pub fn push_quit_addr(&mut self) -> Result<(), ExecutionError> {
         self.push_immediate(QUIT_ADDR as i128)
     }
@@ -0,0 +1,253 @@
+//! Measures where a program spends its time
+//!
+//! The cost of running a program is measured in instructions executed. The profiler counts how
+//! many times each instruction is executed and uses `call` and `ret` instructions to track the
+//! call stack, so that the cost of each function can be reported. The cost of each call stack can
+//! also be written in the collapsed stack format read by flamegraph tools (e.g. `flamegraph.pl`
+//! or `inferno-flamegraph`).
+
+use std::io::{self, Write};
+use std::collections::{HashMap, HashSet};
+
+use wolf_asm::executable::DebugInfo;
+
+use crate::machine::Machine;
+use crate::decode::Instr;
+
+/// The number of instructions listed in the report by `Profiler::write_report`
+const REPORT_INSTRUCTIONS: usize = 20;
+
+/// A call stack, stored as a node in the tree of every call stack seen so far
+#[derive(Debug)]
+struct StackNode {
+    /// The address of the function called by this stack frame
+    func: u64,
+    /// The caller of this function, or None for the entry point
+    parent: Option<usize>,
+    /// The index of the node for each function called from this stack frame
+    children: HashMap<u64, usize>,
+    /// The number of instructions executed with exactly this call stack
+    steps: u64,
+}
+
+/// The instructions executed by a function, including the instructions executed by the functions
+/// it called (inclusive) and excluding them (exclusive)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionCost {
+    /// The address of the first instruction of the function
+    pub addr: u64,
+    /// The number of times the function was called (0 for the entry point)
+    pub calls: u64,
+    pub inclusive: u64,
+    pub exclusive: u64,
+}
+
+/// A change to the call stack that will happen after the current instruction executes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StackChange {
+    Call,
+    Ret,
+}
+
+/// Counts the instructions executed by a program
+///
+/// Call `record` before each step of the program.
+#[derive(Debug, Default)]
+pub struct Profiler<'a> {
+    /// The number of times the instruction at each address was executed
+    instr_counts: HashMap<u64, u64>,
+    /// Every call stack seen so far, starting with the stack of the entry point
+    stacks: Vec<StackNode>,
+    /// The index of the current call stack in `stacks`
+    current: usize,
+    /// The number of times each function was called
+    calls: HashMap<u64, u64>,
+    /// The change to the stack made by the previously recorded instruction
+    pending: Option<StackChange>,
+    total_steps: u64,
+    debug_info: Option<&'a DebugInfo>,
+    load_addr: u64,
+}
+
+impl<'a> Profiler<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses the labels in the debug info of a program loaded at the given address to name
+    /// functions
+    pub fn with_debug_info(self, debug_info: &'a DebugInfo, load_addr: u64) -> Self {
+        Self {debug_info: Some(debug_info), load_addr, ..self}
+    }
+
+    /// Records that the instruction at the program counter is about to be executed
+    ///
+    /// The function that contains the first recorded instruction is treated as the entry point.
+    pub fn record(&mut self, vm: &Machine) {
+        let pc = vm.program_counter;
+
+        // The target of a call is only known once it has been executed
+        match self.pending.take() {
+            Some(StackChange::Call) => self.push(pc),
+            // Returning from the entry point quits the program, so the stack is never empty
+            Some(StackChange::Ret) => if let Some(parent) = self.stacks[self.current].parent {
+                self.current = parent;
+            },
+            None => {},
+        }
+
+        if self.stacks.is_empty() {
+            self.stacks.push(StackNode {func: pc, parent: None, children: HashMap::new(), steps: 0});
+        }
+
+        *self.instr_counts.entry(pc).or_default() += 1;
+        self.stacks[self.current].steps += 1;
+        self.total_steps += 1;
+
+        let instr = vm.memory.read_u64(pc).ok().and_then(|instr| Instr::decode(instr).ok());
+        self.pending = match instr {
+            Some(Instr::Call(_)) => Some(StackChange::Call),
+            Some(Instr::Ret(_)) => Some(StackChange::Ret),
+            _ => None,
+        };
+    }
+
+    fn push(&mut self, func: u64) {
+        *self.calls.entry(func).or_default() += 1;
+
+        let next_index = self.stacks.len();
+        let index = *self.stacks[self.current].children.entry(func).or_insert(next_index);
+        if index == next_index {
+            self.stacks.push(StackNode {func, parent: Some(self.current), children: HashMap::new(), steps: 0});
+        }
+        self.current = index;
+    }
+
+    /// Returns the total number of instructions recorded
+    pub fn total_steps(&self) -> u64 {
+        self.total_steps
+    }
+
+    /// Returns the address and execution count of every instruction that was executed, starting
+    /// with the most executed
+    pub fn instructions(&self) -> Vec<(u64, u64)> {
+        let mut instrs: Vec<_> = self.instr_counts.iter().map(|(&addr, &count)| (addr, count)).collect();
+        instrs.sort_unstable_by_key(|&(addr, count)| (std::cmp::Reverse(count), addr));
+        instrs
+    }
+
+    /// Returns the cost of every function that was executed, starting with the highest inclusive
+    /// cost
+    pub fn functions(&self) -> Vec<FunctionCost> {
+        let mut costs: HashMap<u64, FunctionCost> = HashMap::new();
+        for node in &self.stacks {
+            costs.entry(node.func).or_insert_with(|| FunctionCost {
+                addr: node.func,
+                calls: self.calls.get(&node.func).copied().unwrap_or(0),
+                inclusive: 0,
+                exclusive: 0,
+            }).exclusive += node.steps;
+        }
+
+        for (index, node) in self.stacks.iter().enumerate() {
+            // Recursive functions appear more than once in a stack but should only be counted once
+            let mut seen = HashSet::new();
+            for func in self.stack_funcs(index) {
+                if seen.insert(func) {
+                    // Every function in a stack has its own node, so it already has a cost
+                    costs.get_mut(&func).expect("bug: function should have a cost").inclusive += node.steps;
+                }
+            }
+        }
+
+        let mut costs: Vec<_> = costs.into_values().collect();
+        costs.sort_unstable_by_key(|cost| (std::cmp::Reverse(cost.inclusive), cost.addr));
+        costs
+    }
+
+    /// Returns the functions in the given call stack, starting with the innermost function
+    fn stack_funcs(&self, index: usize) -> impl Iterator<Item=u64> + '_ {
+        let mut next = Some(index);
+        std::iter::from_fn(move || {
+            let node = &self.stacks[next?];
+            next = node.parent;
+            Some(node.func)
+        })
+    }
+
+    /// Returns the name of the function at the given address
+    ///
+    /// Uses the label at that address if there is one, or the closest label before it
+    /// (e.g. `main+0x10`), or the address itself if there is no debug info.
+    pub fn function_name(&self, addr: u64) -> String {
+        let offset = addr.wrapping_sub(self.load_addr);
+        let label = self.debug_info.and_then(|info| {
+            info.symbols.iter().find(|symbol| symbol.addr == offset)
+                .or_else(|| info.label_at(offset))
+        });
+
+        match label {
+            Some(symbol) if symbol.addr == offset => symbol.name.to_string(),
+            Some(symbol) => format!("{}+0x{:x}", symbol.name, offset - symbol.addr),
+            None => format!("0x{:x}", addr),
+        }
+    }
+
+    /// Writes the number of instructions executed in each call stack in the collapsed stack
+    /// format, e.g. `main;fib;fib 120`
+    pub fn write_collapsed_stacks(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for (index, node) in self.stacks.iter().enumerate() {
+            if node.steps == 0 {
+                continue;
+            }
+
+            let mut names: Vec<_> = self.stack_funcs(index).map(|func| self.function_name(func)).collect();
+            names.reverse();
+            lines.push(format!("{} {}", names.join(";"), node.steps));
+        }
+
+        // Sorted so that the output is the same every time the program runs
+        lines.sort_unstable();
+        for line in lines {
+            writeln!(out, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a table of the cost of every function and the most executed instructions
+    pub fn write_report(&self, vm: &Machine, out: &mut impl Write) -> io::Result<()> {
+        let total = self.total_steps;
+        // Avoids dividing by zero if nothing was executed
+        let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+
+        writeln!(out, "Executed {} instructions", total)?;
+
+        writeln!(out)?;
+        writeln!(out, "Functions:")?;
+        writeln!(out, "{:>12} {:>7} {:>12} {:>7} {:>8}  function", "inclusive", "%", "exclusive", "%", "calls")?;
+        for FunctionCost {addr, calls, inclusive, exclusive} in self.functions() {
+            writeln!(out, "{:>12} {:>6.2}% {:>12} {:>6.2}% {:>8}  {}", inclusive, percent(inclusive),
+                exclusive, percent(exclusive), calls, self.function_name(addr))?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Most executed instructions:")?;
+        writeln!(out, "{:>12} {:>7}  {:<10}  {:<24}  location", "count", "%", "address", "instruction")?;
+        for (addr, count) in self.instructions().into_iter().take(REPORT_INSTRUCTIONS) {
+            let instr = vm.memory.read_u64(addr).ok()
+                .and_then(|instr| Instr::decode(instr).ok())
+                .map(|instr| instr.to_string())
+                .unwrap_or_else(|| "<invalid instruction>".to_string());
+            let location = self.debug_info
+                .and_then(|info| info.describe(addr.wrapping_sub(self.load_addr)))
+                .unwrap_or_default();
+
+            let line = format!("{:>12} {:>6.2}%  0x{:08x}  {:<24}  {}", count, percent(count), addr, instr, location);
+            writeln!(out, "{}", line.trim_end())?;
+        }
+
+        Ok(())
+    }
+}
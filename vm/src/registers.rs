@@ -71,6 +71,12 @@ impl Registers {
         *value = u64::reinterpret(new_value);
     }
 
+    /// Returns the kind and value of every register, in order
+    pub fn iter(&self) -> impl Iterator<Item=(asm::RegisterKind, u64)> + '_ {
+        self.registers.iter().enumerate()
+            .map(|(index, &value)| (register_kind(index as u8), value))
+    }
+
     /// Loads the value of the stack pointer
     pub fn load_sp<R: Reinterpret<u64>>(&self) -> R {
         self.load(asm::RegisterKind::StackPointer.into())
@@ -95,3 +101,15 @@ impl Registers {
         self.store(asm::RegisterKind::FramePointer.into(), new_value)
     }
 }
+
+/// Returns the kind of the register with the given index (e.g. the last register is the stack
+/// pointer)
+pub fn register_kind(index: u8) -> asm::RegisterKind {
+    if index == asm::REGISTERS - 1 {
+        asm::RegisterKind::StackPointer
+    } else if index == asm::REGISTERS - 2 {
+        asm::RegisterKind::FramePointer
+    } else {
+        asm::RegisterKind::Numbered(index)
+    }
+}
@@ -1,3 +1,4 @@
+mod reinterpret_f64;
 mod reinterpret_i128;
 mod reinterpret_i16;
 mod reinterpret_i32;
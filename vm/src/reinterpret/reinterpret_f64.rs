@@ -0,0 +1,9 @@
+use super::Reinterpret;
+
+impl Reinterpret<f64> for u64 {
+    #[inline(always)]
+    fn reinterpret(value: f64) -> Self {
+        // Reinterpret the IEEE 754 binary64 bits as u64
+        value.to_bits()
+    }
+}
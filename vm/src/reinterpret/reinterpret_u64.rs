@@ -82,3 +82,11 @@ impl Reinterpret<u64> for i8 {
         Self::from_le_bytes(*bytes)
     }
 }
+
+impl Reinterpret<u64> for f64 {
+    #[inline(always)]
+    fn reinterpret(value: u64) -> Self {
+        // Reinterpret the bits as an IEEE 754 binary64 value
+        Self::from_bits(value)
+    }
+}
@@ -0,0 +1,173 @@
+//! The services available through the `syscall` instruction
+//!
+//! The syscall number is read from `$0` and up to three arguments are read from `$1`, `$2`, and
+//! `$3`. The return value of the syscall (if any) is stored in `$0`.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use wolf_asm::asm::{self, layout::Reg};
+
+use crate::reinterpret::Reinterpret;
+use crate::machine::{Machine, MemoryWrite};
+use crate::execute::{QUIT_ADDR, ExecuteError};
+
+/// The alignment (in bytes) of every block returned by the `alloc` syscall
+const ALLOC_ALIGN: u64 = 8;
+
+/// The register that holds the syscall number and the return value of the syscall
+pub fn syscall_reg() -> Reg {
+    asm::RegisterKind::Numbered(0).into()
+}
+
+/// The register that holds the given argument (0-based) of the syscall
+pub fn arg_reg(index: u8) -> Reg {
+    debug_assert!(index < 3, "bug: syscalls take at most 3 arguments");
+    asm::RegisterKind::Numbered(index + 1).into()
+}
+
+macro_rules! syscalls {
+    (
+        $(#[$m:meta])*
+        $v:vis enum $syscall_enum:ident {
+            $(
+                $(#[$variant_m:meta])*
+                $syscall_variant:ident = $number:literal,
+            )*
+        }
+    ) => {
+        $(#[$m])*
+        $v enum $syscall_enum {
+            $(
+                $(#[$variant_m])*
+                $syscall_variant = $number,
+            )*
+        }
+
+        impl $syscall_enum {
+            /// Returns the syscall corresponding to the given number, if any
+            pub fn from_number(number: u64) -> Option<Self> {
+                match number {
+                    $($number => Some($syscall_enum::$syscall_variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+syscalls! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum SyscallKind {
+        /// `read(addr, len)` - reads up to `len` bytes from stdin into memory starting at `addr`
+        ///
+        /// At most one line of input is read at a time. Returns the number of bytes read, or 0 at
+        /// EOF.
+        Read = 0,
+        /// `write(addr, len)` - writes `len` bytes from memory starting at `addr` to stdout
+        ///
+        /// Returns the number of bytes written.
+        Write = 1,
+        /// `exit(code)` - quits the program immediately with the given exit code
+        Exit = 2,
+        /// `time()` - returns the number of milliseconds since the Unix epoch
+        Time = 3,
+        /// `alloc(size)` - allocates a block of at least `size` bytes of memory
+        ///
+        /// Returns the address of the block, or 0 if there is not enough memory available.
+        Alloc = 4,
+    }
+}
+
+/// Runs the syscall requested by the current values of the machine registers
+pub fn dispatch(vm: &mut Machine) -> Result<(), ExecuteError> {
+    let number: u64 = vm.registers.load(syscall_reg());
+    let kind = SyscallKind::from_number(number)
+        .ok_or(ExecuteError::UnknownSyscall(number))?;
+
+    use SyscallKind::*;
+    match kind {
+        Read => read(vm),
+        Write => write(vm),
+        Exit => exit(vm),
+        Time => time(vm),
+        Alloc => alloc(vm),
+    }
+}
+
+fn read(vm: &mut Machine) -> Result<(), ExecuteError> {
+    let addr: u64 = vm.registers.load(arg_reg(0));
+    let len: u64 = vm.registers.load(arg_reg(1));
+
+    let buf = vm.memory.slice_mut(addr..addr.saturating_add(len))?;
+    let nbytes = vm.io.read_buf(buf)?;
+    // Not using `Machine::log_write` since `buf` is still borrowed from memory
+    match &mut vm.write_log {
+        Some(write_log) if nbytes > 0 => write_log.push(MemoryWrite {addr, bytes: buf[..nbytes].to_vec()}),
+        _ => {},
+    }
+
+    vm.registers.store(syscall_reg(), nbytes as u64);
+
+    Ok(())
+}
+
+fn write(vm: &mut Machine) -> Result<(), ExecuteError> {
+    let addr: u64 = vm.registers.load(arg_reg(0));
+    let len: u64 = vm.registers.load(arg_reg(1));
+
+    let buf = vm.memory.slice(addr..addr.saturating_add(len))?;
+    vm.io.write_buf(buf)?;
+
+    vm.registers.store(syscall_reg(), len);
+
+    Ok(())
+}
+
+fn exit(vm: &mut Machine) -> Result<(), ExecuteError> {
+    let code: u64 = vm.registers.load(arg_reg(0));
+
+    vm.exit_code = Some(i32::reinterpret(code));
+    vm.program_counter = QUIT_ADDR;
+
+    Ok(())
+}
+
+fn time(vm: &mut Machine) -> Result<(), ExecuteError> {
+    // A clock set before the Unix epoch is treated as if it were at the epoch
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0);
+
+    vm.registers.store(syscall_reg(), millis);
+
+    Ok(())
+}
+
+fn alloc(vm: &mut Machine) -> Result<(), ExecuteError> {
+    let size: u64 = vm.registers.load(arg_reg(0));
+
+    // The heap grows upwards towards the stack, so the stack pointer is the limit unless the heap
+    // is above the stack
+    let limit = match vm.heap_limit {
+        Some(limit) => limit,
+        None => vm.registers.load_sp(),
+    };
+
+    let start = match vm.heap_end.checked_add(ALLOC_ALIGN - 1) {
+        Some(addr) => addr / ALLOC_ALIGN * ALLOC_ALIGN,
+        None => limit,
+    };
+    let addr = match start.checked_add(size) {
+        Some(end) if end <= limit => {
+            vm.heap_end = end;
+            start
+        },
+
+        // Not enough memory available
+        _ => 0,
+    };
+
+    vm.registers.store(syscall_reg(), addr);
+
+    Ok(())
+}
@@ -0,0 +1,206 @@
+//! Traces the execution of a program one instruction at a time
+//!
+//! Each step of a trace records the instruction that was executed and everything it changed: the
+//! registers, the flags, and any writes to memory or to devices. Traces can be written as text for
+//! people to read or as one JSON object per line for other programs to read.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
+
+use serde::Serialize;
+use wolf_asm::executable::DebugInfo;
+
+use crate::machine::{Machine, ProgramStatus, ExecutionError, MemoryWrite};
+use crate::flags::Flags;
+use crate::decode::Instr;
+
+/// A register whose value was changed by an instruction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RegisterChange {
+    /// The name of the register, e.g. `$8` or `$sp`
+    pub reg: String,
+    pub old: u64,
+    pub new: u64,
+}
+
+/// A flag whose value was changed by an instruction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FlagChange {
+    /// The name of the flag, e.g. `ZF`
+    pub flag: &'static str,
+    pub old: u8,
+    pub new: u8,
+}
+
+/// Everything that changed while executing a single instruction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceStep {
+    /// The address of the instruction
+    pub pc: u64,
+    /// The instruction that was executed, or None if it could not be decoded
+    pub instr: Option<String>,
+    pub registers: Vec<RegisterChange>,
+    pub flags: Vec<FlagChange>,
+    /// Every write made by the instruction, in order
+    pub writes: Vec<MemoryWrite>,
+}
+
+impl TraceStep {
+    /// Executes the instruction at the program counter and records what it changed
+    ///
+    /// The step is recorded even if the instruction fails, along with any changes it made before
+    /// it failed. Any writes already in `Machine::write_log` are discarded.
+    pub fn execute(vm: &mut Machine) -> (Self, Result<ProgramStatus, ExecutionError>) {
+        let pc = vm.program_counter;
+        let instr = vm.memory.read_u64(pc).ok()
+            .and_then(|instr| Instr::decode(instr).ok())
+            .map(|instr| instr.to_string());
+        let old_registers = vm.registers.clone();
+        let old_flags = flag_values(&vm.flags);
+
+        vm.write_log = Some(Vec::new());
+        let result = vm.step();
+        let writes = vm.write_log.take().unwrap_or_default();
+
+        let registers = old_registers.iter().zip(vm.registers.iter())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((kind, old), (_, new))| RegisterChange {reg: format!("${}", kind), old, new})
+            .collect();
+        let flags = old_flags.iter().zip(&flag_values(&vm.flags))
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|(&(flag, old), &(_, new))| FlagChange {flag, old, new})
+            .collect();
+
+        (Self {pc, instr, registers, flags, writes}, result)
+    }
+}
+
+/// Returns the name and value of each flag
+fn flag_values(flags: &Flags) -> [(&'static str, u8); 4] {
+    [
+        ("CF", flags.carry as u8),
+        ("ZF", flags.zero as u8),
+        ("SF", flags.sign as u8),
+        ("OF", flags.overflow as u8),
+    ]
+}
+
+/// The format of each step written to a trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One line of text per step
+    Text,
+    /// One JSON object per line per step
+    Json,
+}
+
+/// The JSON object written for each step
+#[derive(Serialize)]
+struct JsonStep<'a> {
+    step: u64,
+    #[serde(flatten)]
+    trace: &'a TraceStep,
+    location: Option<String>,
+}
+
+/// Writes the steps of a program to a trace
+pub struct Tracer<'a> {
+    out: Box<dyn Write + 'a>,
+    format: TraceFormat,
+    /// Only the steps that execute an instruction in one of these ranges are written, or every
+    /// step if there are no ranges
+    filter: Vec<Range<u64>>,
+    debug_info: Option<&'a DebugInfo>,
+    load_addr: u64,
+    /// The number of steps passed to `write` so far
+    steps: u64,
+}
+
+impl<'a> Tracer<'a> {
+    pub fn new(out: impl Write + 'a, format: TraceFormat) -> Self {
+        Self {
+            out: Box::new(out),
+            format,
+            filter: Vec::new(),
+            debug_info: None,
+            load_addr: 0,
+            steps: 0,
+        }
+    }
+
+    /// Uses the debug info of a program loaded at the given address to write the source location
+    /// of each step
+    pub fn with_debug_info(self, debug_info: &'a DebugInfo, load_addr: u64) -> Self {
+        Self {debug_info: Some(debug_info), load_addr, ..self}
+    }
+
+    /// Only writes the steps that execute an instruction in the given range of addresses
+    ///
+    /// May be used multiple times to write the steps in any of several ranges.
+    pub fn with_filter(mut self, range: Range<u64>) -> Self {
+        self.filter.push(range);
+        self
+    }
+
+    /// Writes the given step to the trace, unless it is excluded by the filter
+    ///
+    /// Steps are numbered starting at 1, counting every step passed to this method (even the ones
+    /// that are not written).
+    pub fn write(&mut self, step: &TraceStep) -> io::Result<()> {
+        self.steps += 1;
+
+        if !self.filter.is_empty() && !self.filter.iter().any(|range| range.contains(&step.pc)) {
+            return Ok(());
+        }
+
+        let location = self.debug_info
+            .and_then(|info| info.describe(step.pc.wrapping_sub(self.load_addr)));
+
+        match self.format {
+            TraceFormat::Text => self.write_text(step, location),
+            TraceFormat::Json => self.write_json(step, location),
+        }
+    }
+
+    /// Writes any buffered steps
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Writes the step number, address, and instruction, followed by everything the instruction
+    /// changed and its source location, e.g.
+    ///
+    /// ```text
+    ///       12  0x00000018  add $8, 1                 $8: 0x2 -> 0x3  ZF: 1 -> 0  # fib.wa:23:3 in `loop`
+    /// ```
+    fn write_text(&mut self, step: &TraceStep, location: Option<String>) -> io::Result<()> {
+        use fmt::Write;
+
+        let TraceStep {pc, instr, registers, flags, writes} = step;
+        let instr = instr.as_deref().unwrap_or("<invalid instruction>");
+
+        // Writing to a `String` cannot fail
+        let mut line = format!("{:>8}  0x{:08x}  {:<24}", self.steps, pc, instr);
+        for RegisterChange {reg, old, new} in registers {
+            write!(line, "  {}: 0x{:x} -> 0x{:x}", reg, old, new).unwrap();
+        }
+        for FlagChange {flag, old, new} in flags {
+            write!(line, "  {}: {} -> {}", flag, old, new).unwrap();
+        }
+        for write in writes {
+            write!(line, "  {}", write).unwrap();
+        }
+        if let Some(location) = location {
+            write!(line, "  # {}", location).unwrap();
+        }
+
+        writeln!(self.out, "{}", line.trim_end())
+    }
+
+    fn write_json(&mut self, step: &TraceStep, location: Option<String>) -> io::Result<()> {
+        let json_step = JsonStep {step: self.steps, trace: step, location};
+        serde_json::to_writer(&mut self.out, &json_step)?;
+        writeln!(self.out)
+    }
+}
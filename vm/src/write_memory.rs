@@ -35,7 +35,7 @@ impl WriteMemory for u64 {
 
 impl WriteMemory for exec::Executable {
     fn write_into(&self, mem: &mut Memory, addr: u64) -> Result<u64, OutOfBounds> {
-        let exec::Executable {code_section, static_section} = self;
+        let exec::Executable {code_section, static_section, debug_info: _, entry: _} = self;
 
         let addr = code_section.write_into(mem, addr)?;
         static_section.write_into(mem, addr)
@@ -0,0 +1,49 @@
+//! Helpers shared by the integration tests
+
+// Each test crate only uses some of these helpers
+#![allow(dead_code)]
+
+use wolf_vm::{
+    memory::Memory,
+    registers::Registers,
+    flags::Flags,
+    io::MemoryIo,
+    bus::DeviceBus,
+    machine::{Machine, MachineConfig},
+};
+use wolf_asm::{assemble, AssembleOptions, executable::Executable, file_provider::MemoryFileProvider};
+
+/// The amount of memory in machines created by `new_machine`
+pub const TEST_MEMORY: usize = 1024; // 1 kB
+
+/// Creates a machine with no program, where every instruction is a `nop` (all zeros)
+pub fn new_machine() -> Machine {
+    Machine {
+        program_counter: 0,
+        memory: Memory::new(TEST_MEMORY),
+        registers: Registers::new(TEST_MEMORY),
+        flags: Flags::default(),
+        io: Box::new(MemoryIo::default()),
+        devices: DeviceBus::with_standard_devices(),
+        heap_end: 0,
+        heap_limit: None,
+        exit_code: None,
+        write_log: None,
+    }
+}
+
+/// Assembles the given source (with debug info) and creates a machine for it that uses the given
+/// input and output
+pub fn build(source: &str, io: &MemoryIo) -> (Executable, Machine) {
+    let mut files = MemoryFileProvider::new();
+    files.insert("main.wa", source);
+    let exec = assemble(AssembleOptions {
+        files: Box::new(files),
+        debug_info: true,
+        ..AssembleOptions::new("main.wa")
+    }).unwrap().executable;
+
+    let mut vm = MachineConfig::new().build(&exec, exec.entry).unwrap();
+    vm.io = Box::new(io.clone());
+    (exec, vm)
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use wolf_vm::{
+    io::{Io, MemoryIo},
+    memory::Memory,
+    bus::{Device, DeviceBus, RegisterError},
+    machine::ProgramStatus,
+    execute::{ExecuteError, STDIN_ADDR, STDOUT_ADDR},
+};
+
+use common::build;
+
+/// A device that counts up every time it is loaded from, and records every store
+#[derive(Debug, Default, Clone)]
+struct Counter {
+    count: u64,
+    stores: Arc<Mutex<Vec<(u64, u64, u64)>>>,
+}
+
+impl Device for Counter {
+    fn load(&mut self, _offset: u64, _size: u64, _io: &mut dyn Io) -> Result<u64, ExecuteError> {
+        self.count += 1;
+        Ok(self.count)
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64, _io: &mut dyn Io) -> Result<(), ExecuteError> {
+        self.stores.lock().unwrap().push((offset, size, value));
+        Ok(())
+    }
+}
+
+#[test]
+fn register_devices() {
+    let mut bus = DeviceBus::new();
+    bus.register(0x100..0x200, Counter::default()).unwrap();
+    bus.register(0x200..0x210, Counter::default()).unwrap();
+    bus.register(0x10..0x20, Counter::default()).unwrap();
+
+    assert_eq!(
+        bus.register(0x1ff..0x201, Counter::default()).unwrap_err(),
+        RegisterError::Overlap {start: 0x1ff, end: 0x201, other_start: 0x100, other_end: 0x200},
+    );
+    assert_eq!(
+        bus.register(0x0..0x11, Counter::default()).unwrap_err(),
+        RegisterError::Overlap {start: 0x0, end: 0x11, other_start: 0x10, other_end: 0x20},
+    );
+    assert_eq!(
+        bus.register(0x300..0x300, Counter::default()).unwrap_err(),
+        RegisterError::EmptyRange {start: 0x300, end: 0x300},
+    );
+
+    assert_eq!(bus.device_at(0x100).map(|(_, offset)| offset), Some(0));
+    assert_eq!(bus.device_at(0x1ff).map(|(_, offset)| offset), Some(0xff));
+    assert_eq!(bus.device_at(0x20f).map(|(_, offset)| offset), Some(0xf));
+    assert_eq!(bus.device_at(0x15).map(|(_, offset)| offset), Some(0x5));
+    assert!(bus.device_at(0x20).is_none());
+    assert!(bus.device_at(0x210).is_none());
+    assert!(bus.device_at(0x0).is_none());
+
+    assert!(bus.unregister(0x100));
+    assert!(!bus.unregister(0x100));
+    assert!(bus.device_at(0x100).is_none());
+}
+
+#[test]
+fn standard_devices() {
+    let mut bus = DeviceBus::with_standard_devices();
+    assert!(bus.device_at(STDIN_ADDR).is_some());
+    assert!(bus.device_at(STDOUT_ADDR).is_some());
+    assert!(bus.device_at(STDIN_ADDR + 1).is_none());
+    assert!(bus.device_at(STDOUT_ADDR + 1).is_none());
+}
+
+#[test]
+fn program_with_devices() {
+    let source = "\
+section .code
+.const COUNTER 0x8000_0000
+.const SCREEN 0x9000_0000
+main:
+    mov $3, COUNTER
+    load8 $1, $3
+    load8 $1, $3
+    add $3, 4
+    store2 $3, $1
+    mov $4, SCREEN + 2
+    store8 $4, 0x4241
+    load2 $2, $4
+    store1 0xffff_000c, $2
+    ret
+";
+    let io = MemoryIo::default();
+    let counter = Counter::default();
+    let (_, mut vm) = build(source, &io);
+    vm.devices.register(0x8000_0000..0x8000_0010, counter.clone()).unwrap();
+    vm.devices.register(0x9000_0000..0x9000_0100, Memory::new(0x100)).unwrap();
+    while vm.step().unwrap() == ProgramStatus::Continue {}
+
+    assert_eq!(*counter.stores.lock().unwrap(), &[(4, 2, 2)]);
+    assert_eq!(io.output(), b"A");
+}
+
+#[test]
+fn devices_shadow_memory() {
+    let source = "\
+section .code
+main:
+    mov $1, 0x41
+    store1 0xffff_0004, $1
+    load8 $2, 0xffff_000c
+    add $2, 0x42
+    store1 0xffff_000c, $2
+    store8 0x800, $1
+    load8 $3, 0x800
+    add $3, 0x40
+    store1 0xffff_000c, $3
+    ret
+";
+    let io = MemoryIo::default();
+    let counter = Counter::default();
+    let (_, mut vm) = build(source, &io);
+    vm.devices.register(0x800..0x808, counter.clone()).unwrap();
+    while vm.step().unwrap() == ProgramStatus::Continue {}
+
+    // The stdio devices are not backed by memory, so the store to stdin is ignored and the load
+    // from stdout is zero. The counter handles the accesses instead of the memory at its address.
+    assert_eq!(io.output(), b"BA");
+    assert_eq!(*counter.stores.lock().unwrap(), &[(0, 8, 0x41)]);
+    assert_eq!(vm.memory.read_u64(0x800).unwrap(), 0);
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use wolf_vm::{
+    machine::Machine,
+    debugger::{Debugger, Command, CommandError, Target},
+};
+
+use common::{new_machine, TEST_MEMORY};
+
+/// Runs each line in the debugger and returns all of the output
+fn run_lines(debugger: &mut Debugger, vm: &mut Machine, lines: &[&str]) -> String {
+    let mut out = Vec::new();
+    for line in lines {
+        let keep_going = debugger.execute(vm, line, &mut out).unwrap();
+        assert!(keep_going, "debugger quit unexpectedly on `{}`", line);
+    }
+
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn parse_commands() {
+    assert_eq!(Command::parse(""), Ok(None));
+    assert_eq!(Command::parse("  \n"), Ok(None));
+    assert_eq!(Command::parse("s"), Ok(Some(Command::Step(1))));
+    assert_eq!(Command::parse("step 0x10"), Ok(Some(Command::Step(16))));
+    assert_eq!(Command::parse("b 24"), Ok(Some(Command::Break(Target::Address(24)))));
+    assert_eq!(Command::parse("break Loop"), Ok(Some(Command::Break(Target::Label("loop".into())))));
+    assert_eq!(Command::parse("x 0x1_0"), Ok(Some(Command::Examine {start: Target::Address(16), len: 64})));
+    assert_eq!(Command::parse("x msg 3"), Ok(Some(Command::Examine {start: Target::Label("msg".into()), len: 3})));
+    assert_eq!(Command::parse("dis"), Ok(Some(Command::Disassemble {count: 5})));
+
+    assert_eq!(Command::parse("jump"), Err(CommandError::UnknownCommand("jump".to_string())));
+    assert_eq!(Command::parse("break"), Err(CommandError::MissingArgument("break")));
+    assert_eq!(Command::parse("c 1"), Err(CommandError::TooManyArguments("continue")));
+    assert_eq!(Command::parse("s 1x"), Err(CommandError::InvalidNumber("1x".to_string())));
+}
+
+#[test]
+fn step_and_continue() {
+    let mut vm = new_machine();
+    let mut debugger = Debugger::new();
+
+    run_lines(&mut debugger, &mut vm, &["step"]);
+    assert_eq!(vm.program_counter, 8);
+    run_lines(&mut debugger, &mut vm, &["step 3"]);
+    assert_eq!(vm.program_counter, 32);
+    // An empty line repeats the last command
+    run_lines(&mut debugger, &mut vm, &[""]);
+    assert_eq!(vm.program_counter, 56);
+
+    let output = run_lines(&mut debugger, &mut vm, &["b 0x80", "b 200", "c"]);
+    assert_eq!(vm.program_counter, 0x80);
+    assert!(output.contains("Breakpoint reached\n0x80: nop\n"), "{}", output);
+
+    // Continuing from a breakpoint does not stop at the same breakpoint again
+    run_lines(&mut debugger, &mut vm, &["c"]);
+    assert_eq!(vm.program_counter, 200);
+
+    let output = run_lines(&mut debugger, &mut vm, &["d 0x80", "d 0x80", "bl"]);
+    assert_eq!(output, "Breakpoint removed at 0x80\nNo breakpoint at 0x80\n0xc8\n");
+}
+
+#[test]
+fn labels() {
+    let mut vm = new_machine();
+    let mut debugger = Debugger::new();
+    debugger.add_label("main".into(), 0);
+    debugger.add_label("loop".into(), 24);
+
+    let output = run_lines(&mut debugger, &mut vm, &["b LOOP", "c", "s"]);
+    assert_eq!(vm.program_counter, 32);
+    assert_eq!(output, "Breakpoint set at 0x18 <loop>\nBreakpoint reached\n0x18 <loop>: nop\n0x20 <loop+8>: nop\n");
+
+    let output = run_lines(&mut debugger, &mut vm, &["b missing"]);
+    assert_eq!(output, "Unknown label `missing`\n");
+}
+
+#[test]
+fn disassemble() {
+    let mut vm = new_machine();
+    let mut debugger = Debugger::new();
+    debugger.add_label("main".into(), 0);
+
+    let output = run_lines(&mut debugger, &mut vm, &["b 8", "dis 2"]);
+    assert_eq!(output, "Breakpoint set at 0x8 <main+8>\nmain:\n=>  0x00000000: nop\n  * 0x00000008: nop\n    0x00000010: nop\n");
+}
+
+#[test]
+fn examine_memory() {
+    let mut vm = new_machine();
+    let mut debugger = Debugger::new();
+    vm.memory.slice_mut(1000..1003).unwrap().copy_from_slice(b"hi\n");
+
+    let output = run_lines(&mut debugger, &mut vm, &["x 1000 3"]);
+    assert_eq!(output, format!("0x000003e8: 68 69 0a {}|hi.|\n", " ".repeat(13 * 3)));
+
+    let output = run_lines(&mut debugger, &mut vm, &["x 1020 8"]);
+    assert!(output.starts_with("Invalid memory access"), "{}", output);
+}
+
+#[test]
+fn execution_error() {
+    let mut vm = new_machine();
+    let mut debugger = Debugger::new();
+    vm.program_counter = TEST_MEMORY as u64;
+
+    let output = run_lines(&mut debugger, &mut vm, &["s"]);
+    assert!(output.starts_with("Failed to execute instruction at 0x400"), "{}", output);
+    assert!(debugger.is_finished());
+
+    let output = run_lines(&mut debugger, &mut vm, &["c", "f"]);
+    assert_eq!(output, "The program is not running\nCF=0 ZF=1 SF=0 OF=0\n");
+}
+
+#[test]
+fn quit() {
+    let mut vm = new_machine();
+    let mut debugger = Debugger::new();
+
+    let mut out = Vec::new();
+    assert!(!debugger.execute(&mut vm, "quit", &mut out).unwrap());
+}
@@ -0,0 +1,48 @@
+use std::ffi::OsStr;
+use std::path::Path;
+
+use wolf_asm::{AssembleOptions, executable::Executable, file_provider::MemoryFileProvider};
+use wolf_vm::disassemble::disassemble;
+
+/// Assembles a program with debug info
+fn assemble(options: AssembleOptions) -> Executable {
+    let path = options.path.clone();
+    wolf_asm::assemble(AssembleOptions {debug_info: true, ..options})
+        .unwrap_or_else(|diagnostics| panic!("failed to assemble `{}`: {:?}", path.display(), diagnostics))
+        .executable
+}
+
+#[test]
+fn round_trip() {
+    let tests_dir = Path::new("../asm/tests/run-pass");
+    let test_files = tests_dir.read_dir()
+        .unwrap_or_else(|err| panic!("Failed to read test files directory '{}': {}", tests_dir.display(), err));
+
+    for entry in test_files {
+        let path = entry.unwrap().path();
+        if path.is_dir() || path.extension() != Some(OsStr::new("wa")) {
+            continue;
+        }
+
+        let exec = assemble(AssembleOptions::new(&path));
+
+        let mut code = Vec::new();
+        disassemble(&exec, &mut code).unwrap();
+
+        let dis_path = path.with_extension("dis.wa");
+        let mut files = MemoryFileProvider::new();
+        files.insert(&dis_path, code);
+        let reassembled = assemble(AssembleOptions {files: Box::new(files), ..AssembleOptions::new(&dis_path)});
+
+        assert_eq!(reassembled.code_section, exec.code_section, "code differs for `{}`", path.display());
+        assert_eq!(reassembled.static_section, exec.static_section, "static data differs for `{}`", path.display());
+        assert_eq!(reassembled.entry, exec.entry, "entry point differs for `{}`", path.display());
+        // Labels from macro and `.rept` expansions cannot be written in the code, so they are
+        // renamed by the disassembler
+        let mut symbols = exec.debug_info.unwrap().symbols;
+        for symbol in &mut symbols {
+            symbol.name = symbol.name.replace('@', "_").into();
+        }
+        assert_eq!(reassembled.debug_info.unwrap().symbols, symbols, "labels differ for `{}`", path.display());
+    }
+}
@@ -1,17 +1,16 @@
+mod common;
+
 use wolf_vm::{
     decode::*,
-    memory::Memory,
-    registers::Registers,
-    machine::{Machine, ExecutionError},
+    machine::ExecutionError,
     flags::{Flags, CF::*, ZF::*, SF::*, OF::*},
-    io::Stdio,
-    execute::Execute,
+    execute::{Execute, ExecuteError, QUIT_ADDR},
 };
 use wolf_asm::{
     asm::{self, layout::Reg},
 };
 
-const TEST_MEMORY: usize = 1024; // 1 kB
+use common::{new_machine, TEST_MEMORY};
 
 pub fn r(reg: u8) -> Reg {
     assert!(reg < asm::REGISTERS);
@@ -58,13 +57,7 @@ macro_rules! execute {
             $($flag_name:ident : $flag_value:expr),* $(,)?
         },)?
     ) => {
-        let mut vm = Machine {
-            program_counter: 0,
-            memory: Memory::new(TEST_MEMORY),
-            registers: Registers::new(TEST_MEMORY),
-            flags: Flags::default(),
-            io: Stdio::default(),
-        };
+        let mut vm = new_machine();
 
         $(
             let instr = $instr {
@@ -329,3 +322,457 @@ fn mullu_flags() -> Result<(), ExecutionError> {
 
     Ok(())
 }
+
+#[test]
+fn shl_flags() -> Result<(), ExecutionError> {
+    macro_rules! shl {
+        (
+            $a:literal << $b:literal == ($cty:ty) $c:expr,
+            {$carry:ident, $zero:ident, $sign:ident, $overflow:ident$(,)?}
+        ) => (
+            execute! {
+                program: [
+                    Mov {dest: r(0), source: $a},
+                    Shl {dest: r(0), source: $b},
+                ],
+                postconditions: [
+                    reg r(0) => ($cty) $c,
+                ],
+                flags: {
+                    carry: $carry,
+                    zero: $zero,
+                    sign: $sign,
+                    overflow: $overflow,
+                },
+            }
+        );
+    }
+
+    shl!(1u64 << 1u64 == (u64) 2, {NoCarry, NonZero, PositiveSign, NoOverflow});
+    shl!(3u64 << 4u64 == (u64) 48, {NoCarry, NonZero, PositiveSign, NoOverflow});
+    shl!(0x4000000000000000u64 << 1u64 == (i64) i64::MIN, {NoCarry, NonZero, NegativeSign, Overflow});
+    shl!(0x8000000000000000u64 << 1u64 == (u64) 0, {Carry, Zero, PositiveSign, Overflow});
+    shl!(0xc000000000000000u64 << 1u64 == (i64) i64::MIN, {Carry, NonZero, NegativeSign, NoOverflow});
+    // Only the lower 6 bits of the count are used
+    shl!(1u64 << 65u64 == (u64) 2, {NoCarry, NonZero, PositiveSign, NoOverflow});
+
+    Ok(())
+}
+
+#[test]
+fn sal_flags() -> Result<(), ExecutionError> {
+    macro_rules! sal {
+        (
+            $a:literal << $b:literal == ($cty:ty) $c:expr,
+            {$carry:ident, $zero:ident, $sign:ident, $overflow:ident$(,)?}
+        ) => (
+            execute! {
+                program: [
+                    Mov {dest: r(0), source: $a},
+                    Sal {dest: r(0), source: $b},
+                ],
+                postconditions: [
+                    reg r(0) => ($cty) $c,
+                ],
+                flags: {
+                    carry: $carry,
+                    zero: $zero,
+                    sign: $sign,
+                    overflow: $overflow,
+                },
+            }
+        );
+    }
+
+    sal!(-1i64 << 1u64 == (i64) -2, {Carry, NonZero, NegativeSign, NoOverflow});
+    sal!(0x8000000000000000u64 << 1u64 == (u64) 0, {Carry, Zero, PositiveSign, Overflow});
+
+    Ok(())
+}
+
+#[test]
+fn shr_flags() -> Result<(), ExecutionError> {
+    macro_rules! shr {
+        (
+            $a:literal >> $b:literal == ($cty:ty) $c:expr,
+            {$carry:ident, $zero:ident, $sign:ident, $overflow:ident$(,)?}
+        ) => (
+            execute! {
+                program: [
+                    Mov {dest: r(0), source: $a},
+                    Shr {dest: r(0), source: $b},
+                ],
+                postconditions: [
+                    reg r(0) => ($cty) $c,
+                ],
+                flags: {
+                    carry: $carry,
+                    zero: $zero,
+                    sign: $sign,
+                    overflow: $overflow,
+                },
+            }
+        );
+    }
+
+    shr!(4u64 >> 1u64 == (u64) 2, {NoCarry, NonZero, PositiveSign, NoOverflow});
+    shr!(1u64 >> 1u64 == (u64) 0, {Carry, Zero, PositiveSign, NoOverflow});
+    shr!(-1i64 >> 1u64 == (u64) 0x7fffffffffffffff, {Carry, NonZero, PositiveSign, Overflow});
+    shr!(-1i64 >> 63u64 == (u64) 1, {Carry, NonZero, PositiveSign, Overflow});
+
+    Ok(())
+}
+
+#[test]
+fn sar_flags() -> Result<(), ExecutionError> {
+    macro_rules! sar {
+        (
+            $a:literal >> $b:literal == ($cty:ty) $c:expr,
+            {$carry:ident, $zero:ident, $sign:ident, $overflow:ident$(,)?}
+        ) => (
+            execute! {
+                program: [
+                    Mov {dest: r(0), source: $a},
+                    Sar {dest: r(0), source: $b},
+                ],
+                postconditions: [
+                    reg r(0) => ($cty) $c,
+                ],
+                flags: {
+                    carry: $carry,
+                    zero: $zero,
+                    sign: $sign,
+                    overflow: $overflow,
+                },
+            }
+        );
+    }
+
+    sar!(4u64 >> 1u64 == (u64) 2, {NoCarry, NonZero, PositiveSign, NoOverflow});
+    sar!(1u64 >> 1u64 == (u64) 0, {Carry, Zero, PositiveSign, NoOverflow});
+    sar!(-1i64 >> 1u64 == (i64) -1, {Carry, NonZero, NegativeSign, NoOverflow});
+    sar!(-8i64 >> 2u64 == (i64) -2, {NoCarry, NonZero, NegativeSign, NoOverflow});
+
+    Ok(())
+}
+
+#[test]
+fn shift_zero_count() -> Result<(), ExecutionError> {
+    // A count of zero should leave the value and all flags unchanged
+    execute! {
+        program: [
+            Mov {dest: r(0), source: 5u64},
+            Sub {dest: r(0), source: 10u64},
+            Shl {dest: r(0), source: 64u64},
+            Shr {dest: r(0), source: 0u64},
+            Sar {dest: r(0), source: 0u64},
+            Rol {dest: r(0), source: 0u64},
+            Ror {dest: r(0), source: 0u64},
+            Rcl {dest: r(0), source: 0u64},
+            Rcr {dest: r(0), source: 0u64},
+        ],
+        postconditions: [
+            reg r(0) => (i64) -5,
+        ],
+        flags: {
+            carry: Carry,
+            zero: NonZero,
+            sign: NegativeSign,
+            overflow: NoOverflow,
+        },
+    }
+
+    Ok(())
+}
+
+#[test]
+fn rotate_flags() -> Result<(), ExecutionError> {
+    macro_rules! rotate {
+        (
+            $instr:ident ($a:literal, $b:literal) == ($cty:ty) $c:expr,
+            {$carry:ident, $overflow:ident$(,)?}
+        ) => (
+            execute! {
+                program: [
+                    Mov {dest: r(0), source: $a},
+                    $instr {dest: r(0), source: $b},
+                ],
+                postconditions: [
+                    reg r(0) => ($cty) $c,
+                    flag carry => $carry,
+                    flag overflow => $overflow,
+                ],
+            }
+        );
+    }
+
+    rotate!(Rol(1u64, 1u64) == (u64) 2, {NoCarry, NoOverflow});
+    rotate!(Rol(0x8000000000000000u64, 1u64) == (u64) 1, {Carry, Overflow});
+    rotate!(Rol(0xc000000000000000u64, 1u64) == (u64) 0x8000000000000001, {Carry, NoOverflow});
+    rotate!(Rol(0x0123456789abcdefu64, 16u64) == (u64) 0x456789abcdef0123, {Carry, Overflow});
+
+    rotate!(Ror(2u64, 1u64) == (u64) 1, {NoCarry, NoOverflow});
+    rotate!(Ror(1u64, 1u64) == (u64) 0x8000000000000000, {Carry, Overflow});
+    rotate!(Ror(3u64, 1u64) == (u64) 0x8000000000000001, {Carry, Overflow});
+    rotate!(Ror(0x0123456789abcdefu64, 16u64) == (u64) 0xcdef0123456789ab, {Carry, NoOverflow});
+
+    // Rotating through carry with the carry flag initially cleared
+    rotate!(Rcl(0x8000000000000000u64, 1u64) == (u64) 0, {Carry, Overflow});
+    rotate!(Rcl(0x8000000000000000u64, 2u64) == (u64) 1, {NoCarry, NoOverflow});
+    rotate!(Rcr(1u64, 1u64) == (u64) 0, {Carry, NoOverflow});
+    rotate!(Rcr(1u64, 2u64) == (u64) 0x8000000000000000, {NoCarry, Overflow});
+
+    Ok(())
+}
+
+#[test]
+fn rotate_through_carry_flags() -> Result<(), ExecutionError> {
+    macro_rules! rotate_carry {
+        (
+            $instr:ident ($a:literal, $b:literal) == ($cty:ty) $c:expr,
+            {$carry:ident, $overflow:ident$(,)?}
+        ) => (
+            execute! {
+                program: [
+                    // Set the carry flag before rotating
+                    Mov {dest: r(1), source: 0u64},
+                    Sub {dest: r(1), source: 1u64},
+                    Mov {dest: r(0), source: $a},
+                    $instr {dest: r(0), source: $b},
+                ],
+                postconditions: [
+                    reg r(0) => ($cty) $c,
+                    flag carry => $carry,
+                    flag overflow => $overflow,
+                ],
+            }
+        );
+    }
+
+    rotate_carry!(Rcl(0u64, 1u64) == (u64) 1, {NoCarry, NoOverflow});
+    rotate_carry!(Rcl(0x4000000000000000u64, 1u64) == (u64) 0x8000000000000001, {NoCarry, Overflow});
+    rotate_carry!(Rcl(0u64, 63u64) == (u64) 0x4000000000000000, {NoCarry, NoOverflow});
+    rotate_carry!(Rcr(0u64, 1u64) == (u64) 0x8000000000000000, {NoCarry, Overflow});
+    rotate_carry!(Rcr(1u64, 1u64) == (u64) 0x8000000000000000, {Carry, Overflow});
+    rotate_carry!(Rcr(0u64, 63u64) == (u64) 2, {NoCarry, NoOverflow});
+
+    Ok(())
+}
+
+#[test]
+fn syscall_alloc() -> Result<(), ExecutionError> {
+    let mut vm = new_machine();
+    // Pretend that the executable occupies the first 60 bytes of memory
+    vm.heap_end = 60;
+
+    Mov {dest: r(0).into(), source: 4u64.into()}.execute(&mut vm)?;
+    Mov {dest: r(1).into(), source: 10u64.into()}.execute(&mut vm)?;
+    Syscall {}.execute(&mut vm)?;
+    // Allocations are aligned to 8 bytes
+    postconditions!(vm, reg r(0) => (u64) 64);
+
+    Mov {dest: r(0).into(), source: 4u64.into()}.execute(&mut vm)?;
+    Syscall {}.execute(&mut vm)?;
+    postconditions!(vm, reg r(0) => (u64) 80);
+
+    // Allocating past the stack pointer fails
+    Mov {dest: r(0).into(), source: 4u64.into()}.execute(&mut vm)?;
+    Mov {dest: r(1).into(), source: (TEST_MEMORY as u64).into()}.execute(&mut vm)?;
+    Syscall {}.execute(&mut vm)?;
+    postconditions!(vm, reg r(0) => (u64) 0);
+    assert_eq!(vm.heap_end, 90);
+
+    Ok(())
+}
+
+#[test]
+fn syscall_alloc_above_stack() -> Result<(), ExecutionError> {
+    let mut vm = new_machine();
+    // Pretend that the executable is loaded above the stack and ends 16 bytes before the end of
+    // memory, past the stack pointer
+    vm.heap_end = TEST_MEMORY as u64 - 16;
+    vm.heap_limit = Some(TEST_MEMORY as u64);
+    vm.registers.store_sp(16u64);
+
+    Mov {dest: r(0).into(), source: 4u64.into()}.execute(&mut vm)?;
+    Mov {dest: r(1).into(), source: 16u64.into()}.execute(&mut vm)?;
+    Syscall {}.execute(&mut vm)?;
+    postconditions!(vm, reg r(0) => (u64) (TEST_MEMORY as u64 - 16));
+
+    // Allocating past the end of memory fails
+    Mov {dest: r(0).into(), source: 4u64.into()}.execute(&mut vm)?;
+    Mov {dest: r(1).into(), source: 1u64.into()}.execute(&mut vm)?;
+    Syscall {}.execute(&mut vm)?;
+    postconditions!(vm, reg r(0) => (u64) 0);
+
+    Ok(())
+}
+
+#[test]
+fn syscall_exit() -> Result<(), ExecutionError> {
+    let mut vm = new_machine();
+
+    Mov {dest: r(0).into(), source: 2u64.into()}.execute(&mut vm)?;
+    Mov {dest: r(1).into(), source: 3u64.into()}.execute(&mut vm)?;
+    Syscall {}.execute(&mut vm)?;
+
+    assert_eq!(vm.exit_code, Some(3));
+    assert_eq!(vm.program_counter, QUIT_ADDR);
+
+    Ok(())
+}
+
+#[test]
+fn syscall_time() -> Result<(), ExecutionError> {
+    let mut vm = new_machine();
+
+    Mov {dest: r(0).into(), source: 3u64.into()}.execute(&mut vm)?;
+    Syscall {}.execute(&mut vm)?;
+
+    let time: u64 = vm.registers.load(r(0));
+    assert!(time > 0);
+
+    Ok(())
+}
+
+#[test]
+fn syscall_unknown() -> Result<(), ExecutionError> {
+    let mut vm = new_machine();
+
+    Mov {dest: r(0).into(), source: 1000u64.into()}.execute(&mut vm)?;
+    match (Syscall {}).execute(&mut vm) {
+        Err(ExecuteError::UnknownSyscall(1000)) => {},
+        res => panic!("expected an unknown syscall error, found: {:?}", res),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn float_arithmetic() -> Result<(), ExecutionError> {
+    macro_rules! float_op {
+        ($instr:ident ($a:literal, $b:expr) == $c:expr) => (
+            execute! {
+                program: [
+                    Mov {dest: r(0), source: f64::to_bits($a)},
+                    $instr {dest: r(0), source: f64::to_bits($b)},
+                ],
+                postconditions: [
+                    reg r(0) => (f64) $c,
+                ],
+            }
+        );
+    }
+
+    float_op!(Fadd(1.5, 2.25) == 3.75);
+    float_op!(Fadd(-1.5, 1.5) == 0.0);
+    float_op!(Fsub(1.5, 2.25) == -0.75);
+    float_op!(Fmul(1.5, -4.0) == -6.0);
+    float_op!(Fdiv(1.0, 4.0) == 0.25);
+    float_op!(Fdiv(1.0, 0.0) == f64::INFINITY);
+    float_op!(Fdiv(-1.0, 0.0) == f64::NEG_INFINITY);
+    float_op!(Fsqrt(0.0, 6.25) == 2.5);
+
+    let mut vm = new_machine();
+    Fsqrt {dest: r(0).into(), source: f64::to_bits(-1.0).into()}.execute(&mut vm)?;
+    let value: f64 = vm.registers.load(r(0));
+    assert!(value.is_nan());
+
+    // Floating point arithmetic does not modify the flags
+    execute! {
+        program: [
+            Mov {dest: r(0), source: 0u64},
+            Cmp {source1: r(0), source2: 1u64},
+            Fadd {dest: r(0), source: f64::to_bits(1.0)},
+        ],
+        flags: {
+            carry: Carry,
+            zero: NonZero,
+            sign: NegativeSign,
+            overflow: NoOverflow,
+        },
+    }
+
+    Ok(())
+}
+
+#[test]
+fn fcmp_flags() -> Result<(), ExecutionError> {
+    macro_rules! fcmp {
+        (
+            $a:expr, $b:expr,
+            {$carry:ident, $zero:ident, $sign:ident, $overflow:ident$(,)?}
+        ) => (
+            execute! {
+                program: [
+                    Fcmp {source1: f64::to_bits($a), source2: f64::to_bits($b)},
+                ],
+                flags: {
+                    carry: $carry,
+                    zero: $zero,
+                    sign: $sign,
+                    overflow: $overflow,
+                },
+            }
+        );
+    }
+
+    fcmp!(1.0, 2.0, {Carry, NonZero, PositiveSign, NoOverflow});
+    fcmp!(2.0, 1.0, {NoCarry, NonZero, PositiveSign, NoOverflow});
+    fcmp!(-2.0, 1.0, {Carry, NonZero, PositiveSign, NoOverflow});
+    fcmp!(1.5, 1.5, {NoCarry, Zero, PositiveSign, NoOverflow});
+    fcmp!(0.0, -0.0, {NoCarry, Zero, PositiveSign, NoOverflow});
+    fcmp!(f64::NEG_INFINITY, f64::INFINITY, {Carry, NonZero, PositiveSign, NoOverflow});
+    // Unordered
+    fcmp!(f64::NAN, 1.0, {Carry, Zero, PositiveSign, Overflow});
+    fcmp!(1.0, f64::NAN, {Carry, Zero, PositiveSign, Overflow});
+    fcmp!(f64::NAN, f64::NAN, {Carry, Zero, PositiveSign, Overflow});
+
+    Ok(())
+}
+
+#[test]
+fn float_conversions() -> Result<(), ExecutionError> {
+    macro_rules! itof {
+        ($a:literal == $b:expr) => (
+            execute! {
+                program: [
+                    Itof {dest: r(0), source: $a},
+                ],
+                postconditions: [
+                    reg r(0) => (f64) $b,
+                ],
+            }
+        );
+    }
+
+    macro_rules! ftoi {
+        ($a:expr => $b:expr) => (
+            execute! {
+                program: [
+                    Ftoi {dest: r(0), source: f64::to_bits($a)},
+                ],
+                postconditions: [
+                    reg r(0) => (i64) $b,
+                ],
+            }
+        );
+    }
+
+    itof!(0i64 == 0.0);
+    itof!(42i64 == 42.0);
+    itof!(-42i64 == -42.0);
+    itof!(0x7fffffffffffffffi64 == 9223372036854775807.0);
+
+    ftoi!(0.0 => 0);
+    ftoi!(42.9 => 42);
+    ftoi!(-42.9 => -42);
+    ftoi!(-9223372036854775808.0 => i64::MIN);
+    // Out of range values and NaN produce i64::MIN
+    ftoi!(9223372036854775808.0 => i64::MIN);
+    ftoi!(-1e19 => i64::MIN);
+    ftoi!(f64::INFINITY => i64::MIN);
+    ftoi!(f64::NAN => i64::MIN);
+
+    Ok(())
+}
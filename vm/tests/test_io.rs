@@ -0,0 +1,80 @@
+use std::io::{Read, Write, Seek, SeekFrom};
+
+use wolf_vm::{
+    io::{Io, MemoryIo, FileIo},
+    machine::{MachineConfig, ProgramStatus},
+};
+use wolf_asm::{assemble, AssembleOptions};
+
+/// Assembles and runs one of the programs in `asm/tests/run-pass` with the given input, and
+/// returns everything it wrote
+fn run_program(name: &str, input: &[u8]) -> Vec<u8> {
+    let path = format!("../asm/tests/run-pass/{}", name);
+    let exec = assemble(AssembleOptions::new(&path))
+        .unwrap_or_else(|diagnostics| panic!("failed to assemble `{}`: {:?}", path, diagnostics))
+        .executable;
+
+    let io = MemoryIo::new(input);
+    let mut vm = MachineConfig::new().build(&exec, exec.entry).unwrap();
+    vm.io = Box::new(io.clone());
+    while vm.step().unwrap() == ProgramStatus::Continue {}
+
+    io.output()
+}
+
+#[test]
+fn program_output() {
+    assert_eq!(run_program("hello.wa", b""), b"hello, world!\n");
+    assert_eq!(run_program("hello-syscall.wa", b""), b"hello, world!\n");
+}
+
+#[test]
+fn program_input() {
+    assert_eq!(run_program("cat.wa", b"line 1\nline 2\n"), b"line 1\nline 2\n");
+    assert_eq!(run_program("cat.wa", b""), b"");
+}
+
+#[test]
+fn memory_io() {
+    let io = MemoryIo::new(&b"ab"[..]);
+    let mut device: Box<dyn Io> = Box::new(io.clone());
+
+    assert_eq!(device.read_byte().unwrap(), Some(b'a'));
+    io.push_input(b"cd");
+    let mut buf = [0; 8];
+    assert_eq!(device.read_buf(&mut buf).unwrap(), 3);
+    assert_eq!(&buf[..3], b"bcd");
+    assert_eq!(device.read_byte().unwrap(), None);
+
+    device.write_bytes('é' as u32).unwrap();
+    // Not a valid `char`
+    device.write_bytes(0xd800).unwrap();
+    device.write_buf(b"!").unwrap();
+    assert_eq!(io.take_output(), "é\u{fffd}!".as_bytes());
+    assert!(io.output().is_empty());
+}
+
+#[test]
+fn file_io() {
+    let mut input = tempfile::tempfile().unwrap();
+    input.write_all(b"xyz").unwrap();
+    input.seek(SeekFrom::Start(0)).unwrap();
+    let mut output = tempfile::tempfile().unwrap();
+
+    let mut device = FileIo::new(Some(input), Some(output.try_clone().unwrap()));
+    assert_eq!(device.read_byte().unwrap(), Some(b'x'));
+    let mut buf = [0; 8];
+    assert_eq!(device.read_buf(&mut buf).unwrap(), 2);
+    assert_eq!(device.read_byte().unwrap(), None);
+    device.write_buf(b"out").unwrap();
+
+    let mut written = String::new();
+    output.seek(SeekFrom::Start(0)).unwrap();
+    output.read_to_string(&mut written).unwrap();
+    assert_eq!(written, "out");
+
+    // Without any files, there is no input and all output is discarded
+    let mut device = FileIo::default();
+    assert_eq!(device.read_byte().unwrap(), None);
+    device.write_buf(b"discarded").unwrap();
+}
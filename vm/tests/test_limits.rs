@@ -0,0 +1,69 @@
+mod common;
+
+use std::time::Duration;
+
+use wolf_vm::{
+    io::MemoryIo,
+    machine::{RunLimits, ExecutionError},
+};
+
+use common::build;
+
+const SPIN: &str = "\
+section .code
+main:
+    jmp main
+";
+
+const PRINT_TWICE: &str = "\
+section .code
+main:
+    mov $8, 97
+    store1 0xffff_000c, $8
+    mov $8, 98
+    store1 0xffff_000c, $8
+    ret
+";
+
+#[test]
+fn max_steps() {
+    let io = MemoryIo::default();
+    let (_, mut vm) = build(SPIN, &io);
+    match vm.run(&RunLimits::new().with_max_steps(100)) {
+        Err(ExecutionError::OutOfSteps {steps}) => assert_eq!(steps, 100),
+        result => panic!("expected the program to run out of steps, got {:?}", result),
+    }
+
+    // Stopping halfway through only runs the instructions before the limit
+    let (_, mut vm) = build(PRINT_TWICE, &io);
+    assert!(vm.run(&RunLimits::new().with_max_steps(2)).is_err());
+    assert_eq!(io.take_output(), b"a");
+    // The program counter is left at the instruction that was not executed
+    assert_eq!(vm.program_counter, 16);
+
+    // The limit is only reached if the program tries to execute more instructions
+    let (_, mut vm) = build(PRINT_TWICE, &io);
+    assert_eq!(vm.run(&RunLimits::new().with_max_steps(5)).unwrap(), 5);
+    assert_eq!(io.take_output(), b"ab");
+}
+
+#[test]
+fn timeout() {
+    let io = MemoryIo::default();
+    let (_, mut vm) = build(SPIN, &io);
+    match vm.run(&RunLimits::new().with_timeout(Duration::from_millis(10))) {
+        Err(ExecutionError::DeadlineExceeded {steps}) => assert!(steps > 0),
+        result => panic!("expected the program to exceed its deadline, got {:?}", result),
+    }
+
+    // A timeout too long to be represented does not set a deadline
+    assert_eq!(RunLimits::new().with_timeout(Duration::MAX).deadline(), None);
+}
+
+#[test]
+fn no_limits() {
+    let io = MemoryIo::default();
+    let (_, mut vm) = build(PRINT_TWICE, &io);
+    assert_eq!(vm.run(&RunLimits::new()).unwrap(), 5);
+    assert_eq!(io.output(), b"ab");
+}
@@ -0,0 +1,65 @@
+use wolf_vm::{
+    machine::{MachineConfig, ConfigError, DEFAULT_MEMORY_SIZE},
+    execute::QUIT_ADDR,
+};
+
+#[test]
+fn default_config() {
+    let program = [1u8, 2, 3];
+    let vm = MachineConfig::new().build(&program[..], 0).unwrap();
+
+    assert_eq!(vm.memory.slice(0..3).unwrap(), &program);
+    assert_eq!(vm.program_counter, 0);
+    assert_eq!(vm.heap_end, 3);
+    assert_eq!(vm.heap_limit, None);
+
+    // The quit address is pushed at the very end of memory
+    let sp: u64 = vm.registers.load_sp();
+    assert_eq!(sp, DEFAULT_MEMORY_SIZE as u64 - 8);
+    assert_eq!(vm.memory.read_u64(sp).unwrap(), QUIT_ADDR);
+}
+
+#[test]
+fn custom_config() {
+    let program = [0u8; 16];
+    let config = MachineConfig::new()
+        .with_memory_size(0x1000)
+        .with_load_addr(0x800)
+        .with_stack_end(0x400);
+    let vm = config.build(&program[..], 8).unwrap();
+
+    assert_eq!(vm.program_counter, 0x808);
+    assert_eq!(vm.heap_end, 0x810);
+    // The heap is above the stack, so it can grow until the end of memory
+    assert_eq!(vm.heap_limit, Some(0x1000));
+    let sp: u64 = vm.registers.load_sp();
+    assert_eq!(sp, 0x3f8);
+    assert_eq!(vm.memory.read_u64(sp).unwrap(), QUIT_ADDR);
+
+    // The entry address overrides the entry point of the program
+    let vm = config.with_entry_addr(0x804).build(&program[..], 8).unwrap();
+    assert_eq!(vm.program_counter, 0x804);
+}
+
+#[test]
+fn invalid_config() {
+    let program = [0u8; 16];
+    let config = MachineConfig::new().with_memory_size(64);
+
+    assert_eq!(
+        config.clone().with_stack_end(65).build(&program[..], 0).unwrap_err(),
+        ConfigError::StackOutOfMemory {stack_end: 65, memory_size: 64},
+    );
+    assert_eq!(
+        config.clone().with_load_addr(56).build(&program[..], 0).unwrap_err(),
+        ConfigError::ProgramOutOfMemory {load_addr: 56, memory_size: 64},
+    );
+    assert_eq!(
+        config.clone().with_stack_end(8).build(&program[..], 0).unwrap_err(),
+        ConfigError::ProgramOverlapsStack {load_addr: 0, program_end: 16, stack_end: 8},
+    );
+    assert_eq!(
+        config.with_load_addr(32).with_stack_end(4).build(&program[..], 0).unwrap_err(),
+        ConfigError::NoStackSpace {stack_end: 4},
+    );
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use wolf_vm::{
+    io::MemoryIo,
+    machine::{Machine, RunLimits},
+    profile::{Profiler, FunctionCost},
+};
+
+use common::build;
+
+const PROGRAM: &str = "\
+section .code
+main:
+    mov $1, 2
+    call countdown
+    call leaf
+    ret
+
+countdown:
+    sub $1, 1
+    jz .done
+    call countdown
+.done:
+    ret
+
+leaf:
+    ret
+";
+
+/// Runs the program to completion, recording every step
+fn run(vm: &mut Machine, profiler: &mut Profiler) {
+    vm.run_with(&RunLimits::new(), |vm| {
+        profiler.record(vm);
+        vm.step()
+    }).unwrap();
+}
+
+#[test]
+fn function_costs() {
+    let (exec, mut vm) = build(PROGRAM, &MemoryIo::default());
+    let mut profiler = Profiler::new().with_debug_info(exec.debug_info.as_ref().unwrap(), 0);
+    run(&mut vm, &mut profiler);
+
+    assert_eq!(profiler.total_steps(), 12);
+    assert_eq!(profiler.functions(), &[
+        FunctionCost {addr: 0, calls: 0, inclusive: 12, exclusive: 4},
+        // Recursive calls are only counted once in the inclusive cost
+        FunctionCost {addr: 32, calls: 2, inclusive: 7, exclusive: 7},
+        FunctionCost {addr: 64, calls: 1, inclusive: 1, exclusive: 1},
+    ]);
+
+    let instructions = profiler.instructions();
+    assert_eq!(instructions.len(), 9);
+    assert_eq!(&instructions[..3], &[(32, 2), (40, 2), (56, 2)]);
+
+    assert_eq!(profiler.function_name(32), "countdown");
+    assert_eq!(profiler.function_name(56), "countdown.done");
+    assert_eq!(profiler.function_name(48), "countdown+0x10");
+}
+
+#[test]
+fn collapsed_stacks() {
+    let (exec, mut vm) = build(PROGRAM, &MemoryIo::default());
+    let mut profiler = Profiler::new().with_debug_info(exec.debug_info.as_ref().unwrap(), 0);
+    run(&mut vm, &mut profiler);
+
+    let mut out = Vec::new();
+    profiler.write_collapsed_stacks(&mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "\
+main 4
+main;countdown 4
+main;countdown;countdown 3
+main;leaf 1
+");
+
+    // Without debug info, functions are named by their address
+    let (_, mut vm) = build(PROGRAM, &MemoryIo::default());
+    let mut profiler = Profiler::new();
+    run(&mut vm, &mut profiler);
+
+    let mut out = Vec::new();
+    profiler.write_collapsed_stacks(&mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "\
+0x0 4
+0x0;0x20 4
+0x0;0x20;0x20 3
+0x0;0x40 1
+");
+}
+
+#[test]
+fn report() {
+    let (exec, mut vm) = build(PROGRAM, &MemoryIo::default());
+    let mut profiler = Profiler::new().with_debug_info(exec.debug_info.as_ref().unwrap(), 0);
+    run(&mut vm, &mut profiler);
+
+    let mut out = Vec::new();
+    profiler.write_report(&vm, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(&lines[..7], &[
+        "Executed 12 instructions",
+        "",
+        "Functions:",
+        "   inclusive       %    exclusive       %    calls  function",
+        "          12 100.00%            4  33.33%        0  main",
+        "           7  58.33%            7  58.33%        2  countdown",
+        "           1   8.33%            1   8.33%        1  leaf",
+    ]);
+    assert_eq!(lines[10], "           2  16.67%  0x00000020  sub $1, 1                 main.wa:9:5 in `countdown`");
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use wolf_vm::{
+    io::MemoryIo,
+    machine::{Machine, RunLimits, ProgramStatus, MemoryWrite},
+    trace::{Tracer, TraceStep, TraceFormat, RegisterChange, FlagChange},
+};
+
+use common::build;
+
+const PROGRAM: &str = "\
+section .code
+main:
+    mov $8, 2
+    push $8
+.loop:
+    sub $8, 1
+    jg .loop
+    pop $9
+    ret
+";
+
+/// Runs the program to completion, writing every step to the given tracer
+fn run(vm: &mut Machine, tracer: &mut Tracer) {
+    vm.run_with(&RunLimits::new(), |vm| {
+        let (step, status) = TraceStep::execute(vm);
+        tracer.write(&step).unwrap();
+        status
+    }).unwrap();
+    tracer.flush().unwrap();
+}
+
+#[test]
+fn trace_step() {
+    let (_, mut vm) = build(PROGRAM, &MemoryIo::default());
+    let sp = vm.registers.load_sp::<u64>();
+
+    let (step, status) = TraceStep::execute(&mut vm);
+    assert_eq!(status.unwrap(), ProgramStatus::Continue);
+    assert_eq!(step.pc, 0);
+    assert_eq!(step.instr.as_deref(), Some("mov $8, 2"));
+    assert_eq!(step.registers, &[RegisterChange {reg: "$8".to_string(), old: 0, new: 2}]);
+    assert!(step.flags.is_empty());
+    assert!(step.writes.is_empty());
+
+    let (step, _) = TraceStep::execute(&mut vm);
+    assert_eq!(step.registers, &[RegisterChange {reg: "$sp".to_string(), old: sp, new: sp - 8}]);
+    assert_eq!(step.writes, &[MemoryWrite {addr: sp - 8, bytes: vec![2, 0, 0, 0, 0, 0, 0, 0]}]);
+
+    let (step, _) = TraceStep::execute(&mut vm);
+    assert_eq!(step.registers, &[RegisterChange {reg: "$8".to_string(), old: 2, new: 1}]);
+    assert_eq!(step.flags, &[FlagChange {flag: "ZF", old: 1, new: 0}]);
+
+    // Tracing does not leave the write log enabled
+    assert!(vm.write_log.is_none());
+}
+
+#[test]
+fn text_trace() {
+    let (exec, mut vm) = build(PROGRAM, &MemoryIo::default());
+    let mut out = Vec::new();
+    let mut tracer = Tracer::new(&mut out, TraceFormat::Text)
+        .with_debug_info(exec.debug_info.as_ref().unwrap(), 0)
+        .with_filter(16..32);
+    run(&mut vm, &mut tracer);
+    drop(tracer);
+
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines, &[
+        "       3  0x00000010  sub $8, 1                 $8: 0x2 -> 0x1  ZF: 1 -> 0  # main.wa:6:5 in `main.loop`",
+        "       4  0x00000018  jg 16                     # main.wa:7:5 in `main.loop`",
+        "       5  0x00000010  sub $8, 1                 $8: 0x1 -> 0x0  ZF: 0 -> 1  # main.wa:6:5 in `main.loop`",
+        "       6  0x00000018  jg 16                     # main.wa:7:5 in `main.loop`",
+    ]);
+}
+
+#[test]
+fn json_trace() {
+    let (_, mut vm) = build(PROGRAM, &MemoryIo::default());
+    let mut out = Vec::new();
+    let mut tracer = Tracer::new(&mut out, TraceFormat::Json);
+    run(&mut vm, &mut tracer);
+    drop(tracer);
+
+    let steps: Vec<serde_json::Value> = out.split(|&byte| byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect();
+    assert_eq!(steps.len(), 8);
+
+    assert_eq!(steps[0], serde_json::json!({
+        "step": 1,
+        "pc": 0,
+        "instr": "mov $8, 2",
+        "registers": [{"reg": "$8", "old": 0, "new": 2}],
+        "flags": [],
+        "writes": [],
+        "location": null,
+    }));
+    assert_eq!(steps[6]["instr"], "pop $9");
+    assert_eq!(steps[6]["registers"][0], serde_json::json!({"reg": "$9", "old": 0, "new": 2}));
+}